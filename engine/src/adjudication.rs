@@ -0,0 +1,142 @@
+use chess::Color;
+
+use crate::{board::Board, eval::Eval, pgn::GameResult};
+
+/// Thresholds for ending engine games early, from the scores the engines report
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Adjudication {
+    /// a side resigns once its score is at or below `-resign_score` for `resign_moves` moves in a row
+    pub resign_score: Eval,
+    pub resign_moves: u16,
+    /// the game is drawn once both sides' scores are within `draw_score` of zero for `draw_moves` moves each
+    pub draw_score: Eval,
+    pub draw_moves: u16,
+    /// draws are not adjudicated before this fullmove number
+    pub draw_move_number: u16,
+}
+
+impl Default for Adjudication {
+    fn default() -> Self {
+        Self {
+            resign_score: 1000,
+            resign_moves: 3,
+            draw_score: 10,
+            draw_moves: 8,
+            draw_move_number: 40,
+        }
+    }
+}
+
+/// Tracks the scores of a game in progress against an `Adjudication`
+#[derive(Clone, Debug)]
+pub struct Adjudicator {
+    pub config: Adjudication,
+    /// consecutive losing moves for white and black
+    resign_counts: [u16; 2],
+    /// consecutive drawish moves by either side
+    draw_count: u16,
+}
+
+impl Adjudicator {
+    pub fn new(config: Adjudication) -> Self {
+        Self { config, resign_counts: [0; 2], draw_count: 0 }
+    }
+
+    /// Records the score the side to move reported for its move, from its own point of view,
+    /// and returns the result if the game should be adjudicated
+    ///
+    /// *must* be called before the move is made
+    pub fn update(&mut self, board: &Board, score: Eval) -> Option<GameResult> {
+        let side = board.side_to_move();
+
+        let resign_count = &mut self.resign_counts[side.to_index()];
+        *resign_count = if score <= -self.config.resign_score { *resign_count + 1 } else { 0 };
+        if *resign_count >= self.config.resign_moves {
+            return Some(match side {
+                Color::White => GameResult::BlackWin,
+                Color::Black => GameResult::WhiteWin,
+            });
+        }
+
+        self.draw_count = if score.abs() <= self.config.draw_score { self.draw_count + 1 } else { 0 };
+        if self.draw_count >= self.config.draw_moves * 2 && board.fullmove_number() >= self.config.draw_move_number {
+            return Some(GameResult::Draw);
+        }
+
+        None
+    }
+
+    /// Returns the result of the game under the rules, or from the scores reported so far
+    ///
+    /// `score` is the side to move's score for its next move, and is ignored if the game is already over
+    pub fn adjudicate(&mut self, board: &Board, score: Eval) -> GameResult {
+        let state = board.game_state();
+        if state.is_over() {
+            return state.result(board.side_to_move());
+        }
+        self.update(board, score).unwrap_or(GameResult::Ongoing)
+    }
+}
+
+impl Default for Adjudicator {
+    fn default() -> Self {
+        Self::new(Adjudication::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Move;
+    use chess::Square;
+
+    #[test]
+    fn test_resign() {
+        let mut board = Board::new();
+        let mut adjudicator = Adjudicator::default();
+        let moves = [(Square::G1, Square::F3), (Square::G8, Square::F6), (Square::F3, Square::G1), (Square::F6, Square::G8)];
+
+        // black's score is losing for three of its moves in a row, after being interrupted once
+        let scores = [0, -1200, 0, -1000, 0, -50, 0, -1500, 0, -1100, 0, -1000];
+        let mut results = Vec::new();
+        for (ply, score) in scores.into_iter().enumerate() {
+            results.push(adjudicator.update(&board, score));
+            let (from, to) = moves[ply % moves.len()];
+            board.make_move(Move::new(from, to, None));
+        }
+        assert!(results[..11].iter().all(Option::is_none));
+        assert_eq!(results[11], Some(GameResult::WhiteWin));
+    }
+
+    #[test]
+    fn test_draw() {
+        let config = Adjudication { draw_moves: 2, draw_move_number: 30, ..Adjudication::default() };
+        let mut adjudicator = Adjudicator::new(config);
+
+        // too early in the game
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 20").unwrap();
+        for _ in 0..4 {
+            assert_eq!(adjudicator.update(&board, 5), None);
+        }
+
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 30").unwrap();
+        let mut adjudicator = Adjudicator::new(config);
+        assert_eq!(adjudicator.update(&board, 5), None);
+        assert_eq!(adjudicator.update(&board, -5), None);
+        assert_eq!(adjudicator.update(&board, 50), None);
+        assert_eq!(adjudicator.update(&board, 0), None);
+        assert_eq!(adjudicator.update(&board, 0), None);
+        assert_eq!(adjudicator.update(&board, 10), None);
+        assert_eq!(adjudicator.update(&board, -10), Some(GameResult::Draw));
+    }
+
+    #[test]
+    fn test_adjudicate() {
+        let mut adjudicator = Adjudicator::default();
+        let board = Board::from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 1 1").unwrap();
+        assert_eq!(adjudicator.adjudicate(&board, 0), GameResult::WhiteWin);
+        let board = Board::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(adjudicator.adjudicate(&board, 0), GameResult::Draw);
+        assert_eq!(adjudicator.adjudicate(&Board::new(), 0), GameResult::Ongoing);
+    }
+}
@@ -1,77 +1,429 @@
 use std::fmt;
 use std::str::FromStr;
 
-use chess::{MoveGen, BitBoard, ChessMove, EMPTY, Piece, Square};
+use chess::{MoveGen, BitBoard, BoardBuilder, CastleRights, ChessMove, Color, File, Piece, Square};
 
-const MAX_PLY: usize = 128;
+use crate::chess960::{self, CastleSide, CastlingRooks};
+use crate::material::MaterialDraw;
 
-const LIGHT_SQUARES: BitBoard = BitBoard(0x55AA_55AA_55AA_55AA);
-const DARK_SQUARES: BitBoard = BitBoard(0xAA55_AA55_AA55_AA55);
+const MAX_PLY: usize = 128;
 
 pub type Move = ChessMove;
 
 #[derive(Clone)]
 pub struct Board {
     pub position: chess::Board,
-    history: Vec<chess::Board>,
-    reversible_counts: Vec<u8>,
+    /// Chess960 castling rights, always empty in standard chess
+    castling: CastlingRooks,
+    halfmove_clock: u8,
+    /// one record for each move that can still be undone
+    undo: Vec<Undo>,
+    /// Zobrist keys for each position played, ending with the current one
+    keys: Vec<u64>,
+    /// index into `keys` of the position the current search started from
+    search_root: usize,
+    /// plies played before the starting position, used for the fullmove number
+    start_ply: u32,
+    chess960: bool,
+}
+
+/// The state `undo_move` needs to rebuild the position before a move from the one after it
+#[derive(Clone, Copy)]
+struct Undo {
+    mv: Move,
+    /// the opponent's piece on the destination square, `None` for en passant
+    captured: Option<Piece>,
+    castle_rights: [CastleRights; 2],
+    en_passant: Option<Square>,
+    /// hash of the position before the move, checked against the rebuilt one in debug builds
+    hash: u64,
+    castling: CastlingRooks,
+    halfmove_clock: u8,
+}
+
+/// When a repeated position counts as a draw
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RepetitionRule {
+    /// any repetition is a draw
+    Twofold,
+    /// a repetition within the search is a draw, but a position from the game before the search root
+    /// must have occurred twice already
+    Threefold,
+}
+
+/// Whether the game has ended, and why
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameState {
+    Ongoing,
+    /// the side to move is checkmated
+    Checkmate,
+    Stalemate,
+    /// the position has occurred three times, counting the current one
+    ThreefoldRepetition,
+    /// no capture or pawn move in the last fifty moves by each side
+    FiftyMoves,
+    /// neither side can checkmate by any sequence of legal moves
+    InsufficientMaterial,
+}
+
+impl GameState {
+    pub fn is_over(&self) -> bool {
+        *self != GameState::Ongoing
+    }
+
+    /// Returns the result of the game, given the side to move in the final position
+    pub fn result(&self, side_to_move: Color) -> GameResult {
+        match self {
+            GameState::Ongoing => GameResult::Ongoing,
+            GameState::Checkmate if side_to_move == Color::White => GameResult::BlackWin,
+            GameState::Checkmate => GameResult::WhiteWin,
+            _ => GameResult::Draw,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWin,
+    BlackWin,
+    Draw,
+    /// the game is unfinished or the result is unknown
+    Ongoing,
+}
+
+impl GameResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameResult::WhiteWin => "1-0",
+            GameResult::BlackWin => "0-1",
+            GameResult::Draw => "1/2-1/2",
+            GameResult::Ongoing => "*",
+        }
+    }
+}
+
+impl FromStr for GameResult {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1-0" => Ok(GameResult::WhiteWin),
+            "0-1" => Ok(GameResult::BlackWin),
+            "1/2-1/2" => Ok(GameResult::Draw),
+            "*" => Ok(GameResult::Ongoing),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Legal moves from the move generator, followed by any Chess960 castling moves
+pub struct Moves {
+    first: Option<Move>,
+    gen: MoveGen,
+    castles: Vec<Move>,
+}
+
+impl Iterator for Moves {
+    type Item = Move;
+
+    fn next(&mut self) -> Option<Move> {
+        self.first.take().or_else(|| self.gen.next()).or_else(|| self.castles.pop())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.first.is_some() as usize + self.gen.len() + self.castles.len();
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Moves { }
+
+/// Reasons a FEN string can fail to parse
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FenError {
+    /// fewer than the four required fields
+    MissingFields(usize),
+    /// more than the six standard fields
+    TooManyFields(usize),
+    InvalidHalfmoveClock(String),
+    InvalidFullmoveNumber(String),
+    /// the placement, side to move, castling or en passant fields are malformed or illegal
+    InvalidPosition(String),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::MissingFields(n) => write!(f, "expected at least 4 fields, found {}", n),
+            FenError::TooManyFields(n) => write!(f, "expected at most 6 fields, found {}", n),
+            FenError::InvalidHalfmoveClock(s) => write!(f, "invalid halfmove clock '{}'", s),
+            FenError::InvalidFullmoveNumber(s) => write!(f, "invalid fullmove number '{}'", s),
+            FenError::InvalidPosition(s) => write!(f, "invalid position '{}'", s),
+        }
+    }
 }
 
+impl std::error::Error for FenError { }
+
 impl Board {
     /// Returns a new instance of `Board` with the default position
     pub fn new() -> Self {
-        let mut reversible_counts = Vec::with_capacity(MAX_PLY);
-        reversible_counts.push(0);
+        let position = chess::Board::default();
+        let mut keys = Vec::with_capacity(MAX_PLY);
+        keys.push(position.get_hash());
         Self {
-            position: chess::Board::default(),
-            history: Vec::with_capacity(MAX_PLY),
-            reversible_counts
+            position,
+            castling: CastlingRooks::default(),
+            halfmove_clock: 0,
+            undo: Vec::with_capacity(MAX_PLY),
+            keys,
+            search_root: 0,
+            start_ply: 0,
+            chess960: false,
         }
     }
 
     /// Returns a new instance of `Board` with the given fen position
-    pub fn from_fen(fen: &str) -> Self {
-        let tokens = fen.split(" ").collect::<Vec<&str>>();
-        let mut reversible_counts = Vec::with_capacity(MAX_PLY);
-        reversible_counts.push(tokens[4].parse().unwrap());
-        Self {
-            position: chess::Board::from_str(fen).unwrap(),
-            history: Vec::with_capacity(MAX_PLY),
-            reversible_counts,
+    ///
+    /// the halfmove clock and fullmove number may be omitted, defaulting to 0 and 1
+    ///
+    /// castling rights may be given in X-FEN or Shredder-FEN, and rights that standard
+    /// castling can't represent switch the board to Chess960
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let tokens = fen.split_whitespace().collect::<Vec<&str>>();
+        if tokens.len() < 4 {
+            return Err(FenError::MissingFields(tokens.len()));
         }
+        if tokens.len() > 6 {
+            return Err(FenError::TooManyFields(tokens.len()));
+        }
+
+        let invalid = || FenError::InvalidPosition(tokens[..4].join(" "));
+        let position = chess::Board::from_str(&format!("{} {} - {}", tokens[0], tokens[1], tokens[3]))
+            .map_err(|_| invalid())?;
+        let rooks = CastlingRooks::from_fen_field(tokens[2], &position).ok_or_else(invalid)?;
+        let (position, rooks, chess960) = match rooks.to_castle_rights(&position) {
+            Some(rights) => (chess960::with_castle_rights(&position, rights), CastlingRooks::default(), false),
+            None => (position, rooks, true),
+        };
+        // anything past a hundred is a draw anyway, so a larger clock only needs to stay large
+        let halfmove_clock = match tokens.get(4) {
+            Some(token) => token.parse::<u32>().map_err(|_| FenError::InvalidHalfmoveClock(token.to_string()))?
+                .min(u8::MAX as u32) as u8,
+            None => 0,
+        };
+        let fullmove_number = match tokens.get(5) {
+            // some writers use 0 for the first move
+            Some(token) => token.parse::<u16>().map_err(|_| FenError::InvalidFullmoveNumber(token.to_string()))?.max(1),
+            None => 1,
+        };
+
+        let mut keys = Vec::with_capacity(MAX_PLY);
+        keys.push(position.get_hash() ^ rooks.hash());
+        Ok(Self {
+            position,
+            castling: rooks,
+            halfmove_clock,
+            undo: Vec::with_capacity(MAX_PLY),
+            keys,
+            search_root: 0,
+            start_ply: (fullmove_number as u32 - 1) * 2 + (position.side_to_move() == Color::Black) as u32,
+            chess960,
+        })
+    }
+
+    /// Switches to Chess960 castling, where castling moves are written as the king capturing its own rook
+    ///
+    /// *must* be called before any moves are made
+    pub fn enable_chess960(&mut self) {
+        debug_assert!(self.keys.len() == 1);
+        if self.chess960 {
+            return;
+        }
+        self.castling = CastlingRooks::from_castle_rights(&self.position);
+        self.position = chess960::with_castle_rights(&self.position, [CastleRights::NoRights; 2]);
+        *self.keys.last_mut().unwrap() = self.position.get_hash() ^ self.castling_rooks().hash();
+        self.chess960 = true;
+    }
+
+    pub fn is_chess960(&self) -> bool {
+        self.chess960
+    }
+
+    /// Returns the Chess960 castling rights, which are always empty in standard chess
+    pub fn castling_rooks(&self) -> CastlingRooks {
+        self.castling
+    }
+
+    /// Returns true if either side may still castle, with standard or Chess960 rights
+    pub fn has_castling_rights(&self) -> bool {
+        !self.castling.is_empty()
+            || self.position.castle_rights(Color::White) != CastleRights::NoRights
+            || self.position.castle_rights(Color::Black) != CastleRights::NoRights
+    }
+
+    /// Returns the fen string of the current position, including both move counters
+    pub fn to_fen(&self) -> String {
+        let position = self.position.to_string();
+        let mut fields = position.split_whitespace().take(4).collect::<Vec<&str>>();
+        let castling = self.castling_rooks().to_fen_field(&self.position);
+        if self.chess960 {
+            fields[2] = &castling;
+        }
+        format!("{} {} {}", fields.join(" "), self.halfmove_clock(), self.fullmove_number())
     }
 
     pub fn hash(&self) -> u64 {
-        self.position.get_hash()
+        self.keys[self.keys.len() - 1]
     }
     
-    pub fn sorted_moves(&self, pv: Option<Move>, captures_only: bool) -> MoveGen {
-        MoveGen::new_sorted(&self.position, pv, captures_only)
+    /// Returns the legal moves with the pv move first, then captures
+    ///
+    /// Chess960 castling moves come last, unless one is the pv move
+    pub fn sorted_moves(&self, pv: Option<Move>, captures_only: bool) -> Moves {
+        let mut castles = self.castling_moves(captures_only);
+        let first = pv.filter(|mv| castles.contains(mv));
+        castles.retain(|&mv| Some(mv) != first);
+        // castling moves are unknown to the move generator, so they can't be passed to it
+        let pv = pv.filter(|&mv| !self.chess960 || self.castle_side(mv).is_none());
+        Moves { first, gen: MoveGen::new_sorted(&self.position, pv, captures_only), castles }
+    }
+
+    /// Returns the legal moves in generation order
+    pub fn legal_moves(&self) -> Moves {
+        Moves { first: None, gen: MoveGen::new_legal(&self.position), castles: self.castling_moves(false) }
+    }
+
+    fn castling_moves(&self, captures_only: bool) -> Vec<Move> {
+        if self.chess960 && !captures_only {
+            self.castling_rooks().castling_moves(&self.position)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Returns the side a move castles towards, or `None` if it isn't a castling move
+    pub fn castle_side(&self, mv: Move) -> Option<CastleSide> {
+        let king = self.position.king_square(self.side_to_move());
+        if mv.get_source() != king {
+            return None;
+        }
+        if self.chess960 {
+            return self.castling_rooks().side_of(self.side_to_move(), mv.get_dest());
+        }
+        match mv.get_dest().get_file().to_index() as i32 - king.get_file().to_index() as i32 {
+            2 => Some(CastleSide::Kingside),
+            -2 => Some(CastleSide::Queenside),
+            _ => None,
+        }
+    }
+
+    /// Returns the position after a legal move, without making it
+    pub fn position_after(&self, mv: Move) -> chess::Board {
+        match self.chess960.then(|| self.castle_side(mv)).flatten() {
+            Some(side) => chess960::castle(&self.position, mv, side),
+            None => self.position.make_move_new(mv),
+        }
     }
 
     /// Make a move on the board
     pub fn make_move(&mut self, mv: Move) {
-        self.history.push(self.position);
-        self.reversible_counts.push(
-            if self.is_reversible(mv) {
-                self.reversible_counts[self.reversible_counts.len() - 1] + 1
-            } else {
-                0
-            }
-        );
-        self.position = self.position.make_move_new(mv);
+        let next = self.position_after(mv);
+        let them = !self.side_to_move();
+        self.undo.push(Undo {
+            mv,
+            captured: self.position.piece_on(mv.get_dest()).filter(|_| self.position.color_on(mv.get_dest()) == Some(them)),
+            castle_rights: [self.position.castle_rights(Color::White), self.position.castle_rights(Color::Black)],
+            en_passant: self.position.en_passant(),
+            hash: self.position.get_hash(),
+            castling: self.castling,
+            halfmove_clock: self.halfmove_clock,
+        });
+        if self.chess960 {
+            self.castling.update(&self.position, mv);
+        }
+        self.halfmove_clock = if self.is_reversible(mv) { self.halfmove_clock.saturating_add(1) } else { 0 };
+        self.position = next;
+        self.keys.push(next.get_hash() ^ self.castling.hash());
     }
     
     /// Undo the most recent move
     pub fn undo_move(&mut self) {
-        if let Some(pos) = self.history.pop() {
-            self.position = pos;
-            self.reversible_counts.pop();
+        if let Some(undo) = self.undo.pop() {
+            self.position = self.position_before(&undo);
+            debug_assert_eq!(self.position.get_hash(), undo.hash, "{}", undo.mv);
+            self.castling = undo.castling;
+            self.halfmove_clock = undo.halfmove_clock;
+            self.keys.pop();
         } else {
             panic!("Attempted to undo a move that doesn't exist!");
         }
     }
 
+    /// Rebuilds the position before the last move from the current one
+    fn position_before(&self, undo: &Undo) -> chess::Board {
+        let (source, dest) = (undo.mv.get_source(), undo.mv.get_dest());
+        let us = !self.side_to_move();
+        let moved = match undo.mv.get_promotion() {
+            Some(_) => Some(Piece::Pawn),
+            None => self.position.piece_on(dest),
+        };
+        // a Chess960 castle is the king capturing its own rook, so it is the only move onto a castling rook
+        let castle_side = match self.chess960 {
+            true => undo.castling.side_of(us, dest),
+            false if moved == Some(Piece::King) => match dest.get_file().to_index() as i32 - source.get_file().to_index() as i32 {
+                2 => Some(CastleSide::Kingside),
+                -2 => Some(CastleSide::Queenside),
+                _ => None,
+            },
+            false => None,
+        };
+
+        let mut builder = BoardBuilder::from(&self.position);
+        if let Some(side) = castle_side {
+            let rank = source.get_rank();
+            let rook = match (self.chess960, side) {
+                (true, _) => dest,
+                (false, CastleSide::Kingside) => Square::make_square(rank, File::H),
+                (false, CastleSide::Queenside) => Square::make_square(rank, File::A),
+            };
+            builder
+                .clear_square(Square::make_square(rank, side.king_file()))
+                .clear_square(Square::make_square(rank, side.rook_file()))
+                .piece(source, Piece::King, us)
+                .piece(rook, Piece::Rook, us);
+        } else {
+            let moved = moved.expect("the moved piece must be on its destination");
+            builder.clear_square(dest).piece(source, moved, us);
+            if let Some(captured) = undo.captured {
+                builder.piece(dest, captured, !us);
+            } else if moved == Piece::Pawn && source.get_file() != dest.get_file() {
+                // en passant, the captured pawn stood beside the capturing one
+                builder.piece(Square::make_square(source.get_rank(), dest.get_file()), Piece::Pawn, !us);
+            }
+        }
+        builder
+            .side_to_move(us)
+            .castle_rights(Color::White, undo.castle_rights[0])
+            .castle_rights(Color::Black, undo.castle_rights[1])
+            .en_passant(undo.en_passant.map(|sq| sq.get_file()));
+        chess::Board::try_from(builder).expect("undoing a move must lead to a valid position")
+    }
+
+    /// Drops the undo records of the moves made so far, which can no longer be undone
+    ///
+    /// their keys are kept for repetition detection, so a game can be replayed move by move
+    /// without the board growing with each one
+    pub fn forget_moves(&mut self) {
+        self.undo.clear();
+    }
+
     /// Returns the current side to move
     pub fn side_to_move(&self) -> chess::Color {
         self.position.side_to_move()
@@ -88,92 +440,101 @@ impl Board {
 
     /// Returns true if the current position matches a previous one
     pub fn is_repeated(&self) -> bool {
-        for pos in &self.history[0..(self.history.len() - 1)] {
-            if pos.get_hash() == self.position.get_hash() {
-                return true 
+        self.is_repetition(RepetitionRule::Twofold)
+    }
+
+    /// Returns true if the current position is a draw by repetition under the given rule
+    ///
+    /// only positions since the last irreversible move with the same side to move are compared
+    pub fn is_repetition(&self, rule: RepetitionRule) -> bool {
+        let current = self.keys.len() - 1;
+        let window = (self.halfmove_clock() as usize).min(current);
+        let hash = self.keys[current];
+        let mut earlier = 0;
+
+        for idx in (current - window..current.saturating_sub(1)).rev().step_by(2) {
+            if self.keys[idx] == hash {
+                if rule == RepetitionRule::Twofold || idx > self.search_root {
+                    return true;
+                }
+                earlier += 1;
+                if earlier == 2 {
+                    return true;
+                }
             }
         }
         false
     }
 
-    /// Returns true if the position is a draw by fifty move rule
-    pub fn is_fifty_move_draw(&self) -> bool {
-        self.reversible_counts[self.reversible_counts.len() - 1] >= 100 
+    /// Returns how many times the current position occurred before, with the same side to move
+    /// and castling rights
+    pub fn repetition_count(&self) -> usize {
+        let current = self.keys.len() - 1;
+        let window = (self.halfmove_clock() as usize).min(current);
+        let hash = self.keys[current];
+        (current - window..current.saturating_sub(1)).rev().step_by(2)
+            .filter(|&idx| self.keys[idx] == hash)
+            .count()
     }
 
-    /// Returns true is the position is guaranteed to be drawn by to insufficient material
-    ///
-    /// Included cases:
+    /// Returns whether the game has ended under the rules, with draws that can be claimed counted as final
     ///
-    /// king vs king
-    /// king + knight vs king
-    /// kings and bishops of the same colour
-    pub fn is_insufficient_material(&self) -> bool {
-        let kings = self.position.pieces(Piece::King);
-        let all_pieces = self.position.combined() ^ kings;
-
-        // if (non-king) pieces exist on both light and dark squares, no draw occurs
-        if (all_pieces & LIGHT_SQUARES != EMPTY) && (all_pieces & DARK_SQUARES != EMPTY) {
-            return false;
-        }
-
-        // king vs king
-        if all_pieces == EMPTY {
-            return true;
+    /// unlike the search, a repetition only ends the game on its third occurrence
+    pub fn game_state(&self) -> GameState {
+        if self.legal_moves().len() == 0 {
+            return match self.checkers().popcnt() {
+                0 => GameState::Stalemate,
+                _ => GameState::Checkmate,
+            };
         }
-
-        // king + knight vs king
-        let knights = *self.position.pieces(Piece::Knight);
-        if all_pieces == knights && knights.popcnt() == 1 {
-            return true;
+        if self.is_insufficient_material() {
+            GameState::InsufficientMaterial
+        } else if self.is_fifty_move_draw() {
+            GameState::FiftyMoves
+        } else if self.repetition_count() >= 2 {
+            GameState::ThreefoldRepetition
+        } else {
+            GameState::Ongoing
         }
+    }
 
-        // kings and bishops of the same colour
-        let bishops = *self.position.pieces(Piece::Bishop);
-        if all_pieces == bishops {
-            return true
-        }
+    /// Marks the current position as the root of a search, for `RepetitionRule::Threefold`
+    pub fn set_search_root(&mut self) {
+        self.search_root = self.keys.len() - 1;
+    }
 
-        false
+    /// Returns the number of moves made since the search root
+    pub fn ply(&self) -> usize {
+        self.keys.len() - 1 - self.search_root
     }
 
-    /// Returns true if the neither side can force a win
-    /// 
-    /// Included cases:
-    ///
-    /// king vs king
-    /// kings and two or less knights
-    /// kings and bishops of the same color
-    ///
-    /// Edge cases:
-    /// king + 2 knights vs king
-    /// king + knight vs king + knight
-    /// - both return true: no forced win is possible
-    ///
-    /// king + bishop vs king + knight
-    /// - returns false: no forced win is possible, but ignoring this case speeds up the function
-    pub fn is_insufficient_material_pseudo(&self) -> bool {
-        let kings = self.position.pieces(Piece::King);
+    /// Returns true if the position is a draw by fifty move rule
+    pub fn is_fifty_move_draw(&self) -> bool {
+        self.halfmove_clock() >= 100
+    }
 
-        if kings == self.position.combined() {
-            return true;
-        }
+    /// Returns the number of plies since the last capture or pawn move
+    pub fn halfmove_clock(&self) -> u8 {
+        self.halfmove_clock
+    }
 
-        // kings and two or less knights
-        let all_pieces = self.position.combined() ^ kings;
-        let knights = *self.position.pieces(Piece::Knight);
-        if all_pieces == knights && knights.popcnt() <= 2 {
-            return true 
-        }
+    /// Returns the fullmove number, starting at 1 and incremented after each black move
+    pub fn fullmove_number(&self) -> u16 {
+        ((self.start_ply + self.keys.len() as u32 - 1) / 2 + 1).min(u16::MAX as u32) as u16
+    }
 
-        // kings and bishops of the same colour
-        let bishops = *self.position.pieces(Piece::Bishop);
-        if all_pieces == bishops
-            && ((bishops & LIGHT_SQUARES == EMPTY) || (bishops & DARK_SQUARES == EMPTY)) {
-            return true
-        }
+    /// Returns true if neither side can checkmate by any sequence of legal moves
+    ///
+    /// see `material_draw` for the cases included
+    pub fn is_insufficient_material(&self) -> bool {
+        self.material_draw() == Some(MaterialDraw::Dead)
+    }
 
-        false
+    /// Returns true if neither side can force a win, although checkmate may still be possible
+    ///
+    /// see `material_draw` for the cases included
+    pub fn is_insufficient_material_pseudo(&self) -> bool {
+        self.material_draw().is_some()
     }
 
     /// Returns true if the given move is reversible (not a pawn move or capture)
@@ -185,9 +546,15 @@ impl Board {
     }
 }
 
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl fmt::Display for Board {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.position)
+        write!(f, "{}", self.to_fen())
     }
 }
 
@@ -204,12 +571,151 @@ mod tests {
         board.undo_move();
         board.undo_move();
         assert!(board.position.is_sane());
-        assert!(board.reversible_counts.pop() == Some(0));
+        assert_eq!(board.halfmove_clock(), 0);
+        assert_eq!(board.hash(), Board::new().hash());
+    }
+
+    #[test]
+    fn test_forget_moves() {
+        let mut board = Board::from_fen("Q6k/8/K7/8/8/8/8/8 b - - 0 1").unwrap();
+        for (from, to) in [(Square::H8, Square::H7), (Square::A8, Square::A7), (Square::H7, Square::H8)] {
+            board.make_move(Move::new(from, to, None));
+        }
+        board.forget_moves();
+        // repetitions and move counters still see the forgotten moves
+        board.make_move(Move::new(Square::A7, Square::A8, None));
+        assert!(board.is_repeated());
+        assert_eq!(board.fullmove_number(), 3);
+        board.undo_move();
+        assert_eq!(board.to_fen(), "7k/Q7/K7/8/8/8/8/8 w - - 3 3");
+    }
+
+    const PERFT_FENS: [&str; 4] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    ];
+
+    #[test]
+    fn test_fen_round_trip() {
+        for fen in PERFT_FENS {
+            assert_eq!(Board::from_fen(fen).unwrap().to_fen(), fen);
+        }
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b Kq - 17 42";
+        assert_eq!(Board::from_fen(fen).unwrap().to_fen(), fen);
+        let fen = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+        assert_eq!(Board::from_fen(fen).unwrap().to_fen(), fen);
+    }
+
+    #[test]
+    fn test_chess960_fen() {
+        // Shredder-FEN is written back as X-FEN
+        let board = Board::from_fen("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9").unwrap();
+        assert!(board.is_chess960());
+        assert_eq!(board.to_fen(), "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9");
+        let fen = "rr2k3/8/8/8/8/8/8/RR2K3 w Bb - 0 1";
+        assert_eq!(Board::from_fen(fen).unwrap().to_fen(), fen);
+
+        // standard rights stay standard unless chess960 is enabled
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w HAha - 0 1").unwrap();
+        assert!(!board.is_chess960());
+        let hash = board.hash();
+        board.enable_chess960();
+        assert_eq!(board.to_fen(), "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        assert_ne!(board.hash(), Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1").unwrap().hash());
+        assert_ne!(board.hash(), hash);
+    }
+
+    #[test]
+    fn test_chess960_castling() {
+        let mut board = Board::from_fen("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1").unwrap();
+        let hash = board.hash();
+        board.make_move(Move::new(Square::G1, Square::B1, None));
+        assert_eq!(board.to_fen(), "1r4kr/8/8/8/8/8/8/2KR3R b kq - 1 1");
+        board.make_move(Move::new(Square::H8, Square::H7, None));
+        assert_eq!(board.to_fen(), "1r4k1/7r/8/8/8/8/8/2KR3R w q - 2 2");
+        board.undo_move();
+        board.undo_move();
+        assert_eq!(board.to_fen(), "1r4kr/8/8/8/8/8/8/1R4KR w KQkq - 0 1");
+        assert_eq!(board.hash(), hash);
+
+        // positions only differing in castling rights are not repetitions
+        let mut board = Board::from_fen("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1").unwrap();
+        for mv in [(Square::B1, Square::A1), (Square::B8, Square::A8), (Square::A1, Square::B1), (Square::A8, Square::B8)] {
+            board.make_move(Move::new(mv.0, mv.1, None));
+        }
+        assert!(!board.is_repeated());
+        assert_eq!(board.to_fen(), "1r4kr/8/8/8/8/8/8/1R4KR w Kk - 4 3");
+    }
+
+    #[test]
+    fn test_fen_move_counters() {
+        let mut board = Board::from_fen(PERFT_FENS[0]).unwrap();
+        board.make_move(Move::new(Square::G1, Square::F3, None));
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1");
+        board.make_move(Move::new(Square::G8, Square::F6, None));
+        assert_eq!(board.to_fen(), "rnbqkb1r/pppppppp/5n2/8/8/5N2/PPPPPPPP/RNBQKB1R w KQkq - 2 2");
+        board.make_move(Move::new(Square::E2, Square::E4, None));
+        assert_eq!(board.to_fen(), "rnbqkb1r/pppppppp/5n2/8/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 0 2");
+        board.undo_move();
+        board.undo_move();
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1");
+
+        // black to move in the starting position counts from the given move number
+        let mut board = Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 3 30").unwrap();
+        board.make_move(Move::new(Square::H5, Square::H6, None));
+        assert_eq!(board.fullmove_number(), 31);
+        assert_eq!(board.halfmove_clock(), 4);
+
+        // counters beyond what is stored are kept as large as possible
+        let mut board = Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 300 65535").unwrap();
+        assert_eq!((board.halfmove_clock(), board.fullmove_number()), (255, 65535));
+        board.make_move(Move::new(Square::H5, Square::H6, None));
+        assert_eq!((board.halfmove_clock(), board.fullmove_number()), (255, 65535));
+        assert!(board.is_fifty_move_draw());
+    }
+
+    #[test]
+    fn test_fen_defaults() {
+        let board = Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - -").unwrap();
+        assert_eq!(board.to_fen(), "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1");
+        let board = Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 12").unwrap();
+        assert_eq!(board.to_fen(), "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 12 1");
+        // surrounding and repeated whitespace is ignored
+        assert!(Board::from_fen("  8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8   w - - 0 1\n").is_ok());
+    }
+
+    #[test]
+    fn test_fen_errors() {
+        assert_eq!(Board::from_fen("").err(), Some(FenError::MissingFields(0)));
+        assert_eq!(Board::from_fen("8/8/8/8/8/8/8/8 w").err(), Some(FenError::MissingFields(2)));
+        assert_eq!(
+            Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1 extra").err(),
+            Some(FenError::TooManyFields(7)),
+        );
+        assert_eq!(
+            Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - x 1").err(),
+            Some(FenError::InvalidHalfmoveClock("x".to_string())),
+        );
+        assert_eq!(
+            Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 -1").err(),
+            Some(FenError::InvalidFullmoveNumber("-1".to_string())),
+        );
+        // malformed placement, bad side to move, missing king
+        for fen in [
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1 w - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/9 w - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 x - - 0 1",
+            "8/2p5/3p4/1P5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ] {
+            assert!(matches!(Board::from_fen(fen), Err(FenError::InvalidPosition(_))), "{}", fen);
+        }
     }
 
     #[test]
     fn test_is_repeated() {
-        let mut board = Board::from_fen("Q6k/8/K7/8/8/8/8/8 b - - 0 1");
+        let mut board = Board::from_fen("Q6k/8/K7/8/8/8/8/8 b - - 0 1").unwrap();
         board.make_move(Move::new(Square::H8, Square::H7, None));
         board.make_move(Move::new(Square::A8, Square::A7, None));
         board.make_move(Move::new(Square::H7, Square::H8, None));
@@ -217,13 +723,81 @@ mod tests {
         assert!(board.is_repeated());
     }
 
+    #[test]
+    fn test_repetition_rules() {
+        let shuffle = [(Square::H8, Square::H7), (Square::A8, Square::A7), (Square::H7, Square::H8), (Square::A7, Square::A8)];
+        let mut board = Board::from_fen("Q6k/8/K7/8/8/8/8/8 b - - 0 1").unwrap();
+        board.set_search_root();
+        for (from, to) in shuffle {
+            board.make_move(Move::new(from, to, None));
+        }
+        // the first occurrence was the search root, so it is part of the game history
+        assert!(board.is_repetition(RepetitionRule::Twofold));
+        assert!(!board.is_repetition(RepetitionRule::Threefold));
+
+        // a third occurrence is a draw
+        board.set_search_root();
+        for (from, to) in shuffle {
+            board.make_move(Move::new(from, to, None));
+        }
+        assert!(board.is_repetition(RepetitionRule::Threefold));
+
+        // a repetition of a position after the root is a draw
+        board.undo_move();
+        board.undo_move();
+        board.undo_move();
+        board.undo_move();
+        board.make_move(Move::new(Square::H8, Square::G7, None));
+        board.set_search_root();
+        board.make_move(Move::new(Square::A8, Square::B8, None));
+        assert!(!board.is_repetition(RepetitionRule::Threefold));
+        for (from, to) in [(Square::G7, Square::G6), (Square::B8, Square::A8), (Square::G6, Square::G7), (Square::A8, Square::B8)] {
+            board.make_move(Move::new(from, to, None));
+        }
+        assert!(board.is_repetition(RepetitionRule::Threefold));
+    }
+
+    #[test]
+    fn test_game_state() {
+        let state = |fen: &str| Board::from_fen(fen).unwrap().game_state();
+        assert_eq!(state(PERFT_FENS[0]), GameState::Ongoing);
+        assert_eq!(state("R5k1/5ppp/8/8/8/8/8/6K1 b - - 1 1"), GameState::Checkmate);
+        assert_eq!(state("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), GameState::Stalemate);
+        assert_eq!(state("4KN1k/8/8/8/8/8/8/8 w - - 0 1"), GameState::InsufficientMaterial);
+        assert_eq!(state("8/1R5p/6k1/8/8/8/1R4K1/8 b - - 100 60"), GameState::FiftyMoves);
+        // mate on the move that would complete the fifty moves still wins
+        assert_eq!(state("R5k1/5ppp/8/8/8/8/8/6K1 b - - 100 60"), GameState::Checkmate);
+
+        assert_eq!(GameState::Checkmate.result(Color::Black), GameResult::WhiteWin);
+        assert_eq!(GameState::Stalemate.result(Color::White), GameResult::Draw);
+        assert_eq!(GameState::Ongoing.result(Color::White), GameResult::Ongoing);
+    }
+
+    #[test]
+    fn test_threefold_repetition() {
+        let shuffle = [(Square::H8, Square::H7), (Square::A8, Square::A7), (Square::H7, Square::H8), (Square::A7, Square::A8)];
+        let mut board = Board::from_fen("Q6k/8/K7/8/8/8/8/8 b - - 0 1").unwrap();
+        for (from, to) in shuffle {
+            board.make_move(Move::new(from, to, None));
+        }
+        // a single repetition is enough for the search, but not for the game
+        assert_eq!(board.repetition_count(), 1);
+        assert!(board.is_repeated());
+        assert_eq!(board.game_state(), GameState::Ongoing);
+        for (from, to) in shuffle {
+            board.make_move(Move::new(from, to, None));
+        }
+        assert_eq!(board.repetition_count(), 2);
+        assert_eq!(board.game_state(), GameState::ThreefoldRepetition);
+    }
+
     #[test]
     fn test_is_fifty_move_draw() {
-        let mut board = Board::from_fen("R4K1k/8/8/8/8/8/8/8 w - - 99 80");
+        let mut board = Board::from_fen("R4K1k/8/8/8/8/8/8/8 w - - 99 80").unwrap();
         board.make_move(Move::new(Square::F8, Square::F7, None));
         assert!(board.is_fifty_move_draw());
 
-        let mut board = Board::from_fen("8/1R5p/6k1/8/8/8/1R4K1/8 w - - 99 60");
+        let mut board = Board::from_fen("8/1R5p/6k1/8/8/8/1R4K1/8 w - - 99 60").unwrap();
         board.make_move(Move::new(Square::B7, Square::H7, None));
         assert!(!board.is_fifty_move_draw());
     }
@@ -231,31 +805,31 @@ mod tests {
     #[test]
     fn test_is_insufficient_material() {
         // king vs king
-        assert!(Board::from_fen("K6k/8/8/8/8/8/8/8 w - - 0 1").is_insufficient_material());
+        assert!(Board::from_fen("K6k/8/8/8/8/8/8/8 w - - 0 1").unwrap().is_insufficient_material());
         // king + knight vs king
-        assert!(Board::from_fen("4KN1k/8/8/8/8/8/8/8 w - - 0 1").is_insufficient_material());
+        assert!(Board::from_fen("4KN1k/8/8/8/8/8/8/8 w - - 0 1").unwrap().is_insufficient_material());
         // kings and bishops of the same colour
-        assert!(Board::from_fen("B1B1BK1k/8/8/8/8/8/8/8 w - - 0 1").is_insufficient_material());
+        assert!(Board::from_fen("B1B1BK1k/8/8/8/8/8/8/8 w - - 0 1").unwrap().is_insufficient_material());
 
         // king + 2 knights vs king
-        assert!(!Board::from_fen("3KNN1k/8/8/8/8/8/8/8 w - - 0 1").is_insufficient_material());
+        assert!(!Board::from_fen("3KNN1k/8/8/8/8/8/8/8 w - - 0 1").unwrap().is_insufficient_material());
         // king + opposite colour bishops
-        assert!(!Board::from_fen("3KBB1k/8/8/8/8/8/8/8 w - - 0 1").is_insufficient_material());
+        assert!(!Board::from_fen("3KBB1k/8/8/8/8/8/8/8 w - - 0 1").unwrap().is_insufficient_material());
     }
 
     #[test]
     fn test_is_insufficent_material_pseudo() {
         // king vs king
-        assert!(Board::from_fen("K6k/8/8/8/8/8/8/8 w - - 0 1").is_insufficient_material_pseudo());
+        assert!(Board::from_fen("K6k/8/8/8/8/8/8/8 w - - 0 1").unwrap().is_insufficient_material_pseudo());
         // king + knight vs king
-        assert!(Board::from_fen("4KN1k/8/8/8/8/8/8/8 w - - 0 1").is_insufficient_material_pseudo());
+        assert!(Board::from_fen("4KN1k/8/8/8/8/8/8/8 w - - 0 1").unwrap().is_insufficient_material_pseudo());
         // kings and bishops of the same colour
-        assert!(Board::from_fen("B1B1BK1k/8/8/8/8/8/8/8 w - - 0 1").is_insufficient_material_pseudo());
+        assert!(Board::from_fen("B1B1BK1k/8/8/8/8/8/8/8 w - - 0 1").unwrap().is_insufficient_material_pseudo());
 
         // king + 2 knights vs king
         // Note: this case differs from `test_is_insufficient_material`
-        assert!(Board::from_fen("3KNN1k/8/8/8/8/8/8/8 w - - 0 1").is_insufficient_material_pseudo());
+        assert!(Board::from_fen("3KNN1k/8/8/8/8/8/8/8 w - - 0 1").unwrap().is_insufficient_material_pseudo());
         // king + opposite colour bishops
-        assert!(!Board::from_fen("3KBB1k/8/8/8/8/8/8/8 w - - 0 1").is_insufficient_material_pseudo());
+        assert!(!Board::from_fen("3KBB1k/8/8/8/8/8/8/8 w - - 0 1").unwrap().is_insufficient_material_pseudo());
     }
 }
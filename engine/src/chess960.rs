@@ -0,0 +1,329 @@
+use chess::{
+    between, get_bishop_moves, get_king_moves, get_knight_moves, get_pawn_attacks, get_rook_moves,
+    BitBoard, BoardBuilder, CastleRights, Color, File, Piece, Square, EMPTY,
+};
+
+use crate::board::Move;
+
+/// Zobrist keys for each castling rook, indexed by color then file
+const CASTLING_KEYS: [[u64; 8]; 2] = {
+    let mut keys = [[0; 8]; 2];
+    let mut seed: u64 = 0x3C6E_F372_FE94_F82B;
+    let mut idx = 0;
+    while idx < 16 {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        keys[idx / 8][idx % 8] = seed;
+        idx += 1;
+    }
+    keys
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CastleSide {
+    Kingside,
+    Queenside,
+}
+
+impl CastleSide {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The file the king ends up on, the same as in standard chess
+    pub fn king_file(self) -> File {
+        match self {
+            CastleSide::Kingside => File::G,
+            CastleSide::Queenside => File::C,
+        }
+    }
+
+    pub fn rook_file(self) -> File {
+        match self {
+            CastleSide::Kingside => File::F,
+            CastleSide::Queenside => File::D,
+        }
+    }
+}
+
+/// The rooks each side may still castle with, indexed by color then side
+///
+/// Chess960 castling rights can't be stored in `chess::Board`, which expects rooks on the a and h files,
+/// so in Chess960 mode `Board` keeps them here and the underlying position has none.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct CastlingRooks([[Option<Square>; 2]; 2]);
+
+impl CastlingRooks {
+    pub fn get(&self, color: Color, side: CastleSide) -> Option<Square> {
+        self.0[color.to_index()][side.index()]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns the side castled towards if the rook on `sq` can castle
+    pub fn side_of(&self, color: Color, sq: Square) -> Option<CastleSide> {
+        [CastleSide::Kingside, CastleSide::Queenside].into_iter().find(|&side| self.get(color, side) == Some(sq))
+    }
+
+    pub fn hash(&self) -> u64 {
+        let mut key = 0;
+        for (color, rooks) in self.0.iter().enumerate() {
+            for rook in rooks.iter().flatten() {
+                key ^= CASTLING_KEYS[color][rook.get_file().to_index()];
+            }
+        }
+        key
+    }
+
+    /// Returns the rooks matching the standard castling rights of a position
+    pub fn from_castle_rights(pos: &chess::Board) -> Self {
+        let mut rooks = Self::default();
+        for color in [Color::White, Color::Black] {
+            let rights = pos.castle_rights(color);
+            let rank = color.to_my_backrank();
+            if rights.has_kingside() {
+                rooks.0[color.to_index()][CastleSide::Kingside.index()] = Some(Square::make_square(rank, File::H));
+            }
+            if rights.has_queenside() {
+                rooks.0[color.to_index()][CastleSide::Queenside.index()] = Some(Square::make_square(rank, File::A));
+            }
+        }
+        rooks
+    }
+
+    /// Returns the equivalent standard castling rights, if every right has the king on the e file
+    /// and rooks in the corners
+    pub fn to_castle_rights(self, pos: &chess::Board) -> Option<[CastleRights; 2]> {
+        let mut rights = [CastleRights::NoRights; 2];
+        for color in [Color::White, Color::Black] {
+            let rank = color.to_my_backrank();
+            for (side, corner, right) in [
+                (CastleSide::Kingside, File::H, CastleRights::KingSide),
+                (CastleSide::Queenside, File::A, CastleRights::QueenSide),
+            ] {
+                let Some(rook) = self.get(color, side) else { continue };
+                if rook != Square::make_square(rank, corner) || pos.king_square(color) != Square::make_square(rank, File::E) {
+                    return None;
+                }
+                rights[color.to_index()] = rights[color.to_index()].add(right);
+            }
+        }
+        Some(rights)
+    }
+
+    /// Parses the castling field of a FEN string
+    ///
+    /// Accepts standard `KQkq`, X-FEN, where `K` and `Q` mean the outermost rook on that side
+    /// of the king, and Shredder-FEN, which names the rook files directly (`HAha`).
+    pub fn from_fen_field(field: &str, pos: &chess::Board) -> Option<Self> {
+        let mut rooks = Self::default();
+        if field == "-" {
+            return Some(rooks);
+        }
+
+        for c in field.chars() {
+            let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+            let rank = color.to_my_backrank();
+            let king = pos.king_square(color);
+            if king.get_rank() != rank {
+                return None;
+            }
+            let king_file = king.get_file().to_index();
+            let back_rooks = (*pos.pieces(Piece::Rook) & *pos.color_combined(color))
+                .filter(|sq| sq.get_rank() == rank)
+                .collect::<Vec<Square>>();
+
+            let rook = match c.to_ascii_lowercase() {
+                'k' => back_rooks.iter().filter(|sq| sq.get_file().to_index() > king_file).max_by_key(|sq| sq.to_index()).copied(),
+                'q' => back_rooks.iter().filter(|sq| sq.get_file().to_index() < king_file).min_by_key(|sq| sq.to_index()).copied(),
+                file @ 'a'..='h' => {
+                    let sq = Square::make_square(rank, File::from_index((file as u8 - b'a') as usize));
+                    back_rooks.contains(&sq).then_some(sq)
+                },
+                _ => None,
+            }?;
+            let side = if rook.get_file().to_index() > king_file { CastleSide::Kingside } else { CastleSide::Queenside };
+            rooks.0[color.to_index()][side.index()] = Some(rook);
+        }
+        Some(rooks)
+    }
+
+    /// Writes the castling field in X-FEN, naming the rook file only when another rook
+    /// stands further out on the same side
+    pub fn to_fen_field(&self, pos: &chess::Board) -> String {
+        let mut field = String::new();
+        for color in [Color::White, Color::Black] {
+            let rank = color.to_my_backrank();
+            for side in [CastleSide::Kingside, CastleSide::Queenside] {
+                let Some(rook) = self.get(color, side) else { continue };
+                let outer_files = match side {
+                    CastleSide::Kingside => rook.get_file().to_index() + 1..8,
+                    CastleSide::Queenside => 0..rook.get_file().to_index(),
+                };
+                let outermost = outer_files
+                    .map(|file| Square::make_square(rank, File::from_index(file)))
+                    .all(|sq| pos.piece_on(sq) != Some(Piece::Rook) || pos.color_on(sq) != Some(color));
+
+                let c = match (outermost, side) {
+                    (true, CastleSide::Kingside) => 'k',
+                    (true, CastleSide::Queenside) => 'q',
+                    (false, _) => (b'a' + rook.get_file().to_index() as u8) as char,
+                };
+                field.push(if color == Color::White { c.to_ascii_uppercase() } else { c });
+            }
+        }
+        if field.is_empty() { "-".to_string() } else { field }
+    }
+
+    /// Removes the rights lost by playing a move, *before* it is made
+    pub fn update(&mut self, pos: &chess::Board, mv: Move) {
+        let us = pos.side_to_move();
+        if mv.get_source() == pos.king_square(us) {
+            self.0[us.to_index()] = [None; 2];
+        }
+        for rook in self.0.iter_mut().flatten() {
+            if *rook == Some(mv.get_source()) || *rook == Some(mv.get_dest()) {
+                *rook = None;
+            }
+        }
+    }
+
+    /// Returns the legal castling moves for the side to move, written as the king capturing its rook
+    pub fn castling_moves(&self, pos: &chess::Board) -> Vec<Move> {
+        let us = pos.side_to_move();
+        if *pos.checkers() != EMPTY {
+            return Vec::new();
+        }
+        [CastleSide::Kingside, CastleSide::Queenside]
+            .into_iter()
+            .filter_map(|side| Some((side, self.get(us, side)?)))
+            .filter(|&(side, rook)| can_castle(pos, rook, side))
+            .map(|(_, rook)| Move::new(pos.king_square(us), rook, None))
+            .collect()
+    }
+}
+
+/// Returns true if every square the king and rook cross is empty, and the king never passes through check
+fn can_castle(pos: &chess::Board, rook: Square, side: CastleSide) -> bool {
+    let us = pos.side_to_move();
+    let king = pos.king_square(us);
+    let king_to = Square::make_square(king.get_rank(), side.king_file());
+    let rook_to = Square::make_square(king.get_rank(), side.rook_file());
+
+    let others = *pos.combined() ^ BitBoard::from_square(king) ^ BitBoard::from_square(rook);
+    let king_path = between(king, king_to) | BitBoard::from_square(king_to);
+    let rook_path = between(rook, rook_to) | BitBoard::from_square(rook_to);
+    if (king_path | rook_path) & others != EMPTY {
+        return false;
+    }
+    if king_path.into_iter().any(|sq| is_attacked(pos, sq, *pos.combined())) {
+        return false;
+    }
+
+    // the castling rook may have been shielding the destination along the back rank
+    let after = others | BitBoard::from_square(king_to) | BitBoard::from_square(rook_to);
+    !is_attacked(pos, king_to, after)
+}
+
+/// Returns true if the opponent of the side to move attacks a square, given the occupied squares
+fn is_attacked(pos: &chess::Board, sq: Square, occupied: BitBoard) -> bool {
+    let them = !pos.side_to_move();
+    let theirs = *pos.color_combined(them);
+    let queens = *pos.pieces(Piece::Queen);
+    let attackers = (get_knight_moves(sq) & *pos.pieces(Piece::Knight))
+        | (get_king_moves(sq) & *pos.pieces(Piece::King))
+        | get_pawn_attacks(sq, !them, *pos.pieces(Piece::Pawn))
+        | (get_rook_moves(sq, occupied) & (*pos.pieces(Piece::Rook) | queens))
+        | (get_bishop_moves(sq, occupied) & (*pos.pieces(Piece::Bishop) | queens));
+    attackers & theirs != EMPTY
+}
+
+/// Returns the position after a castling move written as the king capturing its rook
+pub fn castle(pos: &chess::Board, mv: Move, side: CastleSide) -> chess::Board {
+    let us = pos.side_to_move();
+    let rank = mv.get_source().get_rank();
+    let mut builder = BoardBuilder::from(pos);
+    builder
+        .clear_square(mv.get_source())
+        .clear_square(mv.get_dest())
+        .piece(Square::make_square(rank, side.king_file()), Piece::King, us)
+        .piece(Square::make_square(rank, side.rook_file()), Piece::Rook, us)
+        .side_to_move(!us)
+        .en_passant(None);
+    chess::Board::try_from(builder).expect("castling must lead to a valid position")
+}
+
+/// Returns the position with its castling rights replaced
+pub fn with_castle_rights(pos: &chess::Board, rights: [CastleRights; 2]) -> chess::Board {
+    let mut builder = BoardBuilder::from(pos);
+    builder
+        .castle_rights(Color::White, rights[0])
+        .castle_rights(Color::Black, rights[1]);
+    chess::Board::try_from(builder).expect("castling rights must match the position")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn rooks(fen: &str) -> (chess::Board, CastlingRooks) {
+        let fields = fen.split_whitespace().collect::<Vec<&str>>();
+        let pos = chess::Board::from_str(&format!("{} {} - -", fields[0], fields[1])).unwrap();
+        let rooks = CastlingRooks::from_fen_field(fields[2], &pos).unwrap();
+        (pos, rooks)
+    }
+
+    #[test]
+    fn test_castling_field() {
+        // X-FEN and Shredder-FEN describe the same rights
+        let (pos, xfen) = rooks("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9");
+        let (_, shredder) = rooks("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9");
+        assert_eq!(xfen, shredder);
+        assert_eq!(xfen.get(Color::White, CastleSide::Kingside), Some(Square::H1));
+        assert_eq!(xfen.get(Color::Black, CastleSide::Queenside), Some(Square::F8));
+        assert_eq!(xfen.to_fen_field(&pos), "KQkq");
+        assert_eq!(xfen.to_castle_rights(&pos), None);
+
+        // an inner rook is named by its file
+        let (pos, inner) = rooks("rr2k3/8/8/8/8/8/8/RR2K3 w Bb - 0 1");
+        assert_eq!(inner.get(Color::White, CastleSide::Queenside), Some(Square::B1));
+        assert_eq!(inner.to_fen_field(&pos), "Bb");
+
+        let (pos, standard) = rooks("r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1");
+        assert_eq!(standard.to_castle_rights(&pos), Some([CastleRights::KingSide, CastleRights::QueenSide]));
+        assert_eq!(CastlingRooks::from_castle_rights(&with_castle_rights(&pos, standard.to_castle_rights(&pos).unwrap())), standard);
+
+        // no rook on that side, or on the named file
+        let pos = chess::Board::from_str("4k3/8/8/8/8/8/8/4K2R w - -").unwrap();
+        assert_eq!(CastlingRooks::from_fen_field("Q", &pos), None);
+        assert_eq!(CastlingRooks::from_fen_field("G", &pos), None);
+        assert_eq!(CastlingRooks::from_fen_field("X", &pos), None);
+    }
+
+    #[test]
+    fn test_castling_moves() {
+        // king and rook swap places
+        let (pos, castling) = rooks("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1");
+        let moves = castling.castling_moves(&pos);
+        assert!(moves.contains(&Move::new(Square::G1, Square::H1, None)));
+        assert!(moves.contains(&Move::new(Square::G1, Square::B1, None)));
+        let after = castle(&pos, Move::new(Square::G1, Square::B1, None), CastleSide::Queenside);
+        assert_eq!(after.piece_on(Square::C1), Some(Piece::King));
+        assert_eq!(after.piece_on(Square::D1), Some(Piece::Rook));
+        assert_eq!(after.piece_on(Square::G1), None);
+
+        // the castling rook shields the king's destination from the rook on a1
+        let (pos, castling) = rooks("4k3/8/8/8/8/8/8/rRK5 w B - 0 1");
+        assert!(castling.castling_moves(&pos).is_empty());
+
+        // the king may not pass through an attacked square, but the rook may
+        let (pos, castling) = rooks("2r1k3/8/8/8/8/8/8/RK6 w A - 0 1");
+        assert!(castling.castling_moves(&pos).is_empty());
+        let (pos, castling) = rooks("1r2k3/8/8/8/8/8/8/R1K5 w A - 0 1");
+        assert_eq!(castling.castling_moves(&pos), vec![Move::new(Square::C1, Square::A1, None)]);
+    }
+}
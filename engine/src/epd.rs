@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{board::{Board, FenError, Move}, eval::Eval, san::SanError};
+
+/// Reasons an EPD record can fail to parse
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EpdError {
+    Fen(FenError),
+    /// a move operand that can't be played in the position
+    Move(String, SanError),
+    /// an operand of the wrong type for its opcode
+    Operand(String, String),
+    Syntax(String),
+}
+
+impl fmt::Display for EpdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EpdError::Fen(e) => write!(f, "{}", e),
+            EpdError::Move(opcode, e) => write!(f, "{} in '{}'", e, opcode),
+            EpdError::Operand(opcode, operand) => write!(f, "invalid operand '{}' for '{}'", operand, opcode),
+            EpdError::Syntax(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for EpdError { }
+
+impl From<FenError> for EpdError {
+    fn from(e: FenError) -> Self {
+        EpdError::Fen(e)
+    }
+}
+
+/// The operands of an opcode, typed by the opcode they belong to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// `bm`, `am` and `pm` list alternatives, `pv` is a sequence of moves
+    Moves(Vec<Move>),
+    /// `id` and the comments `c0` to `c9`
+    Text(String),
+    /// `ce` in centipawns, and the analysis counters `acd`, `acn`, `acs`
+    Integer(i64),
+    /// any other opcode, kept as written
+    Raw(Vec<String>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OperandKind {
+    Moves,
+    Sequence,
+    Text,
+    Integer,
+    Raw,
+}
+
+fn operand_kind(opcode: &str) -> OperandKind {
+    match opcode {
+        "bm" | "am" | "pm" => OperandKind::Moves,
+        "pv" => OperandKind::Sequence,
+        "id" | "c0" | "c1" | "c2" | "c3" | "c4" | "c5" | "c6" | "c7" | "c8" | "c9" => OperandKind::Text,
+        "ce" | "acd" | "acn" | "acs" => OperandKind::Integer,
+        _ => OperandKind::Raw,
+    }
+}
+
+/// A position with its EPD operations
+#[derive(Clone)]
+pub struct Epd {
+    pub board: Board,
+    pub operations: BTreeMap<String, Operand>,
+}
+
+impl Epd {
+    pub fn new(board: Board) -> Self {
+        Self {
+            board,
+            operations: BTreeMap::new(),
+        }
+    }
+
+    fn moves(&self, opcode: &str) -> &[Move] {
+        match self.operations.get(opcode) {
+            Some(Operand::Moves(moves)) => moves,
+            _ => &[],
+        }
+    }
+
+    fn integer(&self, opcode: &str) -> Option<i64> {
+        match self.operations.get(opcode) {
+            Some(Operand::Integer(n)) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn text(&self, opcode: &str) -> Option<&str> {
+        match self.operations.get(opcode) {
+            Some(Operand::Text(text)) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn best_moves(&self) -> &[Move] {
+        self.moves("bm")
+    }
+
+    pub fn avoid_moves(&self) -> &[Move] {
+        self.moves("am")
+    }
+
+    pub fn pv(&self) -> &[Move] {
+        self.moves("pv")
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.text("id")
+    }
+
+    /// Returns comment `c0` to `c9`
+    pub fn comment(&self, n: u8) -> Option<&str> {
+        self.text(&format!("c{}", n))
+    }
+
+    pub fn centipawn_eval(&self) -> Option<Eval> {
+        self.integer("ce").map(|ce| ce as Eval)
+    }
+
+    pub fn analysis_depth(&self) -> Option<u32> {
+        self.integer("acd").map(|acd| acd as u32)
+    }
+
+    /// Returns true if `mv` satisfies the `bm` and `am` operations of a test position
+    pub fn is_solution(&self, mv: Move) -> bool {
+        (self.best_moves().is_empty() || self.best_moves().contains(&mv)) && !self.avoid_moves().contains(&mv)
+    }
+
+    /// Records the result of analysing the position
+    pub fn set_analysis(&mut self, score: Eval, depth: u8, nodes: u64, millis: u64, pv: &[Move]) {
+        self.operations.insert("ce".to_string(), Operand::Integer(score as i64));
+        self.operations.insert("acd".to_string(), Operand::Integer(depth as i64));
+        self.operations.insert("acn".to_string(), Operand::Integer(nodes as i64));
+        self.operations.insert("acs".to_string(), Operand::Integer((millis / 1000) as i64));
+        if !pv.is_empty() {
+            self.operations.insert("pv".to_string(), Operand::Moves(pv.to_vec()));
+        }
+    }
+}
+
+/// Splits the operation section into opcodes and their operands
+fn split_operations(text: &str) -> Result<Vec<(String, Vec<String>)>, EpdError> {
+    let mut operations = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() { }
+        if chars.peek().is_none() {
+            return Ok(operations);
+        }
+
+        let mut opcode = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != ';') {
+            opcode.push(c);
+        }
+        if opcode.is_empty() {
+            return Err(EpdError::Syntax("missing opcode before ';'".to_string()));
+        }
+
+        let mut operands = Vec::new();
+        loop {
+            match chars.next() {
+                Some(';') => break,
+                Some('"') => {
+                    let mut operand = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some(c) => operand.push(c),
+                            None => return Err(EpdError::Syntax(format!("unterminated string in '{}'", opcode))),
+                        }
+                    }
+                    operands.push(operand);
+                },
+                Some(c) if c.is_whitespace() => (),
+                Some(c) => {
+                    let mut operand = c.to_string();
+                    while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != ';') {
+                        operand.push(c);
+                    }
+                    operands.push(operand);
+                },
+                None => return Err(EpdError::Syntax(format!("missing ';' after '{}'", opcode))),
+            }
+        }
+        operations.push((opcode, operands));
+    }
+}
+
+/// Parses a move operand as SAN, falling back to coordinate notation
+fn parse_move(board: &Board, opcode: &str, text: &str) -> Result<Move, EpdError> {
+    board.parse_san(text).or_else(|e| {
+        Move::from_str(text).ok()
+            .filter(|mv| board.position.legal(*mv))
+            .ok_or_else(|| EpdError::Move(opcode.to_string(), e))
+    })
+}
+
+impl FromStr for Epd {
+    type Err = EpdError;
+
+    /// Parses an EPD record, also accepting move counters after the fourth field
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s.trim();
+        let mut fields = Vec::with_capacity(6);
+        while fields.len() < 6 {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let field = &rest[..end];
+            let is_counter = field.parse::<u16>().is_ok();
+            if field.is_empty() || (fields.len() >= 4 && !is_counter) {
+                break;
+            }
+            fields.push(field);
+            rest = rest[end..].trim_start();
+        }
+        if fields.len() < 4 {
+            return Err(EpdError::Fen(FenError::MissingFields(fields.len())));
+        }
+
+        let operations = split_operations(rest)?;
+        let mut fen = fields.join(" ");
+        if fields.len() == 4 {
+            let counter = |opcode: &str, default: &str| operations.iter()
+                .find(|(op, _)| op == opcode)
+                .and_then(|(_, operands)| operands.first().cloned())
+                .unwrap_or(default.to_string());
+            fen = format!("{} {} {}", fen, counter("hmvc", "0"), counter("fmvn", "1"));
+        }
+        let mut epd = Epd::new(Board::from_fen(&fen)?);
+
+        for (opcode, operands) in operations {
+            let operand = match operand_kind(&opcode) {
+                OperandKind::Moves => Operand::Moves(operands.iter()
+                    .map(|text| parse_move(&epd.board, &opcode, text))
+                    .collect::<Result<_, _>>()?),
+                OperandKind::Sequence => {
+                    let mut board = epd.board.clone();
+                    let mut moves = Vec::with_capacity(operands.len());
+                    for text in &operands {
+                        let mv = parse_move(&board, &opcode, text)?;
+                        board.make_move(mv);
+                        moves.push(mv);
+                    }
+                    Operand::Moves(moves)
+                },
+                OperandKind::Text => Operand::Text(operands.join(" ")),
+                OperandKind::Integer => match operands.as_slice() {
+                    [n] => Operand::Integer(n.parse().map_err(|_| EpdError::Operand(opcode.clone(), n.clone()))?),
+                    _ => return Err(EpdError::Operand(opcode, operands.join(" "))),
+                },
+                OperandKind::Raw => Operand::Raw(operands),
+            };
+            epd.operations.insert(opcode, operand);
+        }
+        Ok(epd)
+    }
+}
+
+impl fmt::Display for Epd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fen = self.board.to_fen();
+        let fields = fen.split_whitespace().take(4).collect::<Vec<&str>>();
+        write!(f, "{}", fields.join(" "))?;
+
+        // the move counters have no field of their own, so any but the defaults are kept as opcodes
+        let mut operations = self.operations.clone();
+        if self.board.halfmove_clock() != 0 {
+            operations.insert("hmvc".to_string(), Operand::Raw(vec![self.board.halfmove_clock().to_string()]));
+        }
+        if self.board.fullmove_number() != 1 {
+            operations.insert("fmvn".to_string(), Operand::Raw(vec![self.board.fullmove_number().to_string()]));
+        }
+
+        for (opcode, operand) in &operations {
+            write!(f, " {}", opcode)?;
+            match operand {
+                Operand::Moves(moves) if operand_kind(opcode) == OperandKind::Sequence => {
+                    let mut board = self.board.clone();
+                    for &mv in moves {
+                        write!(f, " {}", board.to_san(mv))?;
+                        board.make_move(mv);
+                    }
+                },
+                Operand::Moves(moves) => {
+                    for &mv in moves {
+                        write!(f, " {}", self.board.to_san(mv))?;
+                    }
+                },
+                Operand::Text(text) => write!(f, " \"{}\"", text)?,
+                Operand::Integer(n) => write!(f, " {}", n)?,
+                Operand::Raw(operands) => {
+                    for operand in operands {
+                        if operand.is_empty() || operand.contains(char::is_whitespace) || operand.contains(';') {
+                            write!(f, " \"{}\"", operand)?;
+                        } else {
+                            write!(f, " {}", operand)?;
+                        }
+                    }
+                },
+            }
+            write!(f, ";")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess::{Piece, Square};
+
+    #[test]
+    fn test_parse_test_positions() {
+        let epd = Epd::from_str("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\";").unwrap();
+        assert_eq!(epd.best_moves(), &[Move::new(Square::G3, Square::G6, None)]);
+        assert_eq!(epd.id(), Some("WAC.001"));
+        assert!(epd.is_solution(Move::new(Square::G3, Square::G6, None)));
+        assert!(!epd.is_solution(Move::new(Square::E5, Square::F7, None)));
+        assert_eq!(epd.board.to_fen(), "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1");
+
+        let epd = Epd::from_str("8/7p/5k2/5p2/p1p2P2/Pr1pPK2/1P1R3P/6r1 b - - am Rg3+ Rf1+; bm Rxb2; id \"WAC.002\";").unwrap();
+        assert_eq!(epd.avoid_moves().len(), 2);
+        assert!(epd.is_solution(Move::new(Square::B3, Square::B2, None)));
+        assert!(!epd.is_solution(Move::new(Square::G1, Square::G3, None)));
+    }
+
+    #[test]
+    fn test_parse_operations() {
+        let line = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - acd 12; c0 \"a; b\"; c9 \"last\"; ce -35; \
+            hmvc 4; fmvn 20; pv e4 e5 Nf3; xyz 1 \"two words\";";
+        let epd = Epd::from_str(line).unwrap();
+        assert_eq!(epd.analysis_depth(), Some(12));
+        assert_eq!(epd.centipawn_eval(), Some(-35));
+        assert_eq!(epd.comment(0), Some("a; b"));
+        assert_eq!(epd.comment(9), Some("last"));
+        assert_eq!(epd.comment(1), None);
+        assert_eq!(epd.pv(), &[
+            Move::new(Square::E2, Square::E4, None),
+            Move::new(Square::E7, Square::E5, None),
+            Move::new(Square::G1, Square::F3, None),
+        ]);
+        assert_eq!(epd.operations["xyz"], Operand::Raw(vec!["1".to_string(), "two words".to_string()]));
+        // move counters come from the hmvc and fmvn opcodes
+        assert_eq!(epd.board.halfmove_clock(), 4);
+        assert_eq!(epd.board.fullmove_number(), 20);
+
+        // written records read back identically
+        let written = epd.to_string();
+        assert_eq!(written, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - acd 12; c0 \"a; b\"; c9 \"last\"; \
+            ce -35; fmvn 20; hmvc 4; pv e4 e5 Nf3; xyz 1 \"two words\";");
+        assert_eq!(Epd::from_str(&written).unwrap().to_string(), written);
+    }
+
+    #[test]
+    fn test_full_fen_and_coordinate_moves() {
+        let epd = Epd::from_str("1n5k/P7/8/8/8/8/8/K7 w - - 3 40 bm a7b8q;").unwrap();
+        assert_eq!(epd.best_moves(), &[Move::new(Square::A7, Square::B8, Some(Piece::Queen))]);
+        assert_eq!(epd.board.fullmove_number(), 40);
+        assert_eq!(epd.to_string(), "1n5k/P7/8/8/8/8/8/K7 w - - bm axb8=Q+; fmvn 40; hmvc 3;");
+        assert_eq!(Epd::from_str(&epd.to_string()).unwrap().board.to_fen(), "1n5k/P7/8/8/8/8/8/K7 w - - 3 40");
+
+        let epd = Epd::from_str("1n5k/P7/8/8/8/8/8/K7 w - -").unwrap();
+        assert!(epd.operations.is_empty());
+    }
+
+    #[test]
+    fn test_write_analysis() {
+        let mut epd = Epd::new(Board::from_fen("6k1/8/R5K1/8/8/8/8/8 w - - 0 1").unwrap());
+        epd.operations.insert("id".to_string(), Operand::Text("mate in one".to_string()));
+        epd.set_analysis(49999, 3, 1200, 2500, &[Move::new(Square::A6, Square::A8, None)]);
+        assert_eq!(epd.to_string(), "6k1/8/R5K1/8/8/8/8/8 w - - acd 3; acn 1200; acs 2; ce 49999; id \"mate in one\"; pv Ra8#;");
+    }
+
+    #[test]
+    fn test_errors() {
+        let fen = "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - -";
+        assert!(matches!(Epd::from_str("2rr3k/pp3pp1 w"), Err(EpdError::Fen(FenError::MissingFields(2)))));
+        assert!(matches!(Epd::from_str(&format!("{} bm Qa8;", fen)), Err(EpdError::Move(..))));
+        assert!(matches!(Epd::from_str(&format!("{} pv Qg6 Qg6;", fen)), Err(EpdError::Move(..))));
+        assert!(matches!(Epd::from_str(&format!("{} acd twelve;", fen)), Err(EpdError::Operand(..))));
+        assert!(matches!(Epd::from_str(&format!("{} id \"WAC.001;", fen)), Err(EpdError::Syntax(_))));
+        assert!(matches!(Epd::from_str(&format!("{} bm Qg6", fen)), Err(EpdError::Syntax(_))));
+        assert!(matches!(Epd::from_str(&format!("{} ;", fen)), Err(EpdError::Syntax(_))));
+    }
+}
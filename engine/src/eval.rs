@@ -1,6 +1,8 @@
-use std::fmt;
+use std::{fmt, mem::size_of};
 
-use crate::board::Board;
+use chess::{Color, Piece};
+
+use crate::{board::Board, kpk};
 
 pub type Eval = i32;
 
@@ -185,6 +187,28 @@ const EG_TABLES: [[Eval; 64]; 6] = [
 
 const SIDE_MULT: [Eval; 2] = [1, -1];
 
+/// kept below the value of a promoted queen so the search still prefers promoting
+const KPK_WIN_VALUE: Eval = 500;
+const KPK_RANK_BONUS: Eval = 40;
+
+/// bonus for having the move
+const TEMPO_BONUS: Eval = 15;
+
+/// upper bound on everything `evaluate` adds on top of material and PSTs
+///
+/// that is only the tempo bonus so far; every new term has to widen it by its largest value
+const LAZY_MARGIN: Eval = TEMPO_BONUS;
+
+/// the score at which the win rate model expects half the games to be won
+///
+/// this and `WDL_SCALE` are fitted by `tools wdl` to the 3000 self-play games in `tools/res/testresults`
+/// (60+0.1, searches of depth 3 to 5). Most of those games were lost on time, and the rest end in mates the
+/// searches didn't see coming, so the curve is flat and predicts few draws; without the time forfeits the
+/// fit doesn't settle at all. `UCI_ShowWDL` isn't offered until a refit on games that end normally settles
+pub const WDL_EVEN_SCORE: f64 = 39.0;
+/// centipawns it takes for the odds of a win to change by a factor of e
+pub const WDL_SCALE: f64 = 629.0;
+
 #[derive(Clone, Copy)]
 struct PhasedEval {
     mg: Eval,
@@ -227,11 +251,56 @@ const fn construct_psts() -> [[PhasedEval; 64]; 12] {
 }
 
 pub fn evaluate(board: &Board) -> Eval {
+    let pos = board.position;
+    if is_kpk(&pos) {
+        return evaluate_kpk(&pos);
+    }
+
+    evaluate_psts(&pos) + TEMPO_BONUS
+}
+
+/// Evaluates the position, but returns the material and PST score alone when it is
+/// far enough outside `(alpha, beta)` that the remaining terms can't bring it back
+///
+/// the returned flag is false when the score is only an estimate and must not be cached
+pub fn evaluate_lazy(board: &Board, alpha: Eval, beta: Eval) -> (Eval, bool) {
+    let pos = board.position;
+    if is_kpk(&pos) {
+        return (evaluate_kpk(&pos), true);
+    }
+
+    let fast = evaluate_psts(&pos);
+    if fast.saturating_add(LAZY_MARGIN) <= alpha || fast.saturating_sub(LAZY_MARGIN) >= beta {
+        return (fast, false);
+    }
+
+    (evaluate(board), true)
+}
+
+/// Maps a score to the expected win, draw and loss rates per mille, from the side to move's perspective
+///
+/// wins and losses each follow a logistic curve in the score, draws take the rest
+pub fn wdl(score: Eval) -> (u32, u32, u32) {
+    let per_mille = |score: Eval| (1000.0 * win_rate(score as f64, WDL_EVEN_SCORE, WDL_SCALE)).round() as u32;
+    let (win, loss) = (per_mille(score), per_mille(-score));
+    (win, 1000 - win - loss, loss)
+}
+
+/// The win rate model behind `wdl`, as a probability, for any choice of its parameters
+pub fn win_rate(score: f64, even_score: f64, scale: f64) -> f64 {
+    1.0 / (1.0 + ((even_score - score) / scale).exp())
+}
+
+fn is_kpk(pos: &chess::Board) -> bool {
+    pos.combined().popcnt() == 3 && pos.pieces(Piece::Pawn).popcnt() == 1
+}
+
+/// Tapered material and piece square table score from the side to move's perspective
+fn evaluate_psts(pos: &chess::Board) -> Eval {
     let mut mg = 0;
     let mut eg = 0;
     let mut phase = 0;
 
-    let pos = board.position;
     for sq in *pos.combined() {
         let piece = pos.piece_on(sq).unwrap().to_index();
         let col = pos.color_on(sq).unwrap().to_index();
@@ -244,3 +313,172 @@ pub fn evaluate(board: &Board) -> Eval {
 
     ((mg * phase + eg * (MAX_GAME_PHASE - phase)) / MAX_GAME_PHASE) * SIDE_MULT[pos.side_to_move().to_index()]
 }
+
+/// Scores king + pawn vs king exactly using the bitbase
+fn evaluate_kpk(pos: &chess::Board) -> Eval {
+    if !kpk::probe(pos) {
+        return 0;
+    }
+
+    let pawn_sq = pos.pieces(Piece::Pawn).to_square();
+    let strong = pos.color_on(pawn_sq).unwrap();
+    let rank = match strong {
+        Color::White => pawn_sq.get_rank().to_index(),
+        Color::Black => 7 - pawn_sq.get_rank().to_index(),
+    };
+    let score = KPK_WIN_VALUE + KPK_RANK_BONUS * rank as Eval;
+
+    if pos.side_to_move() == strong { score } else { -score }
+}
+
+#[derive(Clone, Copy, Default)]
+struct CacheEntry {
+    key: u64,
+    score: Eval,
+}
+
+/// Small always-replace table of static evaluations, keyed by position hash
+#[derive(Clone)]
+pub struct EvalCache {
+    entries: Vec<CacheEntry>,
+}
+
+impl Default for EvalCache {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_SIZE)
+    }
+}
+
+impl EvalCache {
+    pub const DEFAULT_SIZE: usize = 1;
+    pub const ENTRY_SIZE: usize = size_of::<CacheEntry>();
+
+    fn index(&self, key: u64) -> usize {
+        (key as usize) & (self.entries.len() - 1)
+    }
+
+    /// Creates a cache of at most `size_mib` MiB, rounded down to a power of two entries
+    pub fn new(size_mib: usize) -> Self {
+        let entry_count = ((size_mib << 20) / Self::ENTRY_SIZE).max(1);
+        let entry_count = 1 << entry_count.ilog2();
+        Self {
+            entries: vec![CacheEntry::default(); entry_count],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|e| *e = CacheEntry::default());
+    }
+
+    pub fn get(&self, key: u64) -> Option<Eval> {
+        let entry = self.entries[self.index(key)];
+        (entry.key == key).then_some(entry.score)
+    }
+
+    pub fn insert(&mut self, key: u64, score: Eval) {
+        let index = self.index(key);
+        self.entries[index] = CacheEntry { key, score };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess::MoveGen;
+
+    /// Positions from deterministic random playouts of the start position and a middlegame
+    fn sample_positions() -> Vec<Board> {
+        let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut positions = Vec::new();
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N2N2/PP2BPPP/R2QKB1R w KQ - 0 9",
+        ] {
+            for _ in 0..20 {
+                let mut board = Board::from_fen(fen).unwrap();
+                for _ in 0..40 {
+                    let moves: Vec<_> = MoveGen::new_legal(&board.position).collect();
+                    if moves.is_empty() {
+                        break;
+                    }
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    board.make_move(moves[seed as usize % moves.len()]);
+                    positions.push(board.clone());
+                }
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn test_eval_cache() {
+        let mut cache = EvalCache::new(1);
+        assert_eq!(cache.entries.len().count_ones(), 1);
+
+        let positions = sample_positions();
+        for board in &positions {
+            let score = cache.get(board.hash()).unwrap_or_else(|| evaluate(board));
+            assert_eq!(score, evaluate(board), "{}", board);
+            cache.insert(board.hash(), score);
+        }
+        for board in &positions {
+            if let Some(score) = cache.get(board.hash()) {
+                assert_eq!(score, evaluate(board), "{}", board);
+            }
+        }
+
+        cache.clear();
+        assert!(positions.iter().all(|board| cache.get(board.hash()).is_none()));
+    }
+
+    #[test]
+    fn test_tempo() {
+        // passing the move flips the sign of everything except the tempo bonus
+        let mut checked = 0;
+        for board in sample_positions() {
+            let Some(passed) = board.position.null_move() else { continue };
+            if is_kpk(&passed) {
+                continue;
+            }
+            let passed = Board::from_fen(&passed.to_string()).unwrap();
+            assert_eq!(evaluate(&board) + evaluate(&passed), 2 * TEMPO_BONUS, "{}", board);
+            checked += 1;
+        }
+        assert!(checked > 100);
+    }
+
+    #[test]
+    fn test_lazy_eval() {
+        for board in sample_positions() {
+            let full = evaluate(&board);
+            for (alpha, beta) in [(-50, 50), (-1000, -600), (600, 1000), (full - 1, full + 1), (full - 20, full - 10), (full + 10, full + 20), (i32::MIN + 1, i32::MAX)] {
+                let (lazy, exact) = evaluate_lazy(&board, alpha, beta);
+                if exact {
+                    assert_eq!(lazy, full, "{}", board);
+                } else {
+                    // an estimate is only returned when the full score falls on the same side of the window
+                    assert!((lazy <= alpha && full <= alpha) || (lazy >= beta && full >= beta), "{}", board);
+                }
+                if alpha < full && full < beta {
+                    assert!(exact, "{}", board);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_wdl() {
+        assert_eq!(wdl(0), (485, 30, 485));
+        assert_eq!(wdl(39), (500, 31, 469));
+        assert_eq!(wdl(-39), (469, 31, 500));
+        assert_eq!(wdl(50000), (1000, 0, 0));
+        assert_eq!(wdl(-50000), (0, 0, 1000));
+        for score in -1000..1000 {
+            let (win, draw, loss) = wdl(score);
+            assert_eq!(win + draw + loss, 1000);
+            assert!(win <= wdl(score + 1).0 && loss >= wdl(score + 1).2);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::sync::OnceLock;
+
+use chess::{BitBoard, CastleRights, ChessMove, Color, File, MoveGen, Piece, Rank, Square, EMPTY};
+
+/// side to move (2) * strong king (64) * weak king (64) * pawn (files a-d, ranks 2-7)
+const NUM_POSITIONS: usize = 2 * 64 * 64 * 24;
+
+/// marks a black position which can reach a draw immediately (pawn capture, stalemate)
+const HAS_DRAW: u8 = u8::MAX;
+
+static BITBASE: OnceLock<Vec<u64>> = OnceLock::new();
+
+/// Builds the bitbase if it hasn't been built already
+pub fn init() {
+    BITBASE.get_or_init(generate);
+}
+
+/// Returns true if the side with the pawn wins the given king + pawn vs king position
+///
+/// *must* only be called on positions containing exactly two kings and one pawn
+pub fn probe(pos: &chess::Board) -> bool {
+    let bits = BITBASE.get_or_init(generate);
+    let idx = normalized_index(pos);
+    bits[idx / 64] & (1 << (idx % 64)) != 0
+}
+
+/// Returns the bitbase index of a position after mapping it so that white holds
+/// the pawn and the pawn is on files a-d
+fn normalized_index(pos: &chess::Board) -> usize {
+    let pawn_sq = pos.pieces(Piece::Pawn).to_square();
+    let strong = pos.color_on(pawn_sq).unwrap();
+
+    let mut flip = if strong == Color::White { 0 } else { 56 };
+    if pawn_sq.get_file() > File::D {
+        flip ^= 7;
+    }
+    let map = |sq: Square| Square::new(sq.to_int() ^ flip);
+
+    index(
+        if pos.side_to_move() == strong { Color::White } else { Color::Black },
+        map(pos.king_square(strong)),
+        map(pos.king_square(!strong)),
+        map(pawn_sq),
+    )
+}
+
+fn index(stm: Color, strong_king: Square, weak_king: Square, pawn: Square) -> usize {
+    let pawn_idx = (pawn.get_rank().to_index() - 1) * 4 + pawn.get_file().to_index();
+    stm.to_index() | (strong_king.to_index() << 1) | (weak_king.to_index() << 7) | (pawn_idx << 13)
+}
+
+fn decode(idx: usize) -> (Color, Square, Square, Square) {
+    let stm = if idx & 1 == 0 { Color::White } else { Color::Black };
+    let strong_king = Square::new(((idx >> 1) & 63) as u8);
+    let weak_king = Square::new(((idx >> 7) & 63) as u8);
+    let pawn_idx = idx >> 13;
+    let pawn = Square::make_square(Rank::from_index(pawn_idx / 4 + 1), File::from_index(pawn_idx % 4));
+    (stm, strong_king, weak_king, pawn)
+}
+
+/// Builds the position for an index, or `None` if the index is not a legal position
+fn position(idx: usize) -> Option<chess::Board> {
+    let (stm, strong_king, weak_king, pawn) = decode(idx);
+    if strong_king == weak_king || strong_king == pawn || weak_king == pawn {
+        return None;
+    }
+    let pieces = [
+        (strong_king, Piece::King, Color::White),
+        (weak_king, Piece::King, Color::Black),
+        (pawn, Piece::Pawn, Color::White),
+    ];
+    let builder = chess::BoardBuilder::setup(&pieces, stm, CastleRights::NoRights, CastleRights::NoRights, None);
+    chess::Board::try_from(builder).ok()
+}
+
+/// Returns true if a promotion can't be refuted by capturing the new piece or by stalemate
+fn is_winning_promotion(pos: &chess::Board, mv: ChessMove) -> bool {
+    let next = pos.make_move_new(mv);
+    let mut replies = MoveGen::new_legal(&next);
+    if replies.len() == 0 {
+        return *next.checkers() != EMPTY;
+    }
+    replies.all(|reply| reply.get_dest() != mv.get_dest())
+}
+
+/// Solves every king + pawn vs king position by retrograde analysis
+///
+/// Wins are seeded from positions where white can safely promote (or black is mated),
+/// then propagated backwards: a white to move position is won if any move reaches a win,
+/// a black to move position is won once every move reaches a win. Anything left is a draw.
+fn generate() -> Vec<u64> {
+    let mut win = vec![false; NUM_POSITIONS];
+    let mut remaining = vec![0u8; NUM_POSITIONS];
+    let mut edges: Vec<(u32, u32)> = Vec::with_capacity(NUM_POSITIONS * 6);
+    let mut queue = Vec::new();
+
+    for idx in 0..NUM_POSITIONS {
+        let Some(pos) = position(idx) else { continue };
+        let moves = MoveGen::new_legal(&pos);
+
+        if pos.side_to_move() == Color::White {
+            for mv in moves {
+                match mv.get_promotion() {
+                    Some(Piece::Queen) | Some(Piece::Rook) => {
+                        if !win[idx] && is_winning_promotion(&pos, mv) {
+                            win[idx] = true;
+                            queue.push(idx);
+                        }
+                    },
+                    Some(_) => (),
+                    None => {
+                        let next = pos.make_move_new(mv);
+                        edges.push((idx as u32, normalized_index(&next) as u32));
+                    },
+                }
+            }
+        } else {
+            if moves.len() == 0 {
+                if *pos.checkers() != EMPTY {
+                    win[idx] = true;
+                    queue.push(idx);
+                } else {
+                    remaining[idx] = HAS_DRAW;
+                }
+                continue;
+            }
+            let pawn = *pos.pieces(Piece::Pawn);
+            for mv in moves {
+                if BitBoard::from_square(mv.get_dest()) & pawn != EMPTY {
+                    remaining[idx] = HAS_DRAW;
+                    break;
+                }
+                remaining[idx] += 1;
+                let next = pos.make_move_new(mv);
+                edges.push((idx as u32, normalized_index(&next) as u32));
+            }
+        }
+    }
+
+    // predecessor lists in compressed form, indexed by the position each edge leads to
+    let mut offsets = vec![0u32; NUM_POSITIONS + 1];
+    for &(_, to) in &edges {
+        offsets[to as usize + 1] += 1;
+    }
+    for i in 0..NUM_POSITIONS {
+        offsets[i + 1] += offsets[i];
+    }
+    let mut fill = offsets.clone();
+    let mut predecessors = vec![0u32; edges.len()];
+    for &(from, to) in &edges {
+        predecessors[fill[to as usize] as usize] = from;
+        fill[to as usize] += 1;
+    }
+    drop(edges);
+
+    while let Some(idx) = queue.pop() {
+        for &pred in &predecessors[offsets[idx] as usize..offsets[idx + 1] as usize] {
+            let pred = pred as usize;
+            if win[pred] || remaining[pred] == HAS_DRAW {
+                continue;
+            }
+            if pred & 1 == 1 {
+                remaining[pred] -= 1;
+                if remaining[pred] > 0 {
+                    continue;
+                }
+            }
+            win[pred] = true;
+            queue.push(pred);
+        }
+    }
+
+    let mut bits = vec![0u64; NUM_POSITIONS / 64];
+    for (idx, _) in win.iter().enumerate().filter(|(_, &w)| w) {
+        bits[idx / 64] |= 1 << (idx % 64);
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use crate::{board::Board, eval::evaluate};
+
+    fn probe_fen(fen: &str) -> bool {
+        probe(&chess::Board::from_str(fen).unwrap())
+    }
+
+    #[test]
+    fn test_kpk_known_positions() {
+        // king in front of the pawn on the sixth rank
+        assert!(probe_fen("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"));
+        // pawn outruns the king
+        assert!(probe_fen("k7/8/8/8/8/8/7P/K7 w - - 0 1"));
+        // same positions with colours reversed
+        assert!(probe_fen("8/8/8/8/4p3/4k3/8/4K3 b - - 0 1"));
+        assert!(probe_fen("k7/p7/8/8/8/8/8/7K b - - 0 1"));
+
+        // opposition decides the key squares
+        assert!(probe_fen("8/8/4k3/8/4K3/4P3/8/8 b - - 0 1"));
+        assert!(!probe_fen("8/8/4k3/8/4K3/4P3/8/8 w - - 0 1"));
+        // rook pawn with the defending king in the corner
+        assert!(!probe_fen("7k/8/8/8/8/8/7P/7K w - - 0 1"));
+        // pawn is captured
+        assert!(!probe_fen("8/8/8/8/8/8/3kP3/7K b - - 0 1"));
+        // stalemate
+        assert!(!probe_fen("k7/P7/1K6/8/8/8/8/8 b - - 0 1"));
+    }
+
+    #[test]
+    fn test_kpk_evaluate() {
+        assert_eq!(evaluate(&Board::from_fen("8/8/4k3/8/4K3/4P3/8/8 w - - 0 1").unwrap()), 0);
+        assert!(evaluate(&Board::from_fen("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").unwrap()) > 0);
+        assert!(evaluate(&Board::from_fen("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1").unwrap()) < 0);
+        assert!(evaluate(&Board::from_fen("8/8/8/8/4p3/4k3/8/4K3 b - - 0 1").unwrap()) > 0);
+    }
+
+    /// Solves every position reachable from `roots` by repeatedly searching the full
+    /// game graph, without the symmetry and indexing used by the bitbase
+    fn brute_force(roots: &[chess::Board]) -> HashMap<u64, (chess::Board, bool)> {
+        enum Node { Open(Vec<u64>), Won, Drawn }
+
+        let mut nodes: HashMap<u64, (chess::Board, Node)> = HashMap::new();
+        let mut stack = roots.to_vec();
+        while let Some(pos) = stack.pop() {
+            if nodes.contains_key(&pos.get_hash()) {
+                continue;
+            }
+            let strong = pos.color_on(pos.pieces(Piece::Pawn).to_square()).unwrap();
+            let attacking = pos.side_to_move() == strong;
+            let mut children = Vec::new();
+            let mut node = None;
+            for mv in MoveGen::new_legal(&pos) {
+                if mv.get_promotion().is_some() {
+                    if is_winning_promotion(&pos, mv) {
+                        node = Some(Node::Won);
+                    }
+                    continue;
+                }
+                if pos.piece_on(mv.get_dest()).is_some() {
+                    node = Some(Node::Drawn);
+                    break;
+                }
+                let next = pos.make_move_new(mv);
+                children.push(next.get_hash());
+                stack.push(next);
+            }
+            let node = node.unwrap_or_else(|| {
+                if children.is_empty() {
+                    if *pos.checkers() != EMPTY && !attacking { Node::Won } else { Node::Drawn }
+                } else {
+                    Node::Open(children)
+                }
+            });
+            nodes.insert(pos.get_hash(), (pos, node));
+        }
+
+        let mut won: HashMap<u64, bool> = nodes.iter()
+            .map(|(&hash, (_, node))| (hash, matches!(node, Node::Won)))
+            .collect();
+        loop {
+            let mut changed = false;
+            for (hash, (pos, node)) in &nodes {
+                let Node::Open(children) = node else { continue };
+                if won[hash] {
+                    continue;
+                }
+                let strong = pos.color_on(pos.pieces(Piece::Pawn).to_square()).unwrap();
+                let result = if pos.side_to_move() == strong {
+                    children.iter().any(|child| won[child])
+                } else {
+                    children.iter().all(|child| won[child])
+                };
+                if result {
+                    won.insert(*hash, true);
+                    changed = true;
+                }
+            }
+            if !changed { break; }
+        }
+
+        nodes.into_iter().map(|(hash, (pos, _))| (hash, (pos, won[&hash]))).collect()
+    }
+
+    #[test]
+    fn test_kpk_brute_force() {
+        // deterministic sample of start positions with the pawn on the fifth rank or higher
+        let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut roots = Vec::new();
+        while roots.len() < 24 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let idx = (seed as usize) % NUM_POSITIONS;
+            let (_, _, _, pawn) = decode(idx);
+            if pawn.get_rank() < Rank::Fifth {
+                continue;
+            }
+            if let Some(pos) = position(idx) {
+                roots.push(pos);
+            }
+        }
+        // colour reversed and mirrored samples exercise the normalization
+        let reversed = ["8/8/8/3k4/1p6/8/8/2K5 b - - 0 1", "8/8/8/8/8/4k1p1/8/6K1 w - - 0 1"];
+        roots.extend(reversed.iter().map(|fen| chess::Board::from_str(fen).unwrap()));
+
+        let solved = brute_force(&roots);
+        assert!(solved.len() > 10_000);
+        for (pos, won) in solved.values() {
+            assert_eq!(probe(pos), *won, "{}", pos);
+        }
+    }
+}
@@ -0,0 +1,18 @@
+pub mod uci;
+
+pub mod board;
+pub mod chess960;
+pub mod material;
+pub mod adjudication;
+pub mod san;
+pub mod pgn;
+pub mod epd;
+pub mod search;
+pub mod timeman;
+pub mod eval;
+pub mod tt;
+pub mod perft;
+pub mod kpk;
+pub mod tablebase;
+pub mod syzygy;
+pub mod polyglot;
@@ -1,11 +1,6 @@
-mod uci;
-
-mod board;
-mod search;
-mod eval;
-mod tt;
-mod perft;
+use engine::{kpk, uci};
 
 fn main() {
+    kpk::init();
     uci::run_uci();
 }
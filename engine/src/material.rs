@@ -0,0 +1,104 @@
+use chess::{BitBoard, Color, EMPTY, Piece};
+
+use crate::board::Board;
+
+const LIGHT_SQUARES: BitBoard = BitBoard(0x55AA_55AA_55AA_55AA);
+const DARK_SQUARES: BitBoard = BitBoard(0xAA55_AA55_AA55_AA55);
+
+/// How drawn a position is by the material on the board alone
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MaterialDraw {
+    /// neither side can checkmate by any sequence of legal moves, a dead position under FIDE rules
+    ///
+    /// king vs king, king + knight vs king, and kings with any bishops all on one square colour
+    Dead,
+    /// checkmate is possible, but only if the defending side helps
+    ///
+    /// king + two knights vs king, and at most one minor piece each
+    NoForcedWin,
+}
+
+impl Board {
+    /// Classifies the position by its material signature
+    ///
+    /// positions with pawns, rooks or queens are never classified, even if the pawns are blocked
+    pub fn material_draw(&self) -> Option<MaterialDraw> {
+        let position = &self.position;
+        let heavy = *position.pieces(Piece::Pawn) | *position.pieces(Piece::Rook) | *position.pieces(Piece::Queen);
+        if heavy != EMPTY {
+            return None;
+        }
+
+        let knights = *position.pieces(Piece::Knight);
+        let bishops = *position.pieces(Piece::Bishop);
+
+        // bishops on one square colour never attack the other, so they can't take away a king's escape squares
+        if knights == EMPTY && ((bishops & LIGHT_SQUARES == EMPTY) || (bishops & DARK_SQUARES == EMPTY)) {
+            return Some(MaterialDraw::Dead);
+        }
+        if bishops == EMPTY && knights.popcnt() == 1 {
+            return Some(MaterialDraw::Dead);
+        }
+
+        let minors = knights | bishops;
+        let white = (minors & *position.color_combined(Color::White)).popcnt();
+        let black = (minors & *position.color_combined(Color::Black)).popcnt();
+        if white <= 1 && black <= 1 {
+            return Some(MaterialDraw::NoForcedWin);
+        }
+        // two knights can't force mate against a bare king
+        if bishops == EMPTY && knights.popcnt() == 2 && (white == 0 || black == 0) {
+            return Some(MaterialDraw::NoForcedWin);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(fen: &str) -> Option<MaterialDraw> {
+        Board::from_fen(fen).unwrap().material_draw()
+    }
+
+    #[test]
+    fn test_dead_positions() {
+        // king vs king
+        assert_eq!(classify("K6k/8/8/8/8/8/8/8 w - - 0 1"), Some(MaterialDraw::Dead));
+        // king + knight vs king
+        assert_eq!(classify("4KN1k/8/8/8/8/8/8/8 w - - 0 1"), Some(MaterialDraw::Dead));
+        // king + bishop vs king
+        assert_eq!(classify("k7/8/8/8/8/8/8/5B1K b - - 0 1"), Some(MaterialDraw::Dead));
+        // bishops of the same colour on both sides
+        assert_eq!(classify("B1B1BK1k/8/8/8/8/8/8/8 w - - 0 1"), Some(MaterialDraw::Dead));
+        assert_eq!(classify("k7/8/8/3b4/8/8/8/5B1K w - - 0 1"), Some(MaterialDraw::Dead));
+    }
+
+    #[test]
+    fn test_no_forced_win() {
+        // king + two knights vs king
+        assert_eq!(classify("3KNN1k/8/8/8/8/8/8/8 w - - 0 1"), Some(MaterialDraw::NoForcedWin));
+        // bishops of opposite colours
+        assert_eq!(classify("k7/8/8/4b3/8/8/8/5B1K w - - 0 1"), Some(MaterialDraw::NoForcedWin));
+        // king + knight vs king + bishop
+        assert_eq!(classify("k7/8/8/3b4/8/8/8/5N1K w - - 0 1"), Some(MaterialDraw::NoForcedWin));
+        // king + knight vs king + knight
+        assert_eq!(classify("kn6/8/8/8/8/8/8/5N1K w - - 0 1"), Some(MaterialDraw::NoForcedWin));
+    }
+
+    #[test]
+    fn test_winnable_material() {
+        // king + bishops of opposite colours vs king
+        assert_eq!(classify("3KBB1k/8/8/8/8/8/8/8 w - - 0 1"), None);
+        // king + bishop + knight vs king
+        assert_eq!(classify("k7/8/8/8/8/8/8/4BN1K w - - 0 1"), None);
+        // king + two knights vs king + knight
+        assert_eq!(classify("kn6/8/8/8/8/8/8/4NN1K w - - 0 1"), None);
+        // any pawn, rook or queen
+        assert_eq!(classify("k7/8/8/8/8/8/P7/7K w - - 0 1"), None);
+        assert_eq!(classify("k7/8/8/8/8/8/8/6RK w - - 0 1"), None);
+        assert_eq!(classify("k7/8/8/8/8/8/8/6QK w - - 0 1"), None);
+    }
+}
@@ -32,7 +32,7 @@ mod tests {
 
     #[test]
     fn test_perft_position_2() {
-        let mut board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let mut board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
         assert_eq!(perft(&mut board, 1), 48);
         assert_eq!(perft(&mut board, 2), 2039);
         assert_eq!(perft(&mut board, 3), 97862);
@@ -41,7 +41,7 @@ mod tests {
 
     #[test]
     fn test_perft_position_3() {
-        let mut board = Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1");
+        let mut board = Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
         assert_eq!(perft(&mut board, 1), 14);
         assert_eq!(perft(&mut board, 2), 191);
         assert_eq!(perft(&mut board, 3), 2812);
@@ -50,10 +50,47 @@ mod tests {
 
     #[test]
     fn test_perft_position_4() {
-        let mut board = Board::from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1");
+        let mut board = Board::from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1").unwrap();
         assert_eq!(perft(&mut board, 1), 6);
         assert_eq!(perft(&mut board, 2), 264);
         assert_eq!(perft(&mut board, 3), 9467);
         assert_eq!(perft(&mut board, 4), 422333);
     }
+
+    #[test]
+    fn test_perft_chess960() {
+        // reference positions from the Chess960 perft results, in Shredder-FEN
+        let positions: [(&str, [usize; 4]); 6] = [
+            ("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", [21, 528, 12189, 326672]),
+            ("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", [21, 807, 18002, 667366]),
+            ("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", [20, 479, 10471, 273318]),
+            ("qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9", [22, 593, 13440, 382958]),
+            ("1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9", [28, 1120, 31058, 1171749]),
+            ("qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9", [29, 899, 26578, 824055]),
+        ];
+        for (fen, counts) in positions {
+            let mut board = Board::from_fen(fen).unwrap();
+            assert!(board.is_chess960());
+            for (depth, count) in counts.into_iter().enumerate() {
+                assert_eq!(perft(&mut board, depth as u8 + 1), count, "{} depth {}", fen, depth + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_perft_chess960_standard_positions() {
+        // Chess960 castling must agree with standard castling when the king and rooks start in place
+        let mut board = Board::new();
+        board.enable_chess960();
+        assert_eq!(perft(&mut board, 4), 197281);
+
+        let mut board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        board.enable_chess960();
+        assert_eq!(perft(&mut board, 3), 97862);
+        assert_eq!(perft(&mut board, 4), 4085603);
+
+        let mut board = Board::from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1").unwrap();
+        board.enable_chess960();
+        assert_eq!(perft(&mut board, 4), 422333);
+    }
 }
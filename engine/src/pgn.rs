@@ -0,0 +1,705 @@
+use std::fmt;
+use std::io::BufRead;
+use std::str::FromStr;
+
+use crate::{board::{Board, Move}, eval::Eval};
+
+pub use crate::board::GameResult;
+
+/// Column at which movetext is wrapped when writing
+const LINE_WIDTH: usize = 80;
+
+/// Characters that end a symbol token
+const DELIMITERS: &str = "[]{}();$";
+
+/// A malformed game, with the line of the input it was found on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PgnError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for PgnError { }
+
+/// A position in the game tree
+#[derive(Clone)]
+pub struct Node {
+    /// the position after `mv` is played
+    pub board: Board,
+    /// the move leading to this position, `None` for the starting position
+    pub mv: Option<Move>,
+    pub nags: Vec<u8>,
+    /// comment written before the move
+    pub starting_comment: Option<String>,
+    /// comment written after the move
+    pub comment: Option<String>,
+    /// the main continuation first, followed by any variations
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    fn new(board: Board, mv: Option<Move>) -> Self {
+        Self {
+            board,
+            mv,
+            nags: Vec::new(),
+            starting_comment: None,
+            comment: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// Returns the score and depth of an engine comment such as `{+1.79/3 13s}`
+    pub fn engine_score(&self) -> Option<(Eval, u8)> {
+        let comment = self.comment.as_deref()?;
+        let (score, rest) = comment.split_once('/')?;
+        let depth = rest.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()?;
+        let pawns = score.trim().parse::<f32>().ok()?;
+        Some(((pawns * 100.0).round() as Eval, depth))
+    }
+}
+
+/// Formats a search result as an engine comment, e.g. `+1.79/3`
+pub fn score_comment(score: Eval, depth: u8) -> String {
+    format!("{:+.2}/{}", score as f32 / 100.0, depth)
+}
+
+#[derive(Clone)]
+pub struct Game {
+    /// tag pairs in the order they were read
+    pub tags: Vec<(String, String)>,
+    pub root: Node,
+    pub result: GameResult,
+}
+
+impl Game {
+    /// Returns a game with the seven tag roster starting from the given position
+    pub fn new(start: Board) -> Self {
+        let mut tags = ["Event", "Site", "Date", "Round", "White", "Black", "Result"]
+            .iter()
+            .map(|name| (name.to_string(), "?".to_string()))
+            .collect::<Vec<_>>();
+        tags[2].1 = "????.??.??".to_string();
+        tags[6].1 = GameResult::Ongoing.to_string();
+
+        let fen = start.to_fen();
+        let mut game = Self {
+            tags,
+            root: Node::new(start, None),
+            result: GameResult::Ongoing,
+        };
+        if fen != Board::new().to_fen() {
+            game.set_tag("SetUp", "1");
+            game.set_tag("FEN", &fen);
+        }
+        game
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn set_result(&mut self, result: GameResult) {
+        self.result = result;
+        self.set_tag("Result", result.as_str());
+    }
+
+    /// Returns the nodes of the main line, excluding the starting position
+    pub fn mainline(&self) -> impl Iterator<Item = &Node> {
+        std::iter::successors(self.root.children.first(), |node| node.children.first())
+    }
+
+    pub fn mainline_moves(&self) -> Vec<Move> {
+        self.mainline().filter_map(|node| node.mv).collect()
+    }
+
+    /// Returns the position at the end of the main line
+    pub fn board(&self) -> &Board {
+        self.mainline().last().map_or(&self.root.board, |node| &node.board)
+    }
+
+    /// Plays a move at the end of the main line, with an optional comment such as an engine score
+    pub fn push(&mut self, mv: Move, comment: Option<String>) {
+        let mut last = &mut self.root;
+        while !last.children.is_empty() {
+            last = &mut last.children[0];
+        }
+        let mut board = last.board.clone();
+        board.make_move(mv);
+        let mut node = Node::new(board, Some(mv));
+        node.comment = comment;
+        last.children.push(node);
+    }
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.tags {
+            writeln!(f, "[{} \"{}\"]", name, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+        }
+        writeln!(f)?;
+
+        let mut tokens = Vec::new();
+        if let Some(comment) = &self.root.comment {
+            tokens.push(format!("{{{}}}", comment));
+        }
+        write_line(&mut tokens, &self.root.children, &self.root.board, true);
+        tokens.push(self.result.to_string());
+
+        let mut line_len = 0;
+        let mut after_open = false;
+        for token in tokens {
+            // parentheses are written against the moves they enclose
+            let glued = after_open || token == ")";
+            if !glued && line_len > 0 {
+                if line_len + 1 + token.len() > LINE_WIDTH {
+                    writeln!(f)?;
+                    line_len = 0;
+                } else {
+                    write!(f, " ")?;
+                    line_len += 1;
+                }
+            }
+            write!(f, "{}", token)?;
+            line_len += token.len();
+            after_open = token == "(";
+        }
+        writeln!(f)
+    }
+}
+
+/// Writes a line of play starting with `siblings`, the moves available from `parent`
+fn write_line(tokens: &mut Vec<String>, siblings: &[Node], parent: &Board, mut force_number: bool) {
+    let mut parent = parent;
+    let mut siblings = siblings;
+    while let Some(main) = siblings.first() {
+        write_move(tokens, main, parent, force_number);
+        force_number = main.comment.is_some();
+
+        for variation in &siblings[1..] {
+            tokens.push("(".to_string());
+            write_move(tokens, variation, parent, true);
+            write_line(tokens, &variation.children, &variation.board, variation.comment.is_some());
+            tokens.push(")".to_string());
+            force_number = true;
+        }
+
+        parent = &main.board;
+        siblings = &main.children;
+    }
+}
+
+fn write_move(tokens: &mut Vec<String>, node: &Node, parent: &Board, force_number: bool) {
+    let Some(mv) = node.mv else { return };
+    if let Some(comment) = &node.starting_comment {
+        tokens.push(format!("{{{}}}", comment));
+    }
+    let white = parent.side_to_move() == chess::Color::White;
+    let san = parent.to_san(mv);
+    if white {
+        tokens.push(format!("{}. {}", parent.fullmove_number(), san));
+    } else if force_number || node.starting_comment.is_some() {
+        tokens.push(format!("{}... {}", parent.fullmove_number(), san));
+    } else {
+        tokens.push(san);
+    }
+    tokens.extend(node.nags.iter().map(|nag| format!("${}", nag)));
+    if let Some(comment) = &node.comment {
+        tokens.push(format!("{{{}}}", comment));
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Open,
+    Close,
+    Nag(u8),
+    San(String),
+    Result(GameResult),
+}
+
+/// A line of play read from movetext
+struct Line {
+    /// the possible first moves, main line first
+    children: Vec<Node>,
+    /// comment before the first move
+    comment: Option<String>,
+    /// the result, if one ended the line
+    result: Option<GameResult>,
+}
+
+/// Reads games one at a time from PGN text
+pub struct PgnReader<R: BufRead> {
+    input: R,
+    line: Vec<char>,
+    col: usize,
+    line_no: usize,
+    eof: bool,
+    /// tokens read ahead, in reverse order
+    pending: Vec<(Token, usize)>,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            line: Vec::new(),
+            col: 0,
+            line_no: 0,
+            eof: false,
+            pending: Vec::new(),
+        }
+    }
+
+    fn error(&self, line: usize, message: impl Into<String>) -> PgnError {
+        PgnError { line, message: message.into() }
+    }
+
+    /// Returns the next character, with line ends as `'\n'`
+    fn next_char(&mut self) -> Result<Option<char>, PgnError> {
+        while self.col >= self.line.len() {
+            if self.eof {
+                return Ok(None);
+            }
+            let mut buf = String::new();
+            let read = self.input.read_line(&mut buf)
+                .map_err(|e| self.error(self.line_no + 1, e.to_string()))?;
+            if read == 0 {
+                self.eof = true;
+                return Ok(None);
+            }
+            self.line_no += 1;
+            // escaped lines are ignored entirely
+            if buf.starts_with('%') {
+                continue;
+            }
+            self.line = buf.trim_end_matches(['\r', '\n']).chars().chain(std::iter::once('\n')).collect();
+            self.col = 0;
+        }
+        self.col += 1;
+        Ok(Some(self.line[self.col - 1]))
+    }
+
+    fn peek_char(&mut self) -> Result<Option<char>, PgnError> {
+        let c = self.next_char()?;
+        if c.is_some() {
+            self.col -= 1;
+        }
+        Ok(c)
+    }
+
+    fn read_tag(&mut self) -> Result<Token, PgnError> {
+        let line = self.line_no;
+        let mut name = String::new();
+        let mut value = String::new();
+        while let Some(c) = self.next_char()? {
+            match c {
+                '"' => break,
+                c if c.is_whitespace() => (),
+                c if c.is_alphanumeric() || c == '_' => name.push(c),
+                _ => return Err(self.error(line, format!("unexpected '{}' in tag name", c))),
+            }
+        }
+        loop {
+            match self.next_char()? {
+                Some('\\') => value.extend(self.next_char()?),
+                Some('"') => break,
+                Some('\n') | None => return Err(self.error(line, "unterminated tag value")),
+                Some(c) => value.push(c),
+            }
+        }
+        loop {
+            match self.next_char()? {
+                Some(']') => break,
+                Some(c) if c.is_whitespace() => (),
+                _ => return Err(self.error(line, "expected ']' after tag value")),
+            }
+        }
+        if name.is_empty() {
+            return Err(self.error(line, "missing tag name"));
+        }
+        Ok(Token::Tag(name, value))
+    }
+
+    fn next_token(&mut self) -> Result<Option<(Token, usize)>, PgnError> {
+        if let Some(token) = self.pending.pop() {
+            return Ok(Some(token));
+        }
+
+        let c = loop {
+            match self.next_char()? {
+                Some(c) if c.is_whitespace() => (),
+                Some(c) => break c,
+                None => return Ok(None),
+            }
+        };
+        let line = self.line_no;
+
+        let token = match c {
+            '[' => self.read_tag()?,
+            '{' => {
+                let mut comment = String::new();
+                loop {
+                    match self.next_char()? {
+                        Some('}') => break,
+                        Some('\n') => comment.push(' '),
+                        Some(c) => comment.push(c),
+                        None => return Err(self.error(line, "unterminated comment")),
+                    }
+                }
+                Token::Comment(comment.trim().to_string())
+            },
+            ';' => {
+                let mut comment = String::new();
+                while let Some(c) = self.next_char()? {
+                    if c == '\n' { break; }
+                    comment.push(c);
+                }
+                Token::Comment(comment.trim().to_string())
+            },
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '$' => {
+                let mut digits = String::new();
+                while let Some(c) = self.peek_char()? {
+                    if !c.is_ascii_digit() { break; }
+                    digits.push(c);
+                    self.next_char()?;
+                }
+                Token::Nag(digits.parse().map_err(|_| self.error(line, format!("invalid NAG '${}'", digits)))?)
+            },
+            ']' | '}' => return Err(self.error(line, format!("unexpected '{}'", c))),
+            _ => {
+                let mut symbol = c.to_string();
+                while let Some(c) = self.peek_char()? {
+                    if c.is_whitespace() || DELIMITERS.contains(c) { break; }
+                    symbol.push(c);
+                    self.next_char()?;
+                }
+                return self.classify_symbol(symbol, line);
+            },
+        };
+        Ok(Some((token, line)))
+    }
+
+    /// Splits a symbol into move number, SAN move, result and suffix annotation tokens
+    fn classify_symbol(&mut self, symbol: String, line: usize) -> Result<Option<(Token, usize)>, PgnError> {
+        if let Ok(result) = GameResult::from_str(&symbol) {
+            return Ok(Some((Token::Result(result), line)));
+        }
+
+        // castling may be written with zeros, so digits are only a move number when a dot or nothing follows
+        let digits = symbol.len() - symbol.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let text = if digits == symbol.len() || symbol[digits..].starts_with('.') {
+            symbol[digits..].trim_start_matches('.')
+        } else {
+            &symbol
+        };
+        let san = text.trim_end_matches(['!', '?']);
+        let nag = match &text[san.len()..] {
+            "" => None,
+            "!" => Some(1),
+            "?" => Some(2),
+            "!!" => Some(3),
+            "??" => Some(4),
+            "!?" => Some(5),
+            "?!" => Some(6),
+            suffix => return Err(self.error(line, format!("invalid annotation '{}'", suffix))),
+        };
+
+        if let Some(nag) = nag {
+            self.pending.push((Token::Nag(nag), line));
+        }
+        if !san.is_empty() {
+            self.pending.push((Token::San(san.to_string()), line));
+        }
+        self.next_token()
+    }
+
+    /// Skips the rest of a malformed game
+    fn skip_game(&mut self) {
+        while let Ok(Some((token, _))) = self.next_token() {
+            if let Token::Result(_) = token { break; }
+        }
+    }
+
+    fn read_game(&mut self) -> Result<Option<Game>, PgnError> {
+        let mut tags = Vec::new();
+        let (first, line) = loop {
+            match self.next_token()? {
+                Some((Token::Tag(name, value), _)) => tags.push((name, value)),
+                Some(token) => break token,
+                None if tags.is_empty() => return Ok(None),
+                None => return Err(self.error(self.line_no, "game has no movetext")),
+            }
+        };
+        self.pending.push((first, line));
+
+        let start = match tags.iter().find(|(name, _)| name == "FEN") {
+            Some((_, fen)) => Board::from_fen(fen).map_err(|e| self.error(line, e.to_string()))?,
+            None => Board::new(),
+        };
+        let mut root = Node::new(start, None);
+        let movetext = self.read_line(&root.board, 0)?;
+        root.children = movetext.children;
+        root.comment = movetext.comment;
+
+        let result = match movetext.result {
+            Some(result) => result,
+            None => tags.iter().find(|(name, _)| name == "Result")
+                .and_then(|(_, value)| GameResult::from_str(value).ok())
+                .unwrap_or(GameResult::Ongoing),
+        };
+        Ok(Some(Game { tags, root, result }))
+    }
+
+    /// Reads a line of play from `start`
+    fn read_line(&mut self, start: &Board, depth: usize) -> Result<Line, PgnError> {
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut variations: Vec<Vec<Node>> = Vec::new();
+        let mut leading_comment: Option<String> = None;
+        let mut pending_comment: Option<String> = None;
+        let mut result = None;
+
+        loop {
+            let Some((token, line)) = self.next_token()? else {
+                if depth > 0 {
+                    return Err(self.error(self.line_no, "unterminated variation"));
+                }
+                break;
+            };
+            match token {
+                Token::San(san) => {
+                    let board = nodes.last().map_or(start, |node| &node.board);
+                    let mv = board.parse_san(&san).map_err(|e| self.error(line, e.to_string()))?;
+                    let mut next = board.clone();
+                    next.make_move(mv);
+                    let mut node = Node::new(next, Some(mv));
+                    node.starting_comment = pending_comment.take();
+                    nodes.push(node);
+                    variations.push(Vec::new());
+                },
+                Token::Nag(nag) => match nodes.last_mut() {
+                    Some(node) => node.nags.push(nag),
+                    None => return Err(self.error(line, "annotation before any move")),
+                },
+                Token::Comment(comment) => {
+                    let target = match nodes.last_mut() {
+                        Some(node) => &mut node.comment,
+                        None if depth == 0 => &mut leading_comment,
+                        None => &mut pending_comment,
+                    };
+                    *target = Some(match target.take() {
+                        Some(existing) => format!("{} {}", existing, comment),
+                        None => comment,
+                    });
+                },
+                Token::Open => {
+                    if nodes.is_empty() {
+                        return Err(self.error(line, "variation before any move"));
+                    }
+                    let base = if nodes.len() >= 2 { &nodes[nodes.len() - 2].board } else { start };
+                    let variation = self.read_line(&base.clone(), depth + 1)?;
+                    let mut alternatives = variation.children.into_iter();
+                    if let Some(mut first) = alternatives.next() {
+                        first.starting_comment = variation.comment;
+                        variations.last_mut().unwrap().push(first);
+                    }
+                    variations.last_mut().unwrap().extend(alternatives);
+                },
+                Token::Close if depth > 0 => break,
+                Token::Close => return Err(self.error(line, "unmatched ')'")),
+                Token::Result(r) if depth > 0 => {
+                    // left for `skip_game` so the following game is still read
+                    self.pending.push((Token::Result(r), line));
+                    return Err(self.error(line, "unterminated variation"));
+                },
+                Token::Result(r) => {
+                    result = Some(r);
+                    break;
+                },
+                Token::Tag(..) => return Err(self.error(line, "tag pair inside movetext")),
+            }
+        }
+        if leading_comment.is_none() {
+            leading_comment = pending_comment;
+        }
+
+        // link each move to the next, with variations as later siblings
+        let mut children = Vec::new();
+        while let Some(mut node) = nodes.pop() {
+            node.children = children;
+            children = std::iter::once(node).chain(variations.pop().unwrap()).collect();
+        }
+        Ok(Line { children, comment: leading_comment, result })
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<Game, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_game() {
+            Ok(game) => game.map(Ok),
+            Err(e) => {
+                self.skip_game();
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+/// Parses every game in a PGN string
+pub fn parse_games(text: &str) -> Result<Vec<Game>, PgnError> {
+    PgnReader::new(text.as_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess::Square;
+    use std::{fs::File, io::BufReader};
+
+    #[test]
+    fn test_read_testresults() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../tools/res/testresults/0.1.0-vs-0.1.1.pgn");
+        let reader = PgnReader::new(BufReader::new(File::open(path).unwrap()));
+
+        let mut games = 0;
+        let mut scored = 0;
+        let mut total_plies = 0;
+        for game in reader {
+            let game = game.unwrap();
+            let plies = game.mainline().count();
+            assert_eq!(game.tag("PlyCount").unwrap().parse::<usize>().unwrap(), plies);
+            assert_eq!(game.tag("Result"), Some(game.result.as_str()));
+            assert_eq!(game.root.board.to_fen(), game.tag("FEN").unwrap());
+            scored += game.mainline().filter(|node| node.engine_score().is_some()).count();
+            total_plies += plies;
+
+            // written games read back identically
+            let written = game.to_string();
+            let reread = parse_games(&written).unwrap();
+            assert_eq!(reread.len(), 1);
+            assert_eq!(reread[0].tags, game.tags);
+            assert_eq!(reread[0].mainline_moves(), game.mainline_moves());
+            assert_eq!(reread[0].result, game.result);
+            assert!(reread[0].mainline().zip(game.mainline()).all(|(a, b)| a.comment == b.comment));
+            assert_eq!(reread[0].to_string(), written);
+            games += 1;
+        }
+        assert_eq!(games, 1000);
+        // a few moves are played instantly without a search
+        assert!(scored * 100 > total_plies * 99);
+    }
+
+    #[test]
+    fn test_variations() {
+        let text = r#"[Event "Variations \"test\""]
+[Result "1-0"]
+
+{Opening} 1. e4 $1 e5 (1... c5 {Sicilian} 2. Nf3 (2. Nc3 Nc6) 2... d6) (1... e6!?)
+2. Nf3 Nc6?! 3. Bb5 ; Ruy Lopez
+a6 4. Ba4 {main} 1-0
+"#;
+        let games = parse_games(text).unwrap();
+        assert_eq!(games.len(), 1);
+        let game = &games[0];
+        assert_eq!(game.tag("Event"), Some("Variations \"test\""));
+        assert_eq!(game.result, GameResult::WhiteWin);
+        assert_eq!(game.root.comment.as_deref(), Some("Opening"));
+        assert_eq!(game.mainline().count(), 7);
+
+        let e4 = &game.root.children[0];
+        assert_eq!(e4.nags, vec![1]);
+        assert_eq!(e4.children.len(), 3);
+        let c5 = &e4.children[1];
+        assert_eq!(c5.mv, Some(Move::new(Square::C7, Square::C5, None)));
+        assert_eq!(c5.comment.as_deref(), Some("Sicilian"));
+        assert_eq!(c5.children.len(), 2);
+        assert_eq!(c5.children[1].children[0].mv, Some(Move::new(Square::B8, Square::C6, None)));
+        assert_eq!(e4.children[2].nags, vec![5]);
+
+        let bb5 = game.mainline().nth(4).unwrap();
+        assert_eq!(bb5.comment.as_deref(), Some("Ruy Lopez"));
+        assert_eq!(game.mainline().nth(3).unwrap().nags, vec![6]);
+        assert_eq!(game.board().to_fen(), "r1bqkbnr/1ppp1ppp/p1n5/4p3/B3P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 1 4");
+
+        let written = game.to_string();
+        assert_eq!(written, r#"[Event "Variations \"test\""]
+[Result "1-0"]
+
+{Opening} 1. e4 $1 e5 (1... c5 {Sicilian} 2. Nf3 (2. Nc3 Nc6) 2... d6) (1... e6
+$5) 2. Nf3 Nc6 $6 3. Bb5 {Ruy Lopez} 3... a6 4. Ba4 {main} 1-0
+"#);
+        let reread = parse_games(&written).unwrap();
+        assert_eq!(reread[0].to_string(), written);
+    }
+
+    #[test]
+    fn test_castling_with_zeros() {
+        let text = "1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4.0-0 Nf6 5. d3 d6 6. Bg5 h6 7. Bh4 Be6 8. Nc3 Qd7 9. a3 0-0-0? 1/2-1/2";
+        let games = parse_games(text).unwrap();
+        let game = &games[0];
+        assert_eq!(game.mainline().nth(6).unwrap().mv, Some(Move::new(Square::E1, Square::G1, None)));
+        let long = game.mainline().last().unwrap();
+        assert_eq!(long.mv, Some(Move::new(Square::E8, Square::C8, None)));
+        assert_eq!(long.nags, vec![2]);
+        assert_eq!(game.result, GameResult::Draw);
+        assert!(game.to_string().contains("4. O-O Nf6"));
+    }
+
+    #[test]
+    fn test_write_engine_game() {
+        let mut game = Game::new(Board::from_fen("6k1/8/R5K1/8/8/8/8/8 w - - 0 1").unwrap());
+        game.set_tag("White", "engine");
+        game.push(Move::new(Square::A6, Square::A8, None), Some(score_comment(49999, 1)));
+        game.set_result(GameResult::WhiteWin);
+
+        let written = game.to_string();
+        assert!(written.contains("[FEN \"6k1/8/R5K1/8/8/8/8/8 w - - 0 1\"]"));
+        assert!(written.contains("[Result \"1-0\"]"));
+        assert!(written.ends_with("1. Ra8# {+499.99/1} 1-0\n"));
+
+        let reread = parse_games(&written).unwrap();
+        assert_eq!(reread[0].mainline().next().unwrap().engine_score(), Some((49999, 1)));
+        assert_eq!(reread[0].tag("White"), Some("engine"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let errors = [
+            ("[Event \"x\"]\n\n1. e4 e6 2. e5 e5 *", 3),
+            ("[Event \"x]\n\n1. e4 *", 1),
+            ("1. e4 (1... e5 *", 1),
+            ("1. e4 e5 ) *", 1),
+            ("[FEN \"8/8 w - -\"]\n1. e4 *", 2),
+        ];
+        for (text, line) in errors {
+            let err = parse_games(text).err().unwrap_or_else(|| panic!("{}", text));
+            assert_eq!(err.line, line, "{}: {}", text, err);
+        }
+
+        // the reader recovers at the next game
+        let text = "1. e4 e4 *\n\n1. d4 d5 *\n";
+        let games = PgnReader::new(text.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(games.len(), 2);
+        assert!(games[0].is_err());
+        assert_eq!(games[1].as_ref().unwrap().mainline().count(), 2);
+    }
+}
@@ -0,0 +1,318 @@
+use std::fmt;
+
+use chess::{File, MoveGen, Piece, Rank, Square, EMPTY};
+
+use crate::{board::{Board, Move}, chess960::CastleSide};
+
+/// Reasons a SAN move can fail to parse
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SanError {
+    /// the text is not a SAN move
+    Invalid(String),
+    /// no legal move matches the text
+    Illegal(String),
+    /// more than one legal move matches the text
+    Ambiguous(String),
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SanError::Invalid(san) => write!(f, "invalid san move '{}'", san),
+            SanError::Illegal(san) => write!(f, "illegal san move '{}'", san),
+            SanError::Ambiguous(san) => write!(f, "ambiguous san move '{}'", san),
+        }
+    }
+}
+
+impl std::error::Error for SanError { }
+
+impl Board {
+    /// Parses a move in standard algebraic notation for the current position
+    ///
+    /// check and annotation suffixes are ignored, as is a missing or superfluous capture sign
+    pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
+        let invalid = || SanError::Invalid(san.to_string());
+        let text = san.trim().trim_end_matches(['+', '#', '!', '?']);
+
+        /* Castling */
+        let castle_side = match text {
+            "O-O" | "0-0" => Some(CastleSide::Kingside),
+            "O-O-O" | "0-0-0" => Some(CastleSide::Queenside),
+            _ => None,
+        };
+        if let Some(side) = castle_side {
+            return self.legal_moves()
+                .find(|&mv| self.castle_side(mv) == Some(side))
+                .ok_or_else(|| SanError::Illegal(san.to_string()));
+        }
+
+        /* Promotion */
+        let (text, promotion) = match text.char_indices().last() {
+            Some((idx, c)) if c.is_ascii_uppercase() => {
+                let piece = char_to_piece(c).filter(|&p| p != Piece::King).ok_or_else(invalid)?;
+                (text[..idx].trim_end_matches('='), Some(piece))
+            },
+            _ => (text, None),
+        };
+
+        /* Piece, Disambiguation and Destination */
+        let mut chars = text.chars().collect::<Vec<char>>();
+        let piece = match chars.first() {
+            Some(&c) if c.is_ascii_uppercase() => {
+                chars.remove(0);
+                char_to_piece(c).ok_or_else(invalid)?
+            },
+            _ => Piece::Pawn,
+        };
+        if chars.len() < 2 {
+            return Err(invalid());
+        }
+        let dest = parse_square(chars[chars.len() - 2], chars[chars.len() - 1]).ok_or_else(invalid)?;
+        chars.truncate(chars.len() - 2);
+        if chars.last() == Some(&'x') {
+            chars.pop();
+        }
+
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in chars {
+            match c {
+                'a'..='h' if from_file.is_none() && from_rank.is_none() => {
+                    from_file = Some(File::from_index((c as u8 - b'a') as usize));
+                },
+                '1'..='8' if from_rank.is_none() => {
+                    from_rank = Some(Rank::from_index((c as u8 - b'1') as usize));
+                },
+                _ => return Err(invalid()),
+            }
+        }
+
+        let mut matches = MoveGen::new_legal(&self.position).filter(|mv| {
+            mv.get_dest() == dest
+                && mv.get_promotion() == promotion
+                && self.position.piece_on(mv.get_source()) == Some(piece)
+                && from_file.is_none_or(|file| mv.get_source().get_file() == file)
+                && from_rank.is_none_or(|rank| mv.get_source().get_rank() == rank)
+        });
+        match (matches.next(), matches.next()) {
+            (Some(mv), None) => Ok(mv),
+            (Some(_), Some(_)) => Err(SanError::Ambiguous(san.to_string())),
+            (None, _) => Err(SanError::Illegal(san.to_string())),
+        }
+    }
+
+    /// Returns the standard algebraic notation of a legal move in the current position
+    pub fn to_san(&self, mv: Move) -> String {
+        let pos = &self.position;
+        let source = mv.get_source();
+        let dest = mv.get_dest();
+        let piece = pos.piece_on(source).expect("move must start on an occupied square");
+        let mut san = String::with_capacity(8);
+
+        let file_distance = source.get_file().to_index().abs_diff(dest.get_file().to_index());
+        if let Some(side) = self.castle_side(mv) {
+            san.push_str(if side == CastleSide::Kingside { "O-O" } else { "O-O-O" });
+        } else {
+            let is_capture = pos.piece_on(dest).is_some() || (piece == Piece::Pawn && file_distance == 1);
+            if piece == Piece::Pawn {
+                if is_capture {
+                    san.push(file_to_char(source.get_file()));
+                }
+            } else {
+                san.push_str(&piece.to_string(chess::Color::White));
+
+                // other pieces of the same type that can reach the destination
+                let others = MoveGen::new_legal(pos)
+                    .filter(|other| other.get_dest() == dest
+                        && other.get_source() != source
+                        && pos.piece_on(other.get_source()) == Some(piece))
+                    .map(|other| other.get_source())
+                    .collect::<Vec<Square>>();
+                if !others.is_empty() {
+                    if others.iter().all(|sq| sq.get_file() != source.get_file()) {
+                        san.push(file_to_char(source.get_file()));
+                    } else if others.iter().all(|sq| sq.get_rank() != source.get_rank()) {
+                        san.push(rank_to_char(source.get_rank()));
+                    } else {
+                        san.push(file_to_char(source.get_file()));
+                        san.push(rank_to_char(source.get_rank()));
+                    }
+                }
+            }
+            if is_capture {
+                san.push('x');
+            }
+            san.push_str(&dest.to_string());
+            if let Some(promotion) = mv.get_promotion() {
+                san.push('=');
+                san.push_str(&promotion.to_string(chess::Color::White));
+            }
+        }
+
+        let next = self.position_after(mv);
+        if *next.checkers() != EMPTY {
+            san.push(if MoveGen::new_legal(&next).len() == 0 { '#' } else { '+' });
+        }
+        san
+    }
+}
+
+fn char_to_piece(c: char) -> Option<Piece> {
+    match c {
+        'N' => Some(Piece::Knight),
+        'B' => Some(Piece::Bishop),
+        'R' => Some(Piece::Rook),
+        'Q' => Some(Piece::Queen),
+        'K' => Some(Piece::King),
+        _ => None,
+    }
+}
+
+fn parse_square(file: char, rank: char) -> Option<Square> {
+    if !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    Some(Square::make_square(
+        Rank::from_index((rank as u8 - b'1') as usize),
+        File::from_index((file as u8 - b'a') as usize),
+    ))
+}
+
+fn file_to_char(file: File) -> char {
+    (b'a' + file.to_index() as u8) as char
+}
+
+fn rank_to_char(rank: Rank) -> char {
+    (b'1' + rank.to_index() as u8) as char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the starting fen and SAN moves of every game in a PGN file, skipping
+    /// comments, move numbers and results
+    fn read_games(path: &str) -> Vec<(String, Vec<String>)> {
+        let text = std::fs::read_to_string(path).unwrap();
+        let mut games = Vec::new();
+        let mut fen = None;
+        let mut moves = Vec::new();
+        let mut in_comment = false;
+        for line in text.lines() {
+            if let Some(tag) = line.strip_prefix("[FEN \"") {
+                fen = Some(tag.trim_end_matches("\"]").to_string());
+                continue;
+            }
+            if line.starts_with('[') {
+                continue;
+            }
+            for token in line.split_whitespace() {
+                if in_comment || token.starts_with('{') {
+                    in_comment = !token.ends_with('}');
+                    continue;
+                }
+                match token {
+                    "1-0" | "0-1" | "1/2-1/2" | "*" => {
+                        let start = fen.take().unwrap_or_else(|| Board::new().to_fen());
+                        games.push((start, std::mem::take(&mut moves)));
+                    },
+                    _ if token.ends_with('.') => (),
+                    _ => moves.push(token.to_string()),
+                }
+            }
+        }
+        games
+    }
+
+    #[test]
+    fn test_san_corpus() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../tools/res/testresults/0.1.0-vs-0.1.1.pgn");
+        let games = read_games(path);
+        assert!(games.len() > 100);
+
+        let mut plies = 0;
+        for (fen, moves) in &games {
+            let mut board = Board::from_fen(fen).unwrap();
+            for san in moves {
+                let mv = board.parse_san(san).unwrap_or_else(|e| panic!("{} in {}", e, board));
+                assert_eq!(&board.to_san(mv), san, "{}", board);
+                board.make_move(mv);
+                plies += 1;
+            }
+        }
+        assert!(plies > 20_000);
+    }
+
+    #[test]
+    fn test_san_every_legal_move() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1",
+        ];
+        for fen in fens {
+            let board = Board::from_fen(fen).unwrap();
+            for mv in board.legal_moves() {
+                let san = board.to_san(mv);
+                assert_eq!(board.parse_san(&san), Ok(mv), "{} {}", fen, san);
+            }
+        }
+    }
+
+    #[test]
+    fn test_san_disambiguation() {
+        // knights on b1 and f3 share neither file nor rank, rooks on a1 and a5 share a file
+        let board = Board::from_fen("7k/8/8/R7/8/5N2/8/RN2K3 w - - 0 1").unwrap();
+        assert_eq!(board.to_san(Move::new(Square::B1, Square::D2, None)), "Nbd2");
+        assert_eq!(board.to_san(Move::new(Square::A1, Square::A3, None)), "R1a3");
+        assert_eq!(board.to_san(Move::new(Square::A5, Square::A3, None)), "R5a3");
+        assert_eq!(board.parse_san("Nd2"), Err(SanError::Ambiguous("Nd2".to_string())));
+        assert_eq!(board.parse_san("Rfa3"), Err(SanError::Illegal("Rfa3".to_string())));
+
+        // three queens where only file and rank together identify the piece
+        let board = Board::from_fen("1k6/8/8/8/4Q2Q/8/8/K6Q w - - 0 1").unwrap();
+        assert_eq!(board.to_san(Move::new(Square::H4, Square::E1, None)), "Qh4e1");
+        assert_eq!(board.parse_san("Qh4e1"), Ok(Move::new(Square::H4, Square::E1, None)));
+        assert_eq!(board.parse_san("Qhe1"), Err(SanError::Ambiguous("Qhe1".to_string())));
+    }
+
+    #[test]
+    fn test_san_special_moves() {
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(board.to_san(Move::new(Square::E1, Square::G1, None)), "O-O");
+        assert_eq!(board.to_san(Move::new(Square::E1, Square::C1, None)), "O-O-O");
+        assert_eq!(board.parse_san("0-0-0"), Ok(Move::new(Square::E1, Square::C1, None)));
+        assert_eq!(board.to_san(Move::new(Square::A1, Square::A8, None)), "Rxa8+");
+
+        // chess960 castling is written as the king taking its rook
+        let board = Board::from_fen("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1").unwrap();
+        assert_eq!(board.to_san(Move::new(Square::G1, Square::H1, None)), "O-O");
+        assert_eq!(board.parse_san("O-O-O"), Ok(Move::new(Square::G1, Square::B1, None)));
+        assert_eq!(board.to_san(Move::new(Square::B1, Square::B8, None)), "Rxb8+");
+
+        let board = Board::from_fen("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3").unwrap();
+        assert_eq!(board.to_san(Move::new(Square::E5, Square::F6, None)), "exf6");
+        assert_eq!(board.parse_san("ef6"), Ok(Move::new(Square::E5, Square::F6, None)));
+
+        let board = Board::from_fen("1n5k/P7/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        assert_eq!(board.to_san(Move::new(Square::A7, Square::B8, Some(Piece::Queen))), "axb8=Q+");
+        assert_eq!(board.parse_san("a8N"), Ok(Move::new(Square::A7, Square::A8, Some(Piece::Knight))));
+        assert_eq!(board.parse_san("a8"), Err(SanError::Illegal("a8".to_string())));
+
+        let board = Board::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        assert_eq!(board.to_san(Move::new(Square::A1, Square::A8, None)), "Ra8#");
+        assert_eq!(board.parse_san("Ra8#!?"), Ok(Move::new(Square::A1, Square::A8, None)));
+    }
+
+    #[test]
+    fn test_san_invalid() {
+        let board = Board::new();
+        for san in ["", "e", "e9", "Xe4", "Nz3", "e8=K", "Nb1c3d"] {
+            assert!(matches!(board.parse_san(san), Err(SanError::Invalid(_))), "{}", san);
+        }
+        assert_eq!(board.parse_san("e5"), Err(SanError::Illegal("e5".to_string())));
+        assert_eq!(board.parse_san("O-O"), Err(SanError::Illegal("O-O".to_string())));
+    }
+}
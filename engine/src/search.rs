@@ -1,93 +1,361 @@
-use chess::Piece;
+use chess::{Color, Piece};
 
-use crate::{board::{Board, Move}, eval::{evaluate, Eval}, tt::{TranspositionTable, Bound}};
-use std::{time::Instant, sync::{atomic::{AtomicBool, Ordering}, Arc}};
+use crate::{board::{Board, Move, RepetitionRule}, material::MaterialDraw, eval::{evaluate_lazy, Eval, EvalCache}, polyglot::{Book, BookChoice}, syzygy::{Syzygy, Wdl}, tablebase::{Outcome, Tablebases}, timeman::TimeManager, tt::{TranspositionTable, Bound}};
+use std::{thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, RecvTimeoutError}, Arc}};
 
 const CHECKMATE_VALUE: Eval = 50000;
 const OUT_OF_TIME_VALUE: Eval = 77777;
 const FUTILITY_MARGIN: Eval = 200;
+/// Syzygy wins have no known distance to mate, so they score below any found mate
+const TB_WIN_VALUE: Eval = CHECKMATE_VALUE - 1000;
+/// Iterative deepening stops here even without any other limit
+const MAX_DEPTH: u8 = 100;
+/// Nodes searched between checks of the time and node limits
+const CHECK_INTERVAL: u64 = 1024;
+/// Milliseconds between progress updates during an iteration, and before the root moves are reported
+const REPORT_INTERVAL: u32 = 1000;
+
+/// Limits on a single search, taken from the parameters of `go`
+///
+/// a search without any limit runs until it is stopped or reaches `MAX_DEPTH`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    /// milliseconds left on the clock of the side to move
+    pub time: Option<u32>,
+    /// milliseconds added to the clock after each move
+    pub inc: u32,
+    /// moves until the next time control, otherwise the rest of the game is played on the clock
+    pub movestogo: Option<u32>,
+    /// milliseconds to search for, stopping even in the middle of an iteration
+    pub move_time: Option<u32>,
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    /// stop once a mate in at most this many moves is found
+    pub mate: Option<u8>,
+    /// only these root moves are searched, unless none of them are legal
+    pub searchmoves: Vec<Move>,
+}
+
+impl SearchLimits {
+    /// Limits a search to exactly `ms` milliseconds
+    pub fn move_time(ms: u32) -> Self {
+        Self { move_time: Some(ms), ..Self::default() }
+    }
+
+    /// Limits a search to the given number of iterations
+    pub fn depth(depth: u8) -> Self {
+        Self { depth: Some(depth), ..Self::default() }
+    }
+}
+
+/// One of the best lines of a search, with its score from the root side to move's perspective
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PvLine {
+    pub score: Eval,
+    pub moves: Vec<Move>,
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct DebugInfo {
-    pub nodes: u32,
+    pub nodes: u64,
     pub delta_pruned: u32,
+    pub tb_hits: u32,
+    /// the deepest ply reached, counting the quiescence search
+    pub seldepth: usize,
 }
 
 #[derive(Clone)]
 pub struct SearchContext {
     pub tt: Arc<TranspositionTable>,
+    pub tablebases: Arc<Tablebases>,
+    pub syzygy: Arc<Syzygy>,
+    pub book: Arc<Book>,
     pub stop_search: Arc<AtomicBool>,
+    /// set while the search ponders on the opponent's time, cleared by `ponderhit`
+    pub pondering: Arc<AtomicBool>,
 
     pub board: Board,
     pub debug: DebugInfo,
+    pub eval_cache: EvalCache,
 
+    pub syzygy_probe_depth: u8,
+    pub syzygy_50_move_rule: bool,
+    /// how much worse than equal a draw is for the root side to move, in centipawns
+    pub contempt: Eval,
+    pub repetition_rule: RepetitionRule,
+    pub own_book: bool,
+    /// the last fullmove number at which the book is probed
+    pub book_depth: u16,
+    /// always play the highest weighted book move instead of picking by weight
+    pub best_book_move: bool,
+    /// milliseconds lost to communication with the GUI on every move
+    pub move_overhead: u32,
+    /// the number of best lines to search and report
+    pub multi_pv: usize,
+
+    root_color: Color,
     root_best_move: Move,
+    /// nodes searched below the root best move in the current iteration
+    root_best_nodes: u64,
+    root_moves: Vec<Move>,
+    /// root moves already reported as a better line in this iteration
+    excluded_moves: Vec<Move>,
+    /// the principal variation from each ply of the current search path, triangular by ply
+    pv_table: Vec<Vec<Move>>,
+    /// the lines of the last completed iteration, best first
+    lines: Vec<PvLine>,
+    syzygy_limit: usize,
     search_depth: u8,
-    strict_timing: bool,
-    move_time: u32,
+    limits: SearchLimits,
+    time: Option<TimeManager>,
+    /// milliseconds into the search at which the clock started, `None` while pondering
+    clock_start: Option<u32>,
+    /// the node count at which the limits are checked next
+    next_check: u64,
+    /// the search time at which the next progress update is sent
+    next_report: u32,
 }
 
 impl SearchContext {
     pub fn new() -> Self {
         Self {
             tt: Arc::new(TranspositionTable::default()),
+            tablebases: Arc::new(Tablebases::default()),
+            syzygy: Arc::new(Syzygy::default()),
+            book: Arc::new(Book::default()),
             stop_search: Arc::new(AtomicBool::new(false)),
+            pondering: Arc::new(AtomicBool::new(false)),
 
             board: Board::new(),
             debug: DebugInfo::default(),
+            eval_cache: EvalCache::default(),
+
+            syzygy_probe_depth: 1,
+            syzygy_50_move_rule: true,
+            contempt: 0,
+            repetition_rule: RepetitionRule::Twofold,
+            own_book: false,
+            book_depth: 16,
+            best_book_move: false,
+            move_overhead: 10,
+            multi_pv: 1,
 
+            root_color: Color::White,
             root_best_move: Move::default(),
+            root_best_nodes: 0,
+            root_moves: Vec::new(),
+            excluded_moves: Vec::new(),
+            pv_table: vec![Vec::new(); MAX_DEPTH as usize + 1],
+            lines: Vec::new(),
+            syzygy_limit: 0,
             search_depth: 0,
-            strict_timing: false,
-            move_time: 0,
+            limits: SearchLimits::default(),
+            time: None,
+            clock_start: None,
+            next_check: 0,
+            next_report: 0,
+        }
+    }
+
+    /// Returns a move from the opening book, if it is enabled and the game is within the book depth
+    pub fn book_move(&self) -> Option<Move> {
+        if !self.own_book || self.board.fullmove_number() > self.book_depth {
+            return None;
         }
+        let choice = if self.best_book_move {
+            BookChoice::Best
+        } else {
+            let mut seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(1, |d| d.as_nanos() as u64) | 1;
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            BookChoice::Weighted(seed)
+        };
+        self.book.probe(&self.board, choice)
     }
 
-    pub fn search(&mut self, move_time: u32, strict_timing: bool, verbose: bool) -> Move {
-        self.strict_timing = strict_timing;
-        self.move_time = move_time;
-        self.stop_search.store(false, Ordering::Relaxed);
+    /// Searches the current position, returning `Move::default()` only if there are no legal moves
+    ///
+    /// `stop_search` is not cleared first, so a stop that arrives before the search starts isn't lost.
+    /// while `pondering` is set the time limits are ignored, and they count from the moment it is cleared
+    pub fn search(&mut self, limits: SearchLimits, verbose: bool) -> Move {
+        self.time = TimeManager::new(&limits, self.move_overhead);
+        self.limits = limits;
+        self.clock_start = None;
+        self.next_check = 0;
+        self.next_report = REPORT_INTERVAL;
+        self.lines.clear();
         self.debug.nodes = 0;
+        self.debug.tb_hits = 0;
+        self.debug.seldepth = 0;
+        self.root_color = self.board.side_to_move();
+        self.board.set_search_root();
 
         let timer = Instant::now();
-        let mut best_move = Move::default();
+
+        /* Tablebase Root Probe
+         *
+         * the line follows the tables down to the mate, or for a draw, to the expected reply
+        */
+        let probe_root = self.limits.searchmoves.is_empty();
+        if let Some((mv, outcome)) = self.tablebases.probe_root(&self.board.position).filter(|_| probe_root) {
+            let length = match outcome {
+                Outcome::Win(dist) | Outcome::Loss(dist) => dist as usize,
+                Outcome::Draw => 2,
+            };
+            let mut line = PvLine { score: tablebase_score(outcome, 0), moves: vec![mv] };
+            let mut pos = self.board.position.make_move_new(mv);
+            while line.moves.len() < length {
+                let Some((reply, _)) = self.tablebases.probe_root(&pos) else { break };
+                line.moves.push(reply);
+                pos = pos.make_move_new(reply);
+            }
+            println!("info depth 1 score {} time {} pv {}",
+                self.format_score(line.score),
+                timer.elapsed().as_millis(),
+                line.moves.iter().map(|mv| mv.to_string()).collect::<Vec<String>>().join(" "),
+            );
+            self.lines.push(line);
+            return mv;
+        }
+
+        /* Syzygy Root Probe
+         *
+         * only the best ranked moves are searched. DTZ ranked moves already make progress,
+         * so the tables are only probed below the root when ranking fell back to WDL
+        */
+        self.root_moves.clear();
+        self.syzygy_limit = self.syzygy.max_pieces();
+        if let Some(ranking) = self.syzygy.rank_root_moves(&self.board, self.syzygy_50_move_rule) {
+            self.debug.tb_hits += ranking.moves.len() as u32;
+            self.root_moves = ranking.best_moves();
+            if ranking.dtz || ranking.best_rank() <= 0 {
+                self.syzygy_limit = 0;
+            }
+        }
+        /* Search Moves
+         *
+         * restricted to the tablebase ranked moves among them, if there are any
+        */
+        let searchmoves = self.board.legal_moves()
+            .filter(|mv| self.limits.searchmoves.contains(mv))
+            .collect::<Vec<Move>>();
+        if !searchmoves.is_empty() {
+            let ranked = self.root_moves.iter().copied().filter(|mv| searchmoves.contains(mv)).collect::<Vec<Move>>();
+            self.root_moves = if ranked.is_empty() { searchmoves } else { ranked };
+        }
+
+        // played if the first iteration is interrupted
+        let best_move = self.root_moves.first().copied()
+            .or_else(|| self.board.legal_moves().next())
+            .unwrap_or_default();
+        if best_move == Move::default() {
+            return best_move;
+        }
+
+        /* Watchdog
+         *
+         * stops the search at the maximum time, even if it is stuck between two checks of the limits
+        */
+        let (done, finished) = mpsc::channel();
+        thread::scope(|scope| {
+            if let Some(maximum) = self.time.as_ref().map(TimeManager::maximum) {
+                let stop = self.stop_search.clone();
+                let pondering = self.pondering.clone();
+                scope.spawn(move || watchdog(maximum, &stop, &pondering, finished));
+            }
+            let best_move = self.iterative_deepening(&timer, best_move, verbose);
+            drop(done);
+            best_move
+        })
+    }
+
+    /// Searches one depth deeper each iteration until a limit is reached, returning the best move
+    /// of the last completed iteration, or `best_move` if none completed
+    ///
+    /// each iteration searches the root once for each of the `multi_pv` best lines,
+    /// excluding the first moves of the lines already found
+    fn iterative_deepening(&mut self, timer: &Instant, mut best_move: Move, verbose: bool) -> Move {
+        let root_move_count = if self.root_moves.is_empty() { self.board.legal_moves().count() } else { self.root_moves.len() };
+        let multi_pv = self.multi_pv.clamp(1, root_move_count);
 
         self.search_depth = 1;
         loop {
-            let score = self.nega_max(&timer, self.search_depth, i32::MIN + 1, i32::MAX);
-            let stop = self.stop_search.load(Ordering::Relaxed);
+            let iteration_nodes = self.debug.nodes;
+            let mut best_move_nodes = 0;
+            let mut lines = Vec::with_capacity(multi_pv);
+            self.excluded_moves.clear();
+            for _ in 0..multi_pv {
+                let score = self.nega_max(timer, self.search_depth, i32::MIN + 1, i32::MAX);
+                let stop = self.stop_search.load(Ordering::Relaxed);
+
+                if verbose {
+                    println!("info string nodes {} delta_pruned {}", self.debug.nodes, self.debug.delta_pruned);
+                }
+                // an interrupted iteration may not have searched the best move yet
+                if stop {
+                    return best_move;
+                }
 
-            if verbose {
-                println!("info string nodes {} delta_pruned {}", self.debug.nodes, self.debug.delta_pruned);
+                if lines.is_empty() {
+                    best_move_nodes = self.root_best_nodes;
+                }
+                let moves = self.extend_pv(self.pv_table[0].clone());
+                self.excluded_moves.push(self.root_best_move);
+                lines.push(PvLine { score, moves });
             }
-            if stop || timer.elapsed().as_millis() as u32 > move_time {
-                return best_move;
+
+            // the table may score a later line above an earlier one
+            lines.sort_by_key(|line| -line.score);
+            for (index, line) in lines.iter().enumerate() {
+                println!("info multipv {} depth {} seldepth {} score {} {} pv {}",
+                    index + 1,
+                    self.search_depth,
+                    self.debug.seldepth,
+                    self.format_score(line.score),
+                    self.format_stats(timer),
+                    line.moves.iter().map(|mv| mv.to_string()).collect::<Vec<String>>().join(" "),
+                );
             }
+            self.next_report = timer.elapsed().as_millis() as u32 + REPORT_INTERVAL;
 
-            let pv = self.trace_pv();
-            println!("info depth {} score cp {} hashfull {} time {} pv {}",
-                self.search_depth,
-                score,
-                self.tt.hashfull(),
-                timer.elapsed().as_millis(),
-                pv,
-            );
+            let score = lines[0].score;
+            best_move = lines[0].moves[0];
+            self.lines = lines;
+
+            let nodes = self.debug.nodes - iteration_nodes;
+            let elapsed = self.time_used(timer);
+            let out_of_time = match (&mut self.time, elapsed) {
+                (Some(time), Some(elapsed)) => {
+                    time.update(best_move, score, best_move_nodes, nodes);
+                    time.soft_limit_reached(elapsed)
+                },
+                _ => false,
+            };
 
-            best_move = self.root_best_move;
+            let depth_reached = self.search_depth >= self.limits.depth.unwrap_or(MAX_DEPTH).min(MAX_DEPTH);
+            // a mate in n moves is found 2n - 1 plies from the root
+            let mate_found = self.limits.mate.is_some_and(|moves| score >= CHECKMATE_VALUE - (2 * moves as i32 - 1));
+            if depth_reached || mate_found || out_of_time {
+                return best_move;
+            }
             self.search_depth += 1;
         }
     }
 
     fn nega_max(&mut self, timer: &Instant, depth: u8, mut alpha: i32, beta: i32) -> i32 {
         self.debug.nodes += 1;
+        self.debug.seldepth = self.debug.seldepth.max((self.search_depth - depth) as usize);
 
-        if self.strict_timing && timer.elapsed().as_millis() as u32 > self.move_time {
-            self.stop_search.store(true, Ordering::Relaxed);
+        if self.debug.nodes >= self.next_check && self.checkpoint(timer) {
             return OUT_OF_TIME_VALUE;
         }
 
         let mut best_move = None;
         let is_root = depth == self.search_depth;
+        // lines end here unless a move raises alpha
+        let ply = (self.search_depth - depth) as usize;
+        self.pv_table[ply].clear();
 
         /* Non-Stalemate Draw Conditions
          *
@@ -96,10 +364,31 @@ impl SearchContext {
          * positions with insufficient material are not stored in the table
         */
         if !is_root {
-            if self.board.is_repeated()
+            if self.board.is_repetition(self.repetition_rule)
             || self.board.is_insufficient_material()
             || self.board.is_fifty_move_draw() {
-                return 0;
+                return self.draw_score();
+            }
+
+            /* Probe Endgame Tablebases */
+            if self.tablebases.can_probe(&self.board) {
+                if let Some(outcome) = self.tablebases.probe(&self.board.position) {
+                    return tablebase_score(outcome, self.search_depth - depth);
+                }
+            }
+
+            /* Probe Syzygy Tablebases
+             *
+             * WDL results ignore the fifty move counter, so only probe right after it resets
+            */
+            if depth >= self.syzygy_probe_depth
+                && self.board.halfmove_clock() == 0
+                && self.board.position.combined().popcnt() as usize <= self.syzygy_limit
+                && self.syzygy.can_probe(&self.board) {
+                if let Some(wdl) = self.syzygy.probe_wdl(&self.board.position) {
+                    self.debug.tb_hits += 1;
+                    return syzygy_score(wdl, self.search_depth - depth, self.syzygy_50_move_rule);
+                }
             }
 
             /* Probe Transposition Table */
@@ -124,12 +413,22 @@ impl SearchContext {
         /* Checkmate or Stalemate */
         let moves = self.board.sorted_moves(best_move, false);
         if moves.len() == 0 {
-            return if self.board.checkers().popcnt() > 0 { -CHECKMATE_VALUE + (self.search_depth - depth) as i32 } else { 0 }
+            return if self.board.checkers().popcnt() > 0 { -CHECKMATE_VALUE + (self.search_depth - depth) as i32 } else { self.draw_score() }
         }
 
         /* Core Negamax Search */
         let alpha_orig = alpha;
+        let mut move_number = 0;
         for mv in moves {
+            if is_root && (!self.root_moves.is_empty() && !self.root_moves.contains(&mv) || self.excluded_moves.contains(&mv)) {
+                continue;
+            }
+            move_number += 1;
+            if is_root && timer.elapsed().as_millis() as u32 >= REPORT_INTERVAL {
+                println!("info depth {} currmove {} currmovenumber {}", self.search_depth, mv, move_number);
+            }
+
+            let nodes = self.debug.nodes;
             self.board.make_move(mv);
             let score = -self.nega_max(timer, depth - 1, -beta, -alpha);
             self.board.undo_move();
@@ -144,7 +443,11 @@ impl SearchContext {
                     break;
                 }
 
-                if is_root { self.root_best_move = mv; }
+                self.update_pv(ply, mv);
+                if is_root {
+                    self.root_best_move = mv;
+                    self.root_best_nodes = self.debug.nodes - nodes;
+                }
             }
         }
 
@@ -169,16 +472,18 @@ impl SearchContext {
     }
 
     fn q_search(&mut self, timer: &Instant, mut alpha: i32, beta: i32) -> i32 {
-        if self.strict_timing && timer.elapsed().as_millis() as u32 > self.move_time {
-            self.stop_search.store(true, Ordering::Relaxed);
+        self.debug.nodes += 1;
+        self.debug.seldepth = self.debug.seldepth.max(self.board.ply());
+
+        if self.debug.nodes >= self.next_check && self.checkpoint(timer) {
             return OUT_OF_TIME_VALUE;
         }
 
         /* Non-Stalemate Draw Conditions */
-        if self.board.is_repeated()
+        if self.board.is_repetition(self.repetition_rule)
             || self.board.is_insufficient_material()
             || self.board.is_fifty_move_draw() {
-            return 0;
+            return self.draw_score();
         }
 
         let mut best_move = None;
@@ -201,7 +506,7 @@ impl SearchContext {
         }
 
         /* Standing Pat */
-        let static_eval = evaluate(&self.board);
+        let static_eval = self.static_eval(alpha, beta);
         if self.board.checkers().popcnt() == 0 && static_eval >= beta { return beta; }
         let alpha_orig = alpha;
         if static_eval > alpha { alpha = static_eval; }
@@ -259,55 +564,199 @@ impl SearchContext {
         alpha
     }
 
-    fn trace_pv(&mut self) -> String {
-        let mut pv_line = String::with_capacity(self.search_depth as usize * 5);
-        let mut move_count = 0;
-        loop {
+    /// Returns the best lines of the last search's last completed iteration, best first
+    pub fn lines(&self) -> &[PvLine] {
+        &self.lines
+    }
+
+    /// Returns the reply to the last search's best move expected by its principal variation
+    pub fn ponder_move(&self) -> Option<Move> {
+        self.lines.first().and_then(|line| line.moves.get(1)).copied()
+    }
+
+    /// Returns the milliseconds counted against the time limits, or `None` while pondering
+    fn time_used(&mut self, timer: &Instant) -> Option<u32> {
+        let elapsed = timer.elapsed().as_millis() as u32;
+        if self.clock_start.is_none() && !self.pondering.load(Ordering::Relaxed) {
+            self.clock_start = Some(elapsed);
+        }
+        self.clock_start.map(|start| elapsed - start)
+    }
+
+    /// Returns true once the maximum time or the node limit is used up, stopping the search,
+    /// and sends a progress update every `REPORT_INTERVAL` milliseconds
+    ///
+    /// called every `CHECK_INTERVAL` nodes, and right at the node limit
+    fn checkpoint(&mut self, timer: &Instant) -> bool {
+        let node_limit = self.limits.nodes.unwrap_or(u64::MAX);
+        self.next_check = self.debug.nodes.saturating_add(CHECK_INTERVAL).min(node_limit);
+
+        if timer.elapsed().as_millis() as u32 >= self.next_report {
+            self.next_report += REPORT_INTERVAL;
+            println!("info depth {} seldepth {} {}", self.search_depth, self.debug.seldepth, self.format_stats(timer));
+        }
+
+        let elapsed = if self.time.is_some() { self.time_used(timer) } else { None };
+        let out_of_time = self.time.as_ref().zip(elapsed).is_some_and(|(time, elapsed)| time.hard_limit_reached(elapsed));
+        let out_of_nodes = self.limits.nodes.is_some_and(|nodes| self.debug.nodes >= nodes);
+        if out_of_time || out_of_nodes {
+            self.stop_search.store(true, Ordering::Relaxed);
+        }
+        out_of_time || out_of_nodes
+    }
+
+    /// Formats a score for `info`
+    ///
+    /// scores past the tablebase wins are mates, given in moves rather than centipawns
+    fn format_score(&self, score: Eval) -> String {
+        match CHECKMATE_VALUE - score.abs() {
+            plies if score.abs() > TB_WIN_VALUE => format!("mate {}", (plies + 1) / 2 * score.signum()),
+            _ => format!("cp {}", score),
+        }
+    }
+
+    /// Formats the node count, speed, table usage and time for `info`
+    fn format_stats(&self, timer: &Instant) -> String {
+        let ms = timer.elapsed().as_millis();
+        format!("nodes {} nps {} hashfull {} tbhits {} time {}",
+            self.debug.nodes,
+            self.debug.nodes as u128 * 1000 / ms.max(1),
+            self.tt.hashfull(),
+            self.debug.tb_hits,
+            ms,
+        )
+    }
+
+    /// Scores a draw from the side to move's perspective
+    ///
+    /// the root side to move sees draws as `contempt` below equal. a one centipawn jitter
+    /// taken from the node count keeps repetitions from all scoring exactly the same
+    fn draw_score(&self) -> i32 {
+        let jitter = (self.debug.nodes & 2) as i32 - 1;
+        if self.board.side_to_move() == self.root_color {
+            -self.contempt + jitter
+        } else {
+            self.contempt + jitter
+        }
+    }
+
+    /// Looks up the static evaluation in the eval cache, evaluating lazily on a miss
+    fn static_eval(&mut self, alpha: i32, beta: i32) -> i32 {
+        // only a blunder by the defending side loses, and the search still finds any mate
+        if self.board.material_draw() == Some(MaterialDraw::NoForcedWin) {
+            return self.draw_score();
+        }
+
+        let key = self.board.hash();
+        if let Some(score) = self.eval_cache.get(key) {
+            return score;
+        }
+
+        let (score, exact) = evaluate_lazy(&self.board, alpha, beta);
+        if exact {
+            self.eval_cache.insert(key, score);
+        }
+        score
+    }
+
+    /// Makes `mv` followed by the line from the next ply the principal variation at `ply`
+    fn update_pv(&mut self, ply: usize, mv: Move) {
+        let (lines, next) = self.pv_table.split_at_mut(ply + 1);
+        let line = &mut lines[ply];
+        line.clear();
+        line.push(mv);
+        line.extend_from_slice(&next[0]);
+    }
+
+    /// Extends a root line cut short by a transposition table cutoff with the table's best moves
+    ///
+    /// the walk stops at the search depth, at a repeated position, or at a move that isn't legal
+    /// because the entry belongs to another position with the same index
+    fn extend_pv(&mut self, mut line: Vec<Move>) -> Vec<Move> {
+        for &mv in &line {
+            self.board.make_move(mv);
+        }
+        while line.len() < self.search_depth as usize && !self.board.is_repeated() {
             let tt_entry = self.tt.get(self.board.hash()).borrow();
-            let pv_move: Move = tt_entry.best_move.into();
-            if tt_entry.key == self.board.hash() && pv_move != Move::default() {
-                pv_line.push_str(&format!("{} ", pv_move));
-                self.board.make_move(pv_move);
-                move_count += 1;
-            } else {
+            let (key, pv_move): (u64, Move) = (tt_entry.key, tt_entry.best_move.into());
+            if key != self.board.hash() || !self.board.legal_moves().any(|mv| mv == pv_move) {
                 break;
             }
+            line.push(pv_move);
+            self.board.make_move(pv_move);
         }
-        while move_count > 0 {
+        for _ in 0..line.len() {
             self.board.undo_move();
-            move_count -= 1;
         }
 
-        pv_line
+        line
+    }
+}
+
+impl Default for SearchContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sets `stop` once `maximum` milliseconds have passed after pondering, unless the search finishes first
+fn watchdog(maximum: u32, stop: &AtomicBool, pondering: &AtomicBool, finished: Receiver<()>) {
+    while pondering.load(Ordering::Relaxed) {
+        if finished.recv_timeout(Duration::from_millis(1)) != Err(RecvTimeoutError::Timeout) {
+            return;
+        }
+    }
+    if finished.recv_timeout(Duration::from_millis(maximum as u64)) == Err(RecvTimeoutError::Timeout) {
+        stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Converts a tablebase outcome at the given ply into a mate score
+fn tablebase_score(outcome: Outcome, ply: u8) -> i32 {
+    match outcome {
+        Outcome::Win(dist) => CHECKMATE_VALUE - ply as i32 - dist as i32,
+        Outcome::Draw => 0,
+        Outcome::Loss(dist) => -CHECKMATE_VALUE + ply as i32 + dist as i32,
     }
 }
 
+/// Converts a Syzygy result at the given ply into a score, treating cursed wins and
+/// blessed losses as near draws when the fifty move rule applies
+fn syzygy_score(wdl: Wdl, ply: u8, rule50: bool) -> i32 {
+    let draw_score = rule50 as i32;
+    match wdl as i32 {
+        wdl if wdl > draw_score => TB_WIN_VALUE - ply as i32,
+        wdl if wdl < -draw_score => -TB_WIN_VALUE + ply as i32,
+        wdl => 2 * wdl * draw_score,
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use chess::Square;
-    use crate::board::Board;
+    use std::{str::FromStr, thread, time::Duration};
+    use crate::{board::Board, tablebase::{Material, Table}};
 
     #[test]
     fn test_checkmate_position() {
         let mut sc = SearchContext::new();
-        sc.board = Board::from_fen("6k1/8/R5K1/8/8/8/8/8 w - - 0 1");
-        let mv = sc.search(20, true, false);
+        sc.board = Board::from_fen("6k1/8/R5K1/8/8/8/8/8 w - - 0 1").unwrap();
+        let mv = sc.search(SearchLimits::move_time(20), false);
         assert_eq!(mv, Move::new(Square::A6, Square::A8, None));
     }
 
     #[test]
     fn test_repeated_draw() {
         let mut sc = SearchContext::new();
-        sc.board = Board::from_fen("r5k1/5p2/3n1QpK/8/8/8/8/8 w - - 0 1");
+        sc.board = Board::from_fen("r5k1/5p2/3n1QpK/8/8/8/8/8 w - - 0 1").unwrap();
         sc.board.make_move(Move::new(Square::F6, Square::E7, None));
         sc.board.make_move(Move::new(Square::G8, Square::H8, None));
         sc.board.make_move(Move::new(Square::E7, Square::F6, None));
         sc.board.make_move(Move::new(Square::H8, Square::G8, None));
         sc.board.make_move(Move::new(Square::F6, Square::E7, None));
         sc.board.make_move(Move::new(Square::G8, Square::H8, None));
-        let mv = sc.search(20, true, false);
+        let mv = sc.search(SearchLimits::move_time(20), false);
         // white cannot play Qe7 (attempting M2) to avoid a draw
         assert_ne!(mv, Move::new(Square::F6, Square::E7, None));
     }
@@ -315,18 +764,263 @@ mod tests {
     #[test]
     fn test_fifty_move_draw() {
         let mut sc = SearchContext::new();
-        sc.board = Board::from_fen("8/1R5p/6k1/8/8/8/1R4K1/8 w - - 99 60");
-        let mv = sc.search(20, true, false);
+        sc.board = Board::from_fen("8/1R5p/6k1/8/8/8/1R4K1/8 w - - 99 60").unwrap();
+        let mv = sc.search(SearchLimits::move_time(20), false);
         // white must sacrifice material to avoid a draw
         assert_eq!(mv, Move::new(Square::B7, Square::H7, None));
     }
 
+    #[test]
+    fn test_tablebase_root() {
+        let fen = "8/8/8/8/8/2k5/8/K6R w - - 0 1";
+        let mut tablebases = Tablebases::default();
+        tablebases.insert(Table::generate(Material::from_str("KRvK").unwrap(), &tablebases).unwrap());
+        let expected = tablebases.probe(&Board::from_fen(fen).unwrap().position);
+
+        let mut sc = SearchContext::new();
+        sc.tablebases = Arc::new(tablebases);
+        sc.board = Board::from_fen(fen).unwrap();
+        let mv = sc.search(SearchLimits::move_time(20), false);
+        // the line is reported as the mate the tables know, ending in checkmate
+        let Some(Outcome::Win(dist)) = expected else { panic!("{:?}", expected) };
+        assert_eq!(sc.format_score(sc.lines()[0].score), format!("mate {}", dist.div_ceil(2)));
+        assert_eq!(sc.lines()[0].moves.len(), dist as usize);
+        assert!(sc.ponder_move().is_some());
+        let mut end = sc.board.clone();
+        sc.lines()[0].moves.iter().for_each(|&mv| end.make_move(mv));
+        assert!(end.legal_moves().next().is_none() && end.checkers().popcnt() > 0);
+
+        sc.board.make_move(mv);
+        // the chosen move must keep the shortest mate
+        assert_eq!(sc.tablebases.probe(&sc.board.position).map(Outcome::parent), expected);
+    }
+
+    #[test]
+    fn test_syzygy_root() {
+        let dir = std::env::temp_dir().join("engine-search-syzygy");
+        std::fs::create_dir_all(&dir).unwrap();
+        crate::syzygy::tests::write_krvk(&dir);
+
+        let mut sc = SearchContext::new();
+        sc.syzygy = Arc::new(Syzygy::load(dir.to_str().unwrap()).unwrap());
+        sc.board = Board::from_fen("8/8/8/8/8/2k5/8/K6R w - - 90 1").unwrap();
+        let mv = sc.search(SearchLimits::move_time(20), false);
+        let fastest = sc.syzygy.rank_root_moves(&sc.board, true).unwrap().best_moves();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // near the fifty move limit the win has to be completed as fast as possible
+        assert!(fastest.contains(&mv));
+        assert!(sc.debug.tb_hits > 0);
+    }
+
+    /// Searches a position to a fixed depth and returns the score for the side to move
+    fn fixed_depth_score(sc: &mut SearchContext, depth: u8) -> i32 {
+        sc.root_color = sc.board.side_to_move();
+        sc.search_depth = depth;
+        sc.nega_max(&Instant::now(), depth, i32::MIN + 1, i32::MAX)
+    }
+
+    #[test]
+    fn test_contempt() {
+        let forced_draws = [
+            // every move leaves bare kings
+            "K7/8/8/8/8/8/8/k7 w - - 0 1",
+            "K7/8/8/8/8/8/8/k7 b - - 0 1",
+            // every move reaches the fifty move limit
+            "8/8/8/8/8/2k5/8/K6R w - - 99 80",
+            // stalemate
+            "k7/P7/1K6/8/8/8/8/8 b - - 0 1",
+        ];
+        for fen in forced_draws {
+            for contempt in [-50, 0, 30] {
+                let mut sc = SearchContext::new();
+                sc.board = Board::from_fen(fen).unwrap();
+                sc.contempt = contempt;
+                let score = fixed_depth_score(&mut sc, 3);
+                // draws are worth `contempt` less to the root side, give or take the jitter
+                assert!((score + contempt).abs() <= 1, "{} contempt {} scored {}", fen, contempt, score);
+            }
+        }
+    }
+
     #[test]
     fn test_insufficient_material() {
         let mut sc = SearchContext::new();
-        sc.board = Board::from_fen("5Nbk/4KP2/8/8/8/8/8/8 w - - 0 1");
-        let mv = sc.search(20, true, false);
+        sc.board = Board::from_fen("5Nbk/4KP2/8/8/8/8/8/8 w - - 0 1").unwrap();
+        let mv = sc.search(SearchLimits::move_time(20), false);
         // white must not trade to avoid a draw
         assert_eq!(mv, Move::new(Square::F8, Square::G6, None));
     }
+
+    #[test]
+    fn test_depth_limit() {
+        let mut sc = SearchContext::new();
+        sc.search(SearchLimits::depth(3), false);
+        assert_eq!(sc.search_depth, 3);
+    }
+
+    #[test]
+    fn test_node_limit() {
+        let mut sc = SearchContext::new();
+        let mv = sc.search(SearchLimits { nodes: Some(500), ..SearchLimits::default() }, false);
+        assert_eq!(sc.debug.nodes, 500);
+        assert!(sc.board.legal_moves().any(|legal| legal == mv));
+    }
+
+    #[test]
+    fn test_mate_limit() {
+        // without a time or depth limit, only finding the mate ends the search
+        let mut sc = SearchContext::new();
+        sc.board = Board::from_fen("6k1/8/R5K1/8/8/8/8/8 w - - 0 1").unwrap();
+        let mv = sc.search(SearchLimits { mate: Some(1), ..SearchLimits::default() }, false);
+        assert_eq!(mv, Move::new(Square::A6, Square::A8, None));
+        assert!(sc.search_depth <= 2);
+    }
+
+    #[test]
+    fn test_searchmoves() {
+        let a3 = Move::new(Square::A2, Square::A3, None);
+        let mut sc = SearchContext::new();
+        let mv = sc.search(SearchLimits { searchmoves: vec![a3], ..SearchLimits::depth(3) }, false);
+        assert_eq!(mv, a3);
+
+        // the mate is not among the moves to search
+        sc.board = Board::from_fen("6k1/8/R5K1/8/8/8/8/8 w - - 0 1").unwrap();
+        let searchmoves = vec![Move::new(Square::A6, Square::A7, None), Move::new(Square::G6, Square::F6, None)];
+        let mv = sc.search(SearchLimits { searchmoves: searchmoves.clone(), ..SearchLimits::depth(3) }, false);
+        assert!(searchmoves.contains(&mv));
+
+        // illegal moves are ignored
+        let mv = sc.search(SearchLimits { searchmoves: vec![a3], ..SearchLimits::depth(3) }, false);
+        assert_eq!(mv, Move::new(Square::A6, Square::A8, None));
+    }
+
+    #[test]
+    fn test_ponder_move() {
+        let mut sc = SearchContext::new();
+        let mv = sc.search(SearchLimits::depth(4), false);
+        let reply = sc.ponder_move().unwrap();
+        sc.board.make_move(mv);
+        assert!(sc.board.legal_moves().any(|legal| legal == reply));
+    }
+
+    #[test]
+    fn test_ponderhit() {
+        let mut sc = SearchContext::new();
+        sc.pondering.store(true, Ordering::Relaxed);
+        let pondering = sc.pondering.clone();
+        let search = thread::spawn(move || sc.search(SearchLimits::move_time(20), false));
+
+        // the time limit doesn't apply while pondering
+        thread::sleep(Duration::from_millis(200));
+        assert!(!search.is_finished());
+
+        pondering.store(false, Ordering::Relaxed);
+        let timer = Instant::now();
+        let mv = search.join().unwrap();
+        assert!(timer.elapsed() < Duration::from_secs(1));
+        assert_ne!(mv, Move::default());
+    }
+
+    #[test]
+    fn test_watchdog() {
+        // a search still running at the maximum time is stopped
+        let stop = AtomicBool::new(false);
+        let (_running, finished) = mpsc::channel();
+        watchdog(10, &stop, &AtomicBool::new(false), finished);
+        assert!(stop.load(Ordering::Relaxed));
+
+        // a finished search is left alone, even while pondering
+        let stop = AtomicBool::new(false);
+        let (done, finished) = mpsc::channel::<()>();
+        drop(done);
+        let timer = Instant::now();
+        watchdog(1000, &stop, &AtomicBool::new(true), finished);
+        assert!(!stop.load(Ordering::Relaxed));
+        assert!(timer.elapsed() < Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_multi_pv() {
+        // both rooks mate on the back rank, anything else doesn't
+        let mut sc = SearchContext::new();
+        sc.multi_pv = 3;
+        sc.board = Board::from_fen("6k1/5ppp/8/8/8/8/5PPP/R2R2K1 w - - 0 1").unwrap();
+        let mv = sc.search(SearchLimits::depth(3), false);
+        let lines = sc.lines();
+        assert_eq!(lines.len(), 3);
+        let mates = [Move::new(Square::A1, Square::A8, None), Move::new(Square::D1, Square::D8, None)];
+        assert!(mates.contains(&mv));
+        assert!(mates.contains(&lines[0].moves[0]) && mates.contains(&lines[1].moves[0]));
+        assert_ne!(lines[0].moves[0], lines[1].moves[0]);
+        assert_eq!(lines[1].score, CHECKMATE_VALUE - 1);
+        assert!(lines[2].score < lines[1].score);
+
+        // taking the queen beats taking the rook, which beats everything else
+        sc.board = Board::from_fen("k7/8/8/3q4/8/8/3Q3r/K7 w - - 0 1").unwrap();
+        sc.search(SearchLimits::depth(4), false);
+        let lines = sc.lines();
+        assert_eq!(lines[0].moves[0], Move::new(Square::D2, Square::D5, None));
+        assert_eq!(lines[1].moves[0], Move::new(Square::D2, Square::H2, None));
+        assert!(lines.windows(2).all(|pair| pair[0].score >= pair[1].score));
+
+        // never more lines than legal moves
+        sc.multi_pv = 5;
+        sc.board = Board::from_fen("k7/8/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        sc.search(SearchLimits::depth(2), false);
+        assert_eq!(sc.lines().len(), 3);
+    }
+
+    #[test]
+    fn test_info_stats() {
+        let mut sc = SearchContext::new();
+        sc.board = Board::from_fen("r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4").unwrap();
+        sc.search(SearchLimits::depth(3), false);
+        // captures are followed past the nominal depth
+        assert!(sc.debug.seldepth > 3);
+        assert_eq!(sc.format_score(-25), "cp -25");
+        assert_eq!(sc.format_score(TB_WIN_VALUE), format!("cp {}", TB_WIN_VALUE));
+        assert_eq!(sc.format_score(CHECKMATE_VALUE - 1), "mate 1");
+        assert_eq!(sc.format_score(CHECKMATE_VALUE - 4), "mate 2");
+        assert_eq!(sc.format_score(-CHECKMATE_VALUE + 2), "mate -1");
+    }
+
+    #[test]
+    fn test_pv_line() {
+        // the line is playable to the full depth
+        let mut sc = SearchContext::new();
+        sc.search(SearchLimits::depth(5), false);
+        let line = sc.lines()[0].moves.clone();
+        assert_eq!(line.len(), 5);
+        let mut board = sc.board.clone();
+        for mv in line {
+            assert!(board.legal_moves().any(|legal| legal == mv));
+            board.make_move(mv);
+        }
+    }
+
+    #[test]
+    fn test_repetition_pv() {
+        // table entries that lead back to the start position
+        let mut sc = SearchContext::new();
+        let knights = [
+            Move::new(Square::G1, Square::F3, None),
+            Move::new(Square::G8, Square::F6, None),
+            Move::new(Square::F3, Square::G1, None),
+            Move::new(Square::F6, Square::G8, None),
+        ];
+        let start = sc.board.hash();
+        for mv in knights {
+            sc.tt.insert(sc.board.hash(), 0, 10, Bound::Exact, mv);
+            sc.board.make_move(mv);
+        }
+        sc.board = Board::new();
+
+        sc.search_depth = 20;
+        assert_eq!(sc.extend_pv(Vec::new()), knights);
+        assert_eq!(sc.extend_pv(knights[..2].to_vec()), knights);
+        assert_eq!(sc.board.hash(), start);
+        sc.search_depth = 3;
+        assert_eq!(sc.extend_pv(Vec::new()), knights[..3]);
+    }
 }
@@ -0,0 +1,1697 @@
+use std::{collections::HashMap, fs, io::{self, Read}, ops::Neg, path::{Path, PathBuf}, sync::OnceLock};
+
+use chess::{CastleRights, Color, MoveGen, Piece, EMPTY};
+
+use crate::board::{Board, Move};
+
+pub const WDL_EXTENSION: &str = "rtbw";
+pub const DTZ_EXTENSION: &str = "rtbz";
+
+/// largest table the reader can index
+pub const MAX_PIECES: usize = 7;
+
+/// rank given to root moves that win (or lose) within the fifty move rule
+const MAX_DTZ: i32 = 1 << 18;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+/* Header Flags */
+const SPLIT: u8 = 1;
+const HAS_PAWNS: u8 = 2;
+
+/* Subtable Flags */
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+/// Result of a WDL probe for the side to move, where cursed wins and blessed losses
+/// are wins and losses that come too late to beat the fifty move rule
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Option<Self> {
+        match value {
+            -2 => Some(Wdl::Loss),
+            -1 => Some(Wdl::BlessedLoss),
+            0 => Some(Wdl::Draw),
+            1 => Some(Wdl::CursedWin),
+            2 => Some(Wdl::Win),
+            _ => None,
+        }
+    }
+
+    fn signum(self) -> i32 {
+        (self as i32).signum()
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Wdl::from_value(-(self as i32)).unwrap()
+    }
+}
+
+/// Root moves ranked by their tablebase result, best first
+pub struct RootRanking {
+    pub moves: Vec<(Move, i32)>,
+    /// false if the ranking fell back to WDL tables because a DTZ table was missing
+    pub dtz: bool,
+}
+
+impl RootRanking {
+    /// Returns the moves sharing the best rank
+    pub fn best_moves(&self) -> Vec<Move> {
+        let best = self.moves[0].1;
+        self.moves.iter().take_while(|(_, rank)| *rank == best).map(|&(mv, _)| mv).collect()
+    }
+
+    pub fn best_rank(&self) -> i32 {
+        self.moves[0].1
+    }
+}
+
+const fn off_diagonal(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+/// squares below the a1-h8 diagonal, numbered 0..28
+const MAP_B1H1H7: [u64; 64] = {
+    let mut map = [0; 64];
+    let (mut code, mut sq) = (0, 0);
+    while sq < 64 {
+        if off_diagonal(sq) < 0 {
+            map[sq] = code;
+            code += 1;
+        }
+        sq += 1;
+    }
+    map
+};
+
+/// a1-d1-d4 triangle numbered 0..10, with the diagonal squares last
+const MAP_A1D1D4: [u64; 64] = {
+    let mut map = [0; 64];
+    let (mut code, mut sq) = (0, 0);
+    while sq < 64 {
+        if off_diagonal(sq) < 0 && sq % 8 < 4 && sq / 8 < 4 {
+            map[sq] = code;
+            code += 1;
+        }
+        sq += 1;
+    }
+    sq = 0;
+    while sq < 64 {
+        if off_diagonal(sq) == 0 && sq % 8 < 4 {
+            map[sq] = code;
+            code += 1;
+        }
+        sq += 1;
+    }
+    map
+};
+
+/// the 462 legal placements of two kings with the first on the a1-d1-d4 triangle,
+/// keeping the second below the diagonal when the first is on it
+const MAP_KK: [[u64; 64]; 10] = {
+    let mut map = [[0; 64]; 10];
+    let mut both_on_diagonal = [(0, 0); 64];
+    let mut diagonal_count = 0;
+    let mut code = 0;
+    let mut idx = 0;
+    while idx < 10 {
+        let mut s1 = 0;
+        while s1 < 28 {
+            if off_diagonal(s1) <= 0 && s1 % 8 < 4 && MAP_A1D1D4[s1] == idx as u64 {
+                let mut s2 = 0;
+                while s2 < 64 {
+                    let adjacent = (s1 % 8).abs_diff(s2 % 8) <= 1 && (s1 / 8).abs_diff(s2 / 8) <= 1;
+                    if adjacent || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0) {
+                        // illegal, or mirrored by the diagonal
+                    } else if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal[diagonal_count] = (idx, s2);
+                        diagonal_count += 1;
+                    } else {
+                        map[idx][s2] = code;
+                        code += 1;
+                    }
+                    s2 += 1;
+                }
+            }
+            s1 += 1;
+        }
+        idx += 1;
+    }
+    let mut i = 0;
+    while i < diagonal_count {
+        let (idx, s2) = both_on_diagonal[i];
+        map[idx][s2] = code;
+        code += 1;
+        i += 1;
+    }
+    map
+};
+
+/// `BINOMIAL[k][n]` ways to choose k squares out of n
+const BINOMIAL: [[u64; 64]; MAX_PIECES + 1] = {
+    let mut binomial = [[0; 64]; MAX_PIECES + 1];
+    binomial[0][0] = 1;
+    let mut n = 1;
+    while n < 64 {
+        let mut k = 0;
+        while k <= MAX_PIECES && k <= n {
+            binomial[k][n] = if k > 0 { binomial[k - 1][n - 1] } else { 0 }
+                + if k < n { binomial[k][n - 1] } else { 0 };
+            k += 1;
+        }
+        n += 1;
+    }
+    binomial
+};
+
+/// pawn squares a2-h7 numbered 0..48, highest for the square closest to the edge
+/// and the first rank, which is the one that leads a group of pawns
+const MAP_PAWNS: [usize; 64] = {
+    let mut map = [0; 64];
+    let mut available: usize = 47;
+    let mut file = 0;
+    while file < 4 {
+        let mut rank = 1;
+        while rank < 7 {
+            let sq = rank * 8 + file;
+            map[sq] = available;
+            map[sq ^ 7] = available - 1;
+            available = available.saturating_sub(2);
+            rank += 1;
+        }
+        file += 1;
+    }
+    map
+};
+
+/// index of the leading pawn square and number of leading pawn placements, by pawn
+/// count and by the file of the leading pawn
+const LEAD_PAWNS: ([[u64; 64]; MAX_PIECES], [[u64; 4]; MAX_PIECES]) = {
+    let mut lead_pawn_idx = [[0; 64]; MAX_PIECES];
+    let mut lead_pawns_size = [[0; 4]; MAX_PIECES];
+    let mut count = 1;
+    while count < MAX_PIECES {
+        let mut file = 0;
+        while file < 4 {
+            let mut idx = 0;
+            let mut rank = 1;
+            while rank < 7 {
+                let sq = rank * 8 + file;
+                lead_pawn_idx[count][sq] = idx;
+                idx += BINOMIAL[count - 1][MAP_PAWNS[sq]];
+                rank += 1;
+            }
+            lead_pawns_size[count][file] = idx;
+            file += 1;
+        }
+        count += 1;
+    }
+    (lead_pawn_idx, lead_pawns_size)
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Kind {
+    Wdl,
+    Dtz,
+}
+
+/// Decoding state of one subtable: one side to move for one file of the leading pawn
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    pieces: [u8; MAX_PIECES],
+    group_len: [usize; MAX_PIECES + 1],
+    group_idx: [u64; MAX_PIECES + 1],
+
+    single_value: u16,
+    block_size: usize,
+    span: u64,
+    sparse_index_size: usize,
+    blocks_num: usize,
+    block_length_size: usize,
+    min_sym_len: usize,
+    lowest_sym: Vec<u16>,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    btree: Vec<[u16; 2]>,
+
+    /* Offsets into the file */
+    sparse_index: usize,
+    block_lengths: usize,
+    data: usize,
+    map_idx: [usize; 4],
+}
+
+/// Location of a position inside a table
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Slot {
+    Entry { file: usize, side: usize, idx: u64 },
+    /// one-sided DTZ tables only store one side to move
+    OtherSide,
+}
+
+/// Bounds checked little-endian reader over a table file
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn u8(&mut self) -> io::Result<u8> {
+        let byte = *self.bytes.get(self.pos).ok_or_else(truncated)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes([self.u8()?, self.u8()?, self.u8()?, self.u8()?]))
+    }
+
+    fn skip(&mut self, len: usize) -> io::Result<()> {
+        self.pos += len;
+        if self.pos > self.bytes.len() {
+            return Err(truncated());
+        }
+        Ok(())
+    }
+
+    fn align(&mut self, to: usize) {
+        self.pos = self.pos.next_multiple_of(to);
+    }
+}
+
+fn truncated() -> io::Error {
+    invalid("truncated table")
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// One WDL or DTZ file, kept in memory and decoded on demand
+struct Table {
+    kind: Kind,
+    bytes: Vec<u8>,
+    symmetric: bool,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    piece_count: usize,
+    pawn_count: [usize; 2],
+    sides: usize,
+    pairs: Vec<Vec<PairsData>>,
+}
+
+impl Table {
+    fn parse(kind: Kind, name: &str, bytes: Vec<u8>) -> io::Result<Self> {
+        let (white, black) = parse_name(name).ok_or_else(|| invalid("not a table name"))?;
+        let pawns = Piece::Pawn.to_index();
+
+        let lead_white = black[pawns] == 0 || (white[pawns] > 0 && black[pawns] >= white[pawns]);
+        let mut table = Self {
+            kind,
+            bytes,
+            symmetric: white == black,
+            has_pawns: white[pawns] + black[pawns] > 0,
+            has_unique_pieces: (0..Piece::King.to_index()).any(|p| white[p] == 1 || black[p] == 1),
+            piece_count: (white.iter().sum::<u8>() + black.iter().sum::<u8>()) as usize,
+            pawn_count: if lead_white {
+                [white[pawns] as usize, black[pawns] as usize]
+            } else {
+                [black[pawns] as usize, white[pawns] as usize]
+            },
+            sides: 0,
+            pairs: Vec::new(),
+        };
+        if table.piece_count > MAX_PIECES {
+            return Err(invalid("too many pieces"));
+        }
+        table.sides = if kind == Kind::Wdl && !table.symmetric { 2 } else { 1 };
+
+        let magic = if kind == Kind::Wdl { WDL_MAGIC } else { DTZ_MAGIC };
+        if table.bytes.len() < 5 || table.bytes[..4] != magic {
+            return Err(invalid("not a Syzygy table"));
+        }
+        let header = table.bytes[4];
+        if (header & HAS_PAWNS != 0) != table.has_pawns || (header & SPLIT != 0) == table.symmetric {
+            return Err(invalid("header does not match the table name"));
+        }
+
+        let bytes = std::mem::take(&mut table.bytes);
+        let result = table.parse_layout(&bytes);
+        table.bytes = bytes;
+        result.map(|_| table)
+    }
+
+    fn parse_layout(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let files = if self.has_pawns { 4 } else { 1 };
+        let both_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut cursor = Cursor { bytes, pos: 5 };
+
+        /* Piece Order and Groups */
+        self.pairs = vec![vec![PairsData::default(); self.sides]; files];
+        for file in 0..files {
+            let first = cursor.u8()?;
+            let second = if both_pawns { cursor.u8()? } else { 0xFF };
+            let order = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+
+            for k in 0..self.piece_count {
+                let byte = cursor.u8()?;
+                for side in 0..self.sides {
+                    let piece = if side == 0 { byte & 0xF } else { byte >> 4 };
+                    if !(1..=6).contains(&(piece & 7)) {
+                        return Err(invalid("unknown piece"));
+                    }
+                    self.pairs[file][side].pieces[k] = piece;
+                }
+            }
+            for (side, &order) in order.iter().enumerate().take(self.sides) {
+                self.set_groups(file, side, order)?;
+            }
+        }
+        cursor.align(2);
+
+        /* Compression Parameters */
+        for file in 0..files {
+            for side in 0..self.sides {
+                set_sizes(&mut self.pairs[file][side], &mut cursor)?;
+            }
+        }
+
+        /* DTZ Value Maps */
+        if self.kind == Kind::Dtz {
+            for file in 0..files {
+                let d = &mut self.pairs[file][0];
+                if d.flags & MAPPED == 0 {
+                    continue;
+                }
+                for i in 0..4 {
+                    if d.flags & WIDE != 0 {
+                        cursor.align(2);
+                        let len = cursor.u16()? as usize;
+                        d.map_idx[i] = cursor.pos;
+                        cursor.skip(2 * len)?;
+                    } else {
+                        let len = cursor.u8()? as usize;
+                        d.map_idx[i] = cursor.pos;
+                        cursor.skip(len)?;
+                    }
+                }
+            }
+            cursor.align(2);
+        }
+
+        /* Sparse Index, Block Lengths and Compressed Data */
+        for file in 0..files {
+            for side in 0..self.sides {
+                let d = &mut self.pairs[file][side];
+                d.sparse_index = cursor.pos;
+                cursor.skip(d.sparse_index_size * 6)?;
+            }
+        }
+        for file in 0..files {
+            for side in 0..self.sides {
+                let d = &mut self.pairs[file][side];
+                d.block_lengths = cursor.pos;
+                cursor.skip(d.block_length_size * 2)?;
+            }
+        }
+        for file in 0..files {
+            for side in 0..self.sides {
+                let d = &mut self.pairs[file][side];
+                cursor.align(64);
+                d.data = cursor.pos;
+                cursor.skip(d.blocks_num * d.block_size)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits the pieces of a subtable into groups and computes the size of each group
+    ///
+    /// Pieces are encoded as `g1 * N(g2) * N(g3) + g2 * N(g3) + g3`, where N(g) is the
+    /// number of placements of group g, in the group order stored with the table.
+    fn set_groups(&mut self, file: usize, side: usize, order: [u8; 2]) -> io::Result<()> {
+        let both_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let d = &mut self.pairs[file][side];
+
+        let mut n = 0;
+        let mut first_len = if self.has_pawns { 0 } else if self.has_unique_pieces { 3 } else { 2 };
+        d.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+        if self.has_pawns && d.group_len[0] >= MAX_PIECES {
+            return Err(invalid("too many leading pawns"));
+        }
+
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if both_pawns { d.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    LEAD_PAWNS.1[d.group_len[0]][file]
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                d.group_idx[1] = idx;
+                idx *= BINOMIAL[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= BINOMIAL[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+        Ok(())
+    }
+
+    /// Maps a position onto its subtable and index
+    ///
+    /// Tables store the stronger side (the first side of the name) as white, so positions
+    /// where black is stronger, and black to move in symmetric tables, are colour flipped.
+    fn slot(&self, pos: &chess::Board, black_stronger: bool) -> Option<Slot> {
+        let flip = black_stronger || (self.symmetric && pos.side_to_move() == Color::Black);
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = flip as usize ^ pos.side_to_move().to_index();
+
+        let mut squares = [0usize; MAX_PIECES];
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_count = 0;
+        let mut lead_pawns = EMPTY;
+        let mut file = 0;
+
+        if pos.combined().popcnt() as usize != self.piece_count {
+            return None;
+        }
+
+        /* Leading Pawns */
+        if self.has_pawns {
+            let lead = self.pairs[0][0].pieces[0] ^ flip_color;
+            let color = if lead & 8 == 0 { Color::White } else { Color::Black };
+            lead_pawns = pos.pieces(Piece::Pawn) & pos.color_combined(color);
+            for sq in lead_pawns {
+                squares[size] = sq.to_index() ^ flip_squares;
+                size += 1;
+            }
+            lead_count = size;
+            if lead_count == 0 {
+                return None;
+            }
+            let leader = (0..lead_count).max_by_key(|&i| MAP_PAWNS[squares[i]]).unwrap();
+            squares.swap(0, leader);
+            file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+
+        // symmetric pawnless DTZ tables hold both sides through the colour flip
+        let one_sided = !self.symmetric || self.has_pawns;
+        if self.kind == Kind::Dtz && one_sided && self.pairs[file][0].flags & STM != stm as u8 {
+            return Some(Slot::OtherSide);
+        }
+
+        for sq in *pos.combined() ^ lead_pawns {
+            let piece = pos.piece_on(sq)?;
+            let color = if pos.color_on(sq)? == Color::White { 0 } else { 8 };
+            squares[size] = sq.to_index() ^ flip_squares;
+            pieces[size] = (piece.to_index() as u8 + 1) | (color ^ flip_color);
+            size += 1;
+        }
+
+        let side = stm % self.sides;
+        let d = &self.pairs[file][side];
+
+        // reorder the pieces to follow the sequence stored in the table
+        for i in lead_count..size - 1 {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // the leading piece is kept on files a-d
+        if squares[0] % 8 > 3 {
+            for sq in &mut squares[..size] {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx;
+        if self.has_pawns {
+            /* Leading Pawn Group */
+            idx = LEAD_PAWNS.0[lead_count][squares[0]];
+            squares[1..lead_count].sort_by_key(|&sq| MAP_PAWNS[sq]);
+            for (i, &sq) in squares.iter().enumerate().take(lead_count).skip(1) {
+                idx += BINOMIAL[i][MAP_PAWNS[sq]];
+            }
+        } else {
+            // pawnless positions are also kept below the fifth rank and the a1-h8 diagonal
+            if squares[0] / 8 > 3 {
+                for sq in &mut squares[..size] {
+                    *sq ^= 56;
+                }
+            }
+            for i in 0..d.group_len[0] {
+                let off = off_diagonal(squares[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    for sq in &mut squares[i..size] {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            /* Leading Piece Group */
+            if self.has_unique_pieces {
+                let [s0, s1, s2] = [squares[0], squares[1], squares[2]];
+                let adjust1 = (s1 > s0) as usize;
+                let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+                let rank = |sq: usize| (sq / 8) as u64;
+
+                idx = if off_diagonal(s0) != 0 {
+                    (MAP_A1D1D4[s0] * 63 + (s1 - adjust1) as u64) * 62 + (s2 - adjust2) as u64
+                } else if off_diagonal(s1) != 0 {
+                    (6 * 63 + rank(s0) * 28 + MAP_B1H1H7[s1]) * 62 + (s2 - adjust2) as u64
+                } else if off_diagonal(s2) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62
+                        + rank(s0) * 7 * 28
+                        + (rank(s1) - adjust1 as u64) * 28
+                        + MAP_B1H1H7[s2]
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28
+                        + rank(s0) * 7 * 6
+                        + (rank(s1) - adjust1 as u64) * 6
+                        + (rank(s2) - adjust2 as u64)
+                };
+            } else {
+                idx = MAP_KK[MAP_A1D1D4[squares[0]] as usize][squares[1]];
+            }
+        }
+
+        /* Remaining Groups */
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&s| sq > s).count();
+                n += BINOMIAL[i + 1][sq - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        Some(Slot::Entry { file, side, idx })
+    }
+
+    /// Decodes the value stored at an index of a subtable
+    ///
+    /// Values are Huffman coded symbols in fixed size blocks, where each symbol expands
+    /// into a run of values by recursive pairing. The sparse index locates a block near
+    /// the wanted index, and the block lengths are walked from there.
+    fn decompress(&self, d: &PairsData, idx: u64) -> Option<u16> {
+        if d.flags & SINGLE_VALUE != 0 {
+            return Some(d.single_value);
+        }
+
+        let k = (idx / d.span) as usize;
+        if k >= d.sparse_index_size {
+            return None;
+        }
+        let entry = d.sparse_index + 6 * k;
+        let mut block = self.read_le(entry, 4)? as usize;
+        let mut offset = self.read_le(entry + 4, 2)? as i64;
+        offset += (idx % d.span) as i64 - (d.span / 2) as i64;
+
+        let block_length = |block: usize| -> Option<i64> {
+            if block >= d.block_length_size {
+                return None;
+            }
+            Some(self.read_le(d.block_lengths + 2 * block, 2)? as i64)
+        };
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+
+        /* Find the Symbol */
+        let mut ptr = d.data + block * d.block_size;
+        let mut buf = self.read_be(ptr, 8);
+        ptr += 8;
+        let mut buf_size = 64;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf < d.base64[len] {
+                len += 1;
+            }
+            sym = ((buf - d.base64[len]) >> (64 - len - d.min_sym_len)) as usize + d.lowest_sym[len] as usize;
+            let sym_len = *d.symlen.get(sym)? as i64;
+            if offset < sym_len + 1 {
+                break;
+            }
+            offset -= sym_len + 1;
+            len += d.min_sym_len;
+            buf <<= len;
+            buf_size -= len;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= self.read_be(ptr, 4) << (64 - buf_size);
+                ptr += 4;
+            }
+        }
+
+        /* Expand the Symbol */
+        while d.symlen[sym] != 0 {
+            let [left, right] = d.btree[sym];
+            if offset < d.symlen[left as usize] as i64 + 1 {
+                sym = left as usize;
+            } else {
+                offset -= d.symlen[left as usize] as i64 + 1;
+                sym = right as usize;
+            }
+        }
+        Some(d.btree[sym][0])
+    }
+
+    fn read_le(&self, at: usize, len: usize) -> Option<u64> {
+        let bytes = self.bytes.get(at..at + len)?;
+        Some(bytes.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u64))
+    }
+
+    /// Reads a big-endian value, treating bytes past the end of the file as zero
+    fn read_be(&self, at: usize, len: usize) -> u64 {
+        (at..at + len).fold(0, |acc, i| (acc << 8) | *self.bytes.get(i).unwrap_or(&0) as u64)
+    }
+
+    fn probe_wdl(&self, pos: &chess::Board, black_stronger: bool) -> Option<Wdl> {
+        match self.slot(pos, black_stronger)? {
+            Slot::Entry { file, side, idx } => {
+                let value = self.decompress(&self.pairs[file][side], idx)?;
+                Wdl::from_value(value as i32 - 2)
+            },
+            Slot::OtherSide => None,
+        }
+    }
+
+    /// Returns the stored distance to zeroing in plies, or `None` for the other side to move
+    fn probe_dtz(&self, pos: &chess::Board, black_stronger: bool, wdl: Wdl) -> Option<Option<i32>> {
+        let Slot::Entry { file, side, idx } = self.slot(pos, black_stronger)? else {
+            return Some(None);
+        };
+        let d = &self.pairs[file][side];
+        let mut value = self.decompress(d, idx)? as i32;
+
+        let flags = self.pairs[file][0].flags;
+        if flags & MAPPED != 0 {
+            let map = self.pairs[file][0].map_idx[match wdl {
+                Wdl::Win | Wdl::Draw => 0,
+                Wdl::Loss => 1,
+                Wdl::CursedWin => 2,
+                Wdl::BlessedLoss => 3,
+            }];
+            value = if flags & WIDE != 0 {
+                self.read_le(map + 2 * value as usize, 2)? as i32
+            } else {
+                self.read_le(map + value as usize, 1)? as i32
+            };
+        }
+
+        // tables store moves rather than plies unless told otherwise
+        if (wdl == Wdl::Win && flags & WIN_PLIES == 0)
+            || (wdl == Wdl::Loss && flags & LOSS_PLIES == 0)
+            || wdl == Wdl::CursedWin
+            || wdl == Wdl::BlessedLoss {
+            value *= 2;
+        }
+        Some(Some(value + 1))
+    }
+}
+
+/// Reads the Huffman code and pairing tree of a subtable
+fn set_sizes(d: &mut PairsData, cursor: &mut Cursor) -> io::Result<()> {
+    d.flags = cursor.u8()?;
+    if d.flags & SINGLE_VALUE != 0 {
+        d.single_value = cursor.u8()? as u16;
+        return Ok(());
+    }
+
+    let tb_size = d.group_idx[d.group_len.iter().position(|&len| len == 0).unwrap()];
+    let block_bits = cursor.u8()?;
+    let span_bits = cursor.u8()?;
+    if block_bits >= 32 || span_bits >= 32 || span_bits == 0 {
+        return Err(invalid("bad block size"));
+    }
+    d.block_size = 1 << block_bits;
+    d.span = 1 << span_bits;
+    d.sparse_index_size = tb_size.div_ceil(d.span) as usize;
+    let padding = cursor.u8()? as usize;
+    d.blocks_num = cursor.u32()? as usize;
+    d.block_length_size = d.blocks_num + padding;
+
+    let max_sym_len = cursor.u8()? as usize;
+    d.min_sym_len = cursor.u8()? as usize;
+    if d.min_sym_len == 0 || max_sym_len < d.min_sym_len || max_sym_len > 32 {
+        return Err(invalid("bad symbol lengths"));
+    }
+    let lengths = max_sym_len - d.min_sym_len + 1;
+    d.lowest_sym = (0..lengths).map(|_| cursor.u16()).collect::<io::Result<_>>()?;
+
+    // Canonical codes give longer symbols lower values, so base64[i] is the lowest code
+    // of length i + min_sym_len, left aligned to 64 bits
+    d.base64 = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        d.base64[i] = (d.base64[i + 1] + d.lowest_sym[i] as u64)
+            .wrapping_sub(d.lowest_sym[i + 1] as u64) / 2;
+    }
+    for (i, base) in d.base64.iter_mut().enumerate() {
+        *base <<= 64 - i - d.min_sym_len;
+    }
+
+    let sym_count = cursor.u16()? as usize;
+    d.btree = Vec::with_capacity(sym_count);
+    for _ in 0..sym_count {
+        let lr = [cursor.u8()? as u16, cursor.u8()? as u16, cursor.u8()? as u16];
+        d.btree.push([((lr[1] & 0xF) << 8) | lr[0], (lr[2] << 4) | (lr[1] >> 4)]);
+    }
+    cursor.skip(sym_count & 1)?;
+
+    // symlen is the number of values a symbol expands into, minus one
+    d.symlen = vec![0; sym_count];
+    let mut visited = vec![false; sym_count];
+    for sym in 0..sym_count {
+        if !visited[sym] {
+            d.symlen[sym] = set_symlen(d, sym, &mut visited)?;
+        }
+    }
+    Ok(())
+}
+
+fn set_symlen(d: &mut PairsData, sym: usize, visited: &mut [bool]) -> io::Result<u8> {
+    visited[sym] = true;
+    let [left, right] = d.btree[sym].map(|s| s as usize);
+    if right == 0xFFF {
+        return Ok(0);
+    }
+    if left >= d.btree.len() || right >= d.btree.len() {
+        return Err(invalid("bad pairing tree"));
+    }
+    for child in [left, right] {
+        if !visited[child] {
+            d.symlen[child] = set_symlen(d, child, visited)?;
+        }
+    }
+    u8::try_from(d.symlen[left] as usize + d.symlen[right] as usize + 1)
+        .map_err(|_| invalid("bad pairing tree"))
+}
+
+/// Returns the piece counts of each side of a name such as `KRPvKR`, indexed by piece
+fn parse_name(name: &str) -> Option<([u8; 6], [u8; 6])> {
+    let side = |pieces: &str| -> Option<[u8; 6]> {
+        let mut counts = [0; 6];
+        for c in pieces.chars() {
+            let piece = match c {
+                'K' => Piece::King,
+                'Q' => Piece::Queen,
+                'R' => Piece::Rook,
+                'B' => Piece::Bishop,
+                'N' => Piece::Knight,
+                'P' => Piece::Pawn,
+                _ => return None,
+            };
+            counts[piece.to_index()] += 1;
+        }
+        (counts[Piece::King.to_index()] == 1).then_some(counts)
+    };
+    let (white, black) = name.split_once('v')?;
+    Some((side(white)?, side(black)?))
+}
+
+/// Returns one side of a table name, such as `KRP`
+fn side_name(pos: &chess::Board, color: Color) -> String {
+    let mut name = String::from("K");
+    for piece in [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight, Piece::Pawn] {
+        let count = (pos.pieces(piece) & pos.color_combined(color)).popcnt();
+        for _ in 0..count {
+            name.push_str(&piece.to_string(Color::White));
+        }
+    }
+    name
+}
+
+fn is_zeroing(pos: &chess::Board, mv: Move) -> bool {
+    pos.piece_on(mv.get_dest()).is_some() || pos.piece_on(mv.get_source()) == Some(Piece::Pawn)
+}
+
+fn is_capture(pos: &chess::Board, mv: Move) -> bool {
+    pos.piece_on(mv.get_dest()).is_some()
+        || (pos.piece_on(mv.get_source()) == Some(Piece::Pawn) && mv.get_source().get_file() != mv.get_dest().get_file())
+}
+
+/// Distance to zeroing of a position whose best move is a capture or pawn move
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+    }
+}
+
+/// A table file found on disk, read the first time it is probed
+struct TableFile {
+    kind: Kind,
+    name: String,
+    path: PathBuf,
+    table: OnceLock<Option<Table>>,
+}
+
+impl TableFile {
+    fn get(&self) -> Option<&Table> {
+        self.table.get_or_init(|| {
+            fs::read(&self.path).ok().and_then(|bytes| Table::parse(self.kind, &self.name, bytes).ok())
+        }).as_ref()
+    }
+}
+
+/// Syzygy WDL and DTZ tables, probed by material
+#[derive(Default)]
+pub struct Syzygy {
+    wdl: HashMap<String, TableFile>,
+    dtz: HashMap<String, TableFile>,
+    max_pieces: usize,
+}
+
+impl Syzygy {
+    /// Finds the tables in a list of directories separated like `PATH`
+    ///
+    /// Files are only checked for a valid header here; tables are read on first use.
+    pub fn load(paths: &str) -> io::Result<Self> {
+        let mut syzygy = Self::default();
+        for dir in std::env::split_paths(paths) {
+            if dir.as_os_str().is_empty() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else { continue };
+                let (kind, magic) = match path.extension().and_then(|ext| ext.to_str()) {
+                    Some(WDL_EXTENSION) => (Kind::Wdl, WDL_MAGIC),
+                    Some(DTZ_EXTENSION) => (Kind::Dtz, DTZ_MAGIC),
+                    _ => continue,
+                };
+                let Some((white, black)) = parse_name(name) else { continue };
+                let piece_count = (white.iter().sum::<u8>() + black.iter().sum::<u8>()) as usize;
+                if piece_count > MAX_PIECES || !has_magic(&path, magic) {
+                    continue;
+                }
+
+                let file = TableFile { kind, name: name.to_string(), path: path.clone(), table: OnceLock::new() };
+                if kind == Kind::Wdl {
+                    syzygy.max_pieces = syzygy.max_pieces.max(piece_count);
+                    syzygy.wdl.insert(name.to_string(), file);
+                } else {
+                    syzygy.dtz.insert(name.to_string(), file);
+                }
+            }
+        }
+        Ok(syzygy)
+    }
+
+    /// Returns the number of WDL tables
+    pub fn len(&self) -> usize {
+        self.wdl.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wdl.is_empty()
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// Returns true if the position has few enough pieces and no castling rights
+    ///
+    /// unlike the probes this also sees Chess960 castling rights, which the board keeps outside the position
+    pub fn can_probe(&self, board: &Board) -> bool {
+        !board.has_castling_rights() && self.fits(&board.position)
+    }
+
+    fn fits(&self, pos: &chess::Board) -> bool {
+        pos.combined().popcnt() as usize <= self.max_pieces
+            && pos.castle_rights(Color::White) == CastleRights::NoRights
+            && pos.castle_rights(Color::Black) == CastleRights::NoRights
+    }
+
+    /// Returns the WDL result of a position, ignoring its fifty move counter
+    ///
+    /// the position must not come from a Chess960 board with castling rights, see `can_probe`
+    pub fn probe_wdl(&self, pos: &chess::Board) -> Option<Wdl> {
+        if !self.fits(pos) {
+            return None;
+        }
+        self.search(pos, false).map(|(wdl, _)| wdl)
+    }
+
+    /// Returns the distance in plies to the next capture or pawn move (or mate) in an
+    /// optimal game, positive for a win and negative for a loss, with cursed wins and
+    /// blessed losses beyond 100 plies
+    pub fn probe_dtz(&self, pos: &chess::Board) -> Option<i32> {
+        if !self.fits(pos) {
+            return None;
+        }
+        self.dtz(pos)
+    }
+
+    fn table<'a>(&'a self, files: &'a HashMap<String, TableFile>, pos: &chess::Board) -> Option<(&'a Table, bool)> {
+        let white = side_name(pos, Color::White);
+        let black = side_name(pos, Color::Black);
+        if let Some(file) = files.get(&format!("{}v{}", white, black)) {
+            return Some((file.get()?, false));
+        }
+        Some((files.get(&format!("{}v{}", black, white))?.get()?, true))
+    }
+
+    fn probe_wdl_table(&self, pos: &chess::Board) -> Option<Wdl> {
+        if pos.combined().popcnt() == 2 {
+            return Some(Wdl::Draw);
+        }
+        let (table, black_stronger) = self.table(&self.wdl, pos)?;
+        table.probe_wdl(pos, black_stronger)
+    }
+
+    /// Resolves captures (and pawn moves, if `check_zeroing`) before probing the table
+    ///
+    /// Tables may store any value for positions where the side to move has a winning
+    /// capture, and may store a loss where a capture draws, so the best capture has to
+    /// be compared against the stored value. Also returns whether the best move is
+    /// zeroing, in which case DTZ tables may not hold a usable value.
+    fn search(&self, pos: &chess::Board, check_zeroing: bool) -> Option<(Wdl, bool)> {
+        let moves = MoveGen::new_legal(pos);
+        let total = moves.len();
+        let mut count = 0;
+        let mut best = Wdl::Loss;
+
+        for mv in moves {
+            if !is_capture(pos, mv) && (!check_zeroing || pos.piece_on(mv.get_source()) != Some(Piece::Pawn)) {
+                continue;
+            }
+            count += 1;
+            let (value, _) = self.search(&pos.make_move_new(mv), false)?;
+            let value = -value;
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        let no_more_moves = count > 0 && count == total;
+        let value = if no_more_moves { best } else { self.probe_wdl_table(pos)? };
+        if best >= value {
+            return Some((best, best > Wdl::Draw || no_more_moves));
+        }
+        Some((value, false))
+    }
+
+    fn dtz(&self, pos: &chess::Board) -> Option<i32> {
+        let (wdl, zeroing) = self.search(pos, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        let (table, black_stronger) = self.table(&self.dtz, pos)?;
+        if let Some(dtz) = table.probe_dtz(pos, black_stronger, wdl)? {
+            let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+            return Some((dtz + if cursed { 100 } else { 0 }) * wdl.signum());
+        }
+
+        // the table stores the other side to move, so look one ply ahead
+        let mut min_dtz = i32::MAX;
+        for mv in MoveGen::new_legal(pos) {
+            let zeroing = is_zeroing(pos, mv);
+            let next = pos.make_move_new(mv);
+            let mut dtz = if zeroing {
+                -dtz_before_zeroing(self.search(&next, false)?.0)
+            } else {
+                -self.dtz(&next)?
+            };
+
+            if dtz == 1 && *next.checkers() != EMPTY && MoveGen::new_legal(&next).len() == 0 {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+    }
+
+    /// Ranks the root moves with DTZ tables, or with WDL tables if a DTZ table is missing
+    ///
+    /// Wins that can be completed before the fifty move counter runs out share the top
+    /// rank, as do losses that can't be held; otherwise shorter wins and longer losses
+    /// rank higher. Returns `None` if any move can't be probed.
+    pub fn rank_root_moves(&self, board: &Board, rule50: bool) -> Option<RootRanking> {
+        if !self.can_probe(board) {
+            return None;
+        }
+        let mut board = board.clone();
+        let mut ranking = self.rank_by_dtz(&mut board, rule50)
+            .map(|moves| RootRanking { moves, dtz: true })
+            .or_else(|| self.rank_by_wdl(&mut board, rule50).map(|moves| RootRanking { moves, dtz: false }))?;
+        if ranking.moves.is_empty() {
+            return None;
+        }
+        ranking.moves.sort_by_key(|&(_, rank)| -rank);
+        Some(ranking)
+    }
+
+    fn rank_by_dtz(&self, board: &mut Board, rule50: bool) -> Option<Vec<(Move, i32)>> {
+        let cnt50 = board.halfmove_clock() as i32;
+        let repeated = board.is_repeated();
+
+        let mut moves = Vec::new();
+        for mv in MoveGen::new_legal(&board.position) {
+            board.make_move(mv);
+            let pos = board.position;
+            let dtz = if board.halfmove_clock() == 0 {
+                self.probe_wdl(&pos).map(|wdl| dtz_before_zeroing(-wdl))
+            } else if board.is_repeated() || board.is_fifty_move_draw() {
+                Some(0)
+            } else {
+                self.dtz(&pos).map(|dtz| -dtz - dtz.signum())
+            };
+            let mate = *pos.checkers() != EMPTY && MoveGen::new_legal(&pos).len() == 0;
+            board.undo_move();
+
+            let dtz = if mate && dtz? == 2 { 1 } else { dtz? };
+            // without the fifty move rule a cursed win is as good as any other win
+            let rank = if dtz > 0 {
+                if (dtz + cnt50 <= 99 || !rule50) && !repeated { MAX_DTZ } else { MAX_DTZ - (dtz + cnt50) }
+            } else if dtz < 0 {
+                if -dtz * 2 + cnt50 < 100 || !rule50 { -MAX_DTZ } else { -MAX_DTZ + (-dtz + cnt50) }
+            } else {
+                0
+            };
+            moves.push((mv, rank));
+        }
+        Some(moves)
+    }
+
+    fn rank_by_wdl(&self, board: &mut Board, rule50: bool) -> Option<Vec<(Move, i32)>> {
+        let mut moves = Vec::new();
+        for mv in MoveGen::new_legal(&board.position) {
+            board.make_move(mv);
+            let wdl = if board.is_repeated() || board.is_fifty_move_draw() {
+                Some(Wdl::Draw)
+            } else {
+                self.probe_wdl(&board.position).map(|wdl| -wdl)
+            };
+            board.undo_move();
+
+            let rank = match wdl? {
+                Wdl::Win => MAX_DTZ,
+                Wdl::CursedWin if rule50 => MAX_DTZ - 101,
+                Wdl::CursedWin => MAX_DTZ,
+                Wdl::Draw => 0,
+                Wdl::BlessedLoss if rule50 => -MAX_DTZ + 101,
+                Wdl::BlessedLoss | Wdl::Loss => -MAX_DTZ,
+            };
+            moves.push((mv, rank));
+        }
+        Some(moves)
+    }
+}
+
+fn has_magic(path: &Path, magic: [u8; 4]) -> bool {
+    let mut header = [0; 4];
+    fs::File::open(path).and_then(|mut file| file.read_exact(&mut header)).is_ok() && header == magic
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{cmp::Reverse, collections::BinaryHeap, str::FromStr};
+    use chess::{BoardBuilder, Square};
+    use crate::{kpk, tablebase::{Material, Outcome, Table as DtmTable, Tablebases}};
+
+    const WHITE_PAWN: u8 = 1;
+    const WHITE_ROOK: u8 = 4;
+    const WHITE_KING: u8 = 6;
+    const BLACK_KING: u8 = 14;
+
+    const BLOCK_BITS: u8 = 6;
+    const SPAN_BITS: u8 = 6;
+
+    /// One compressed subtable, split into the sections of the file layout
+    #[derive(Default)]
+    struct Compressed {
+        sizes: Vec<u8>,
+        sparse: Vec<u8>,
+        lengths: Vec<u8>,
+        blocks: Vec<u8>,
+    }
+
+    /// Compresses values with a symbol for each value and for each pair of equal values,
+    /// Huffman coded into 64 byte blocks
+    fn compress(flags: u8, values: &[u16]) -> Compressed {
+        if values.iter().all(|&v| v == values[0]) {
+            return Compressed { sizes: vec![flags | SINGLE_VALUE, values[0] as u8], ..Default::default() };
+        }
+
+        // symbol 2i is the value distinct[i], symbol 2i + 1 is two of them
+        let mut distinct = values.to_vec();
+        distinct.sort_unstable();
+        distinct.dedup();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < values.len() {
+            let leaf = 2 * distinct.binary_search(&values[i]).unwrap();
+            if i + 1 < values.len() && values[i + 1] == values[i] {
+                tokens.push(leaf + 1);
+                i += 2;
+            } else {
+                tokens.push(leaf);
+                i += 1;
+            }
+        }
+
+        /* Huffman Code Lengths */
+        let sym_count = 2 * distinct.len();
+        let mut freq = vec![0u64; sym_count];
+        for &t in &tokens {
+            freq[t] += 1;
+        }
+        let used: Vec<usize> = (0..sym_count).filter(|&s| freq[s] > 0).collect();
+        let mut lens = vec![0usize; sym_count];
+        if used.len() == 1 {
+            lens[used[0]] = 1;
+        }
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = BinaryHeap::new();
+        let mut members: Vec<Vec<usize>> = Vec::new();
+        for &s in &used {
+            heap.push(Reverse((freq[s], members.len())));
+            members.push(vec![s]);
+        }
+        while heap.len() > 1 {
+            let Reverse((f1, a)) = heap.pop().unwrap();
+            let Reverse((f2, b)) = heap.pop().unwrap();
+            let mut merged = std::mem::take(&mut members[a]);
+            merged.append(&mut members[b]);
+            for &s in &merged {
+                lens[s] += 1;
+            }
+            heap.push(Reverse((f1 + f2, members.len())));
+            members.push(merged);
+        }
+
+        /* Canonical Codes: longer codes take lower symbol ids, unused symbols come last */
+        let max_len = used.iter().map(|&s| lens[s]).max().unwrap();
+        let min_len = used.iter().map(|&s| lens[s]).min().unwrap();
+        assert!(max_len <= 32);
+        let mut order: Vec<usize> = (0..sym_count).collect();
+        order.sort_by_key(|&s| (lens[s] == 0, Reverse(lens[s])));
+        let mut id = vec![0; sym_count];
+        for (i, &s) in order.iter().enumerate() {
+            id[s] = i;
+        }
+        let lowest = |len: usize| used.iter().filter(|&&s| lens[s] > len).count();
+        let count = |len: usize| used.iter().filter(|&&s| lens[s] == len).count() as u64;
+        let mut first_code = vec![0u64; max_len + 1];
+        for len in (min_len..max_len).rev() {
+            first_code[len] = (first_code[len + 1] + count(len + 1)) / 2;
+        }
+        let code = |s: usize| first_code[lens[s]] + (id[s] - lowest(lens[s])) as u64;
+
+        /* Blocks */
+        let block_size = 1usize << BLOCK_BITS;
+        let mut blocks: Vec<u8> = Vec::new();
+        let mut block_values: Vec<usize> = Vec::new();
+        let mut bit = 8 * block_size;
+        for &t in &tokens {
+            if bit + lens[t] > 8 * block_size {
+                blocks.resize(blocks.len() + block_size, 0);
+                block_values.push(0);
+                bit = 0;
+            }
+            let start = blocks.len() - block_size;
+            for b in 0..lens[t] {
+                if code(t) >> (lens[t] - 1 - b) & 1 != 0 {
+                    blocks[start + (bit + b) / 8] |= 0x80 >> ((bit + b) % 8);
+                }
+            }
+            bit += lens[t];
+            *block_values.last_mut().unwrap() += t % 2 + 1;
+        }
+
+        let mut compressed = Compressed::default();
+        for &n in &block_values {
+            compressed.lengths.extend_from_slice(&(n as u16 - 1).to_le_bytes());
+        }
+        let span = 1usize << SPAN_BITS;
+        for k in 0..values.len().div_ceil(span) {
+            let target = k * span + span / 2;
+            let (mut block, mut start) = (0, 0);
+            while block + 1 < block_values.len() && start + block_values[block] <= target {
+                start += block_values[block];
+                block += 1;
+            }
+            compressed.sparse.extend_from_slice(&(block as u32).to_le_bytes());
+            compressed.sparse.extend_from_slice(&((target - start) as u16).to_le_bytes());
+        }
+
+        let sizes = &mut compressed.sizes;
+        sizes.extend_from_slice(&[flags, BLOCK_BITS, SPAN_BITS, 0]);
+        sizes.extend_from_slice(&(block_values.len() as u32).to_le_bytes());
+        sizes.extend_from_slice(&[max_len as u8, min_len as u8]);
+        for len in min_len..=max_len {
+            sizes.extend_from_slice(&(lowest(len) as u16).to_le_bytes());
+        }
+        sizes.extend_from_slice(&(sym_count as u16).to_le_bytes());
+        for &s in &order {
+            let (left, right) = if s % 2 == 0 {
+                (distinct[s / 2], 0xFFF)
+            } else {
+                (id[s - 1] as u16, id[s - 1] as u16)
+            };
+            sizes.extend_from_slice(&[left as u8, ((left >> 8) | ((right & 0xF) << 4)) as u8, (right >> 4) as u8]);
+        }
+        if sym_count % 2 == 1 {
+            sizes.push(0);
+        }
+        compressed.blocks = blocks;
+        compressed
+    }
+
+    /// Lays out a table file from its subtables, ordered by file and then side to move
+    fn build(kind: Kind, name: &str, pieces: &[u8], subtables: &[Compressed], map: &[u8]) -> Vec<u8> {
+        let (white, black) = parse_name(name).unwrap();
+        let has_pawns = white[0] + black[0] > 0;
+        let files = if has_pawns { 4 } else { 1 };
+
+        let mut bytes = if kind == Kind::Wdl { WDL_MAGIC.to_vec() } else { DTZ_MAGIC.to_vec() };
+        bytes.push(if white != black { SPLIT } else { 0 } | if has_pawns { HAS_PAWNS } else { 0 });
+        for _ in 0..files {
+            bytes.push(0);
+            bytes.extend(pieces.iter().map(|&p| p | (p << 4)));
+        }
+        let align = |bytes: &mut Vec<u8>, to: usize| bytes.resize(bytes.len().next_multiple_of(to), 0);
+        align(&mut bytes, 2);
+        subtables.iter().for_each(|s| bytes.extend_from_slice(&s.sizes));
+        bytes.extend_from_slice(map);
+        align(&mut bytes, 2);
+        subtables.iter().for_each(|s| bytes.extend_from_slice(&s.sparse));
+        subtables.iter().for_each(|s| bytes.extend_from_slice(&s.lengths));
+        for s in subtables {
+            align(&mut bytes, 64);
+            bytes.extend_from_slice(&s.blocks);
+        }
+        bytes
+    }
+
+    /// The header of a table file to write: the table it belongs to, its piece order and
+    /// how its values are stored
+    struct TableSpec<'a> {
+        kind: Kind,
+        name: &'a str,
+        pieces: &'a [u8],
+        flags: u8,
+        map: &'a [u8],
+    }
+
+    /// Writes a table holding `value` for each of `positions`, which must have white as
+    /// the first side of the name
+    fn write_table(dir: &Path, spec: TableSpec, positions: &[chess::Board], value: impl Fn(&chess::Board) -> u16) {
+        let TableSpec { kind, name, pieces, flags, map } = spec;
+        // an empty table of the same layout gives the size of each subtable
+        let (white, black) = parse_name(name).unwrap();
+        let files = if white[0] + black[0] > 0 { 4 } else { 1 };
+        let sides = if kind == Kind::Wdl && white != black { 2 } else { 1 };
+        let empty: Vec<Compressed> = (0..files * sides).map(|_| compress(flags, &[0])).collect();
+        let layout = Table::parse(kind, name, build(kind, name, pieces, &empty, map)).unwrap();
+
+        let mut values: Vec<Vec<Option<u16>>> = layout.pairs.iter().flatten()
+            .map(|d| vec![None; d.group_idx[d.group_len.iter().position(|&len| len == 0).unwrap()] as usize])
+            .collect();
+        for pos in positions {
+            if let Some(Slot::Entry { file, side, idx }) = layout.slot(pos, false) {
+                let stored = &mut values[file * sides + side][idx as usize];
+                assert!(stored.is_none() || *stored == Some(value(pos)), "index collision at {}", pos);
+                *stored = Some(value(pos));
+            }
+        }
+
+        let subtables: Vec<Compressed> = values.iter().map(|subtable| {
+            let mut last = subtable.iter().flatten().next().copied().unwrap_or(0);
+            let filled: Vec<u16> = subtable.iter().map(|v| { last = v.unwrap_or(last); last }).collect();
+            compress(flags, &filled)
+        }).collect();
+        let ext = if kind == Kind::Wdl { WDL_EXTENSION } else { DTZ_EXTENSION };
+        fs::write(dir.join(format!("{}.{}", name, ext)), build(kind, name, pieces, &subtables, map)).unwrap();
+    }
+
+    /// Every legal placement of the given pieces, with either side to move
+    fn positions(pieces: &[(Piece, Color)]) -> Vec<chess::Board> {
+        let mut result = Vec::new();
+        let mut squares = vec![0usize; pieces.len()];
+        'outer: loop {
+            let distinct = (0..squares.len()).all(|i| !squares[..i].contains(&squares[i]));
+            if distinct {
+                let setup: Vec<(Square, Piece, Color)> = pieces.iter().zip(&squares)
+                    .map(|(&(piece, color), &sq)| (Square::new(sq as u8), piece, color))
+                    .collect();
+                for stm in [Color::White, Color::Black] {
+                    let builder = BoardBuilder::setup(&setup, stm, CastleRights::NoRights, CastleRights::NoRights, None);
+                    if let Ok(pos) = chess::Board::try_from(builder) {
+                        result.push(pos);
+                    }
+                }
+            }
+            for sq in squares.iter_mut() {
+                *sq += 1;
+                if *sq < 64 {
+                    continue 'outer;
+                }
+                *sq = 0;
+            }
+            return result;
+        }
+    }
+
+    /// The same position with the colours reversed
+    fn flipped(pos: &chess::Board) -> chess::Board {
+        let setup: Vec<(Square, Piece, Color)> = pos.combined().map(|sq| {
+            (Square::new(sq.to_int() ^ 56), pos.piece_on(sq).unwrap(), !pos.color_on(sq).unwrap())
+        }).collect();
+        let builder = BoardBuilder::setup(&setup, !pos.side_to_move(), CastleRights::NoRights, CastleRights::NoRights, None);
+        chess::Board::try_from(builder).unwrap()
+    }
+
+    fn krvk() -> &'static Tablebases {
+        static KRVK: OnceLock<Tablebases> = OnceLock::new();
+        KRVK.get_or_init(|| {
+            let mut tablebases = Tablebases::default();
+            tablebases.insert(DtmTable::generate(Material::from_str("KRvK").unwrap(), &tablebases).unwrap());
+            tablebases
+        })
+    }
+
+    fn krvk_positions() -> Vec<chess::Board> {
+        positions(&[(Piece::Rook, Color::White), (Piece::King, Color::White), (Piece::King, Color::Black)])
+    }
+
+    /// Writes KRvK WDL and DTZ tables built from the distance to mate tables
+    pub(crate) fn write_krvk(dir: &Path) {
+        let positions = krvk_positions();
+        let dtm = krvk();
+        let spec = TableSpec { kind: Kind::Wdl, name: "KRvK", pieces: &[WHITE_ROOK, WHITE_KING, BLACK_KING], flags: 0, map: &[] };
+        write_table(dir, spec, &positions, |pos| {
+            match dtm.probe(pos).unwrap() {
+                Outcome::Win(_) => 4,
+                Outcome::Draw => 2,
+                Outcome::Loss(_) => 0,
+            }
+        });
+
+        // white to move wins are stored as an index into the map of distances
+        let mut distances: Vec<u8> = positions.iter().filter_map(|pos| match dtm.probe(pos).unwrap() {
+            Outcome::Win(d) if pos.side_to_move() == Color::White => Some(d - 1),
+            _ => None,
+        }).collect();
+        distances.sort_unstable();
+        distances.dedup();
+        let mut map = vec![distances.len() as u8];
+        map.extend_from_slice(&distances);
+        map.extend_from_slice(&[0, 0, 0]);
+        let spec = TableSpec { kind: Kind::Dtz, name: "KRvK", pieces: &[WHITE_ROOK, WHITE_KING, BLACK_KING], flags: MAPPED | WIN_PLIES, map: &map };
+        write_table(dir, spec, &positions, |pos| {
+            match dtm.probe(pos).unwrap() {
+                Outcome::Win(d) => distances.binary_search(&(d - 1)).unwrap() as u16,
+                _ => 0,
+            }
+        });
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("engine-syzygy-{}", name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Deterministic sample of positions and their colour reversed versions
+    fn sample(positions: &[chess::Board], count: usize) -> Vec<chess::Board> {
+        let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut result = Vec::with_capacity(2 * count);
+        for _ in 0..count {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let pos = positions[(seed % positions.len() as u64) as usize];
+            result.push(pos);
+            result.push(flipped(&pos));
+        }
+        result
+    }
+
+    #[test]
+    fn test_encoding_tables() {
+        assert_eq!(MAP_KK.iter().flatten().max(), Some(&461));
+        assert_eq!(MAP_B1H1H7.iter().max(), Some(&27));
+        assert_eq!(MAP_A1D1D4[Square::B1.to_index()], 0);
+        assert_eq!(MAP_A1D1D4[Square::D4.to_index()], 9);
+        assert_eq!(MAP_PAWNS[Square::A2.to_index()], 47);
+        assert_eq!(MAP_PAWNS[Square::H2.to_index()], 46);
+        assert_eq!(MAP_PAWNS[Square::E7.to_index()], 0);
+        assert_eq!(BINOMIAL[3][62], 37820);
+        assert_eq!(LEAD_PAWNS.1[1], [6, 6, 6, 6]);
+        // the lead pawn is on the edge file with the most pawns on other files still to place
+        assert_eq!(LEAD_PAWNS.1[2][0], (1..7).map(|r| BINOMIAL[1][MAP_PAWNS[r * 8]]).sum::<u64>());
+    }
+
+    #[test]
+    fn test_parse_name() {
+        assert_eq!(parse_name("KRPvKR"), Some(([1, 0, 0, 1, 0, 1], [0, 0, 0, 1, 0, 1])));
+        assert_eq!(parse_name("KRvK"), Some(([0, 0, 0, 1, 0, 1], [0, 0, 0, 0, 0, 1])));
+        assert_eq!(parse_name("KRKvK"), None);
+        assert_eq!(parse_name("RvK"), None);
+        assert_eq!(parse_name("KXvK"), None);
+        assert_eq!(side_name(&chess::Board::from_str("8/8/8/8/8/2k5/1p6/KR5N w - - 0 1").unwrap(), Color::White), "KRN");
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = test_dir("load");
+        fs::write(dir.join("KQvK.rtbw"), b"not a table").unwrap();
+        fs::write(dir.join("readme.txt"), b"").unwrap();
+        fs::write(dir.join("KQvK.rtbz"), [DTZ_MAGIC.as_slice(), &[0]].concat()).unwrap();
+
+        let syzygy = Syzygy::load(dir.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(syzygy.is_empty());
+        assert_eq!(syzygy.dtz.len(), 1);
+        assert!(Syzygy::load("/nonexistent/syzygy").is_err());
+        assert!(Syzygy::load("").unwrap().is_empty());
+
+        // truncated tables are rejected when read
+        assert!(Table::parse(Kind::Dtz, "KQvK", [DTZ_MAGIC.as_slice(), &[SPLIT]].concat()).is_err());
+        assert!(Table::parse(Kind::Wdl, "KQvK", [WDL_MAGIC.as_slice(), &[SPLIT, 0, 0x44]].concat()).is_err());
+        // the header has to match the name
+        assert!(Table::parse(Kind::Wdl, "KQvK", [WDL_MAGIC.as_slice(), &[HAS_PAWNS]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_single_value_table() {
+        let dir = test_dir("single");
+        let positions = positions(&[(Piece::Rook, Color::White), (Piece::King, Color::White), (Piece::King, Color::Black)]);
+        let spec = TableSpec { kind: Kind::Wdl, name: "KRvK", pieces: &[WHITE_ROOK, WHITE_KING, BLACK_KING], flags: 0, map: &[] };
+        write_table(&dir, spec, &positions, |_| 2);
+        let syzygy = Syzygy::load(dir.to_str().unwrap()).unwrap();
+
+        assert_eq!(syzygy.len(), 1);
+        assert_eq!(syzygy.max_pieces(), 3);
+        let table = syzygy.wdl["KRvK"].get().unwrap();
+        assert!(table.pairs[0].iter().all(|d| d.flags & SINGLE_VALUE != 0));
+        assert_eq!(syzygy.probe_wdl(&chess::Board::from_str("8/8/8/8/8/2k5/8/K6R w - - 0 1").unwrap()), Some(Wdl::Draw));
+        // missing tables and castling rights can't be probed
+        assert_eq!(syzygy.probe_wdl(&chess::Board::from_str("8/8/8/8/8/2k5/8/K6Q w - - 0 1").unwrap()), None);
+        assert_eq!(syzygy.probe_wdl(&chess::Board::from_str("k7/8/8/8/8/8/8/4K2R w K - 0 1").unwrap()), None);
+        // nor can Chess960 castling rights, which aren't in the position
+        let chess960 = Board::from_fen("k7/8/8/8/8/8/8/1R2K3 w B - 0 1").unwrap();
+        assert!(!syzygy.can_probe(&chess960));
+        assert!(syzygy.rank_root_moves(&chess960, true).is_none());
+        assert!(syzygy.can_probe(&Board::from_fen("k7/8/8/8/8/8/8/1R2K3 w - - 0 1").unwrap()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_krvk_wdl() {
+        let dir = test_dir("krvk-wdl");
+        write_krvk(&dir);
+        let syzygy = Syzygy::load(dir.to_str().unwrap()).unwrap();
+
+        let table = syzygy.wdl["KRvK"].get().unwrap();
+        // white to move always wins, black to move needs the compressed data
+        assert!(table.pairs[0][0].flags & SINGLE_VALUE != 0);
+        assert!(table.pairs[0][1].flags & SINGLE_VALUE == 0 && table.pairs[0][1].blocks_num > 1);
+        for pos in sample(&krvk_positions(), 20_000) {
+            let expected = match krvk().probe(&pos).unwrap() {
+                Outcome::Win(_) => Wdl::Win,
+                Outcome::Draw => Wdl::Draw,
+                Outcome::Loss(_) => Wdl::Loss,
+            };
+            assert_eq!(syzygy.probe_wdl(&pos), Some(expected), "{}", pos);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_krvk_dtz() {
+        let dir = test_dir("krvk-dtz");
+        write_krvk(&dir);
+        let syzygy = Syzygy::load(dir.to_str().unwrap()).unwrap();
+
+        // the only zeroing move is mate, so distance to zeroing is distance to mate
+        for pos in sample(&krvk_positions(), 2_000) {
+            let expected = match krvk().probe(&pos).unwrap() {
+                Outcome::Win(d) => d as i32,
+                Outcome::Draw => 0,
+                Outcome::Loss(0) => -1,
+                Outcome::Loss(d) => -(d as i32),
+            };
+            assert_eq!(syzygy.probe_dtz(&pos), Some(expected), "{}", pos);
+        }
+
+        let board = Board::from_fen("8/8/8/8/8/2k5/8/K6R w - - 0 1").unwrap();
+        let ranking = syzygy.rank_root_moves(&board, true).unwrap();
+        assert!(ranking.dtz);
+        assert_eq!(ranking.best_rank(), MAX_DTZ);
+        // close to the fifty move limit only the fastest wins are kept
+        let board = Board::from_fen("8/8/8/8/8/2k5/8/K6R w - - 90 1").unwrap();
+        let ranking = syzygy.rank_root_moves(&board, true).unwrap();
+        let fastest = ranking.best_moves().iter()
+            .map(|&mv| syzygy.probe_dtz(&board.position.make_move_new(mv)).unwrap())
+            .collect::<Vec<_>>();
+        let all = MoveGen::new_legal(&board.position)
+            .filter_map(|mv| syzygy.probe_dtz(&board.position.make_move_new(mv)))
+            .filter(|&dtz| dtz < 0)
+            .max()
+            .unwrap();
+        assert!(ranking.best_rank() < MAX_DTZ);
+        assert!(fastest.iter().all(|&dtz| dtz == all));
+        // without the fifty move rule any win will do
+        let ranking = syzygy.rank_root_moves(&board, false).unwrap();
+        assert_eq!(ranking.best_rank(), MAX_DTZ);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Checks known values against tables written by the Syzygy generator, rather than by [`write_table`]
+    #[test]
+    #[ignore = "needs KRvK and KPvK .rtbw and .rtbz files from the Syzygy generator in engine/res/syzygy"]
+    fn test_generator_tables() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("res").join("syzygy");
+        let syzygy = Syzygy::load(dir.to_str().unwrap()).unwrap();
+        let probe = |fen: &str| {
+            let pos = chess::Board::from_str(fen).unwrap();
+            (syzygy.probe_wdl(&pos), syzygy.probe_dtz(&pos))
+        };
+
+        assert_eq!(probe("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").0, Some(Wdl::Win));
+        assert_eq!(probe("4k3/8/8/8/8/8/8/R3K3 b - - 0 1").0, Some(Wdl::Loss));
+        // mate in one
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/7R w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        // the rook is taken
+        assert_eq!(probe("8/8/8/8/8/8/1kR5/4K3 b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+
+        // the pawn promotes at once, or on the next move
+        assert_eq!(probe("8/4P3/8/8/8/8/8/k3K3 w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        assert_eq!(probe("8/4P3/8/8/8/8/8/k3K3 b - - 0 1"), (Some(Wdl::Loss), Some(-2)));
+        assert_eq!(probe("8/8/8/8/8/8/kP6/7K b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+    }
+
+    #[test]
+    fn test_kpvk_wdl() {
+        let dir = test_dir("kpvk-wdl");
+        let positions = positions(&[(Piece::Pawn, Color::White), (Piece::King, Color::White), (Piece::King, Color::Black)]);
+        let expected = |pos: &chess::Board| {
+            let strong = pos.color_on(pos.pieces(Piece::Pawn).to_square()).unwrap();
+            match (kpk::probe(pos), pos.side_to_move() == strong) {
+                (true, true) => Wdl::Win,
+                (true, false) => Wdl::Loss,
+                (false, _) => Wdl::Draw,
+            }
+        };
+        let spec = TableSpec { kind: Kind::Wdl, name: "KPvK", pieces: &[WHITE_PAWN, WHITE_KING, BLACK_KING], flags: 0, map: &[] };
+        write_table(&dir, spec, &positions, |pos| (expected(pos) as i32 + 2) as u16);
+        let syzygy = Syzygy::load(dir.to_str().unwrap()).unwrap();
+
+        assert_eq!(syzygy.wdl["KPvK"].get().unwrap().pairs.len(), 4);
+        for pos in sample(&positions, 20_000) {
+            assert_eq!(syzygy.probe_wdl(&pos), Some(expected(&pos)), "{}", pos);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,801 @@
+use std::{cmp::Reverse, collections::HashMap, fmt, fs, io::{self, Read, Write}, path::Path, str::FromStr};
+
+use chess::{
+    get_bishop_moves, get_king_moves, get_knight_moves, get_pawn_attacks, get_rook_moves,
+    BitBoard, CastleRights, Color, MoveGen, Piece, Rank, Square, EMPTY,
+};
+
+use crate::board::{Board, Move};
+
+pub const MAX_PIECES: usize = 4;
+pub const FILE_EXTENSION: &str = "etb";
+
+const MAGIC: &[u8; 4] = b"ETB1";
+const NO_REGION: u8 = u8::MAX;
+
+/// a1-d1-d4 triangle, used to place the white king in pawnless tables
+const TRIANGLE: [u8; 64] = {
+    let mut region = [NO_REGION; 64];
+    let mut count = 0;
+    let mut sq = 0;
+    while sq < 64 {
+        let (file, rank) = (sq % 8, sq / 8);
+        if file < 4 && rank <= file {
+            region[sq] = count;
+            count += 1;
+        }
+        sq += 1;
+    }
+    region
+};
+
+/// files a-d, used to place the white king in tables with pawns
+const QUEENSIDE: [u8; 64] = {
+    let mut region = [NO_REGION; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        if sq % 8 < 4 {
+            region[sq] = ((sq / 8) * 4 + sq % 8) as u8;
+        }
+        sq += 1;
+    }
+    region
+};
+
+/// Result of a tablebase probe for the side to move, with the distance to mate in plies
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Win(u8),
+    Draw,
+    Loss(u8),
+}
+
+impl Outcome {
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => Outcome::Draw,
+            n if (n - 1) % 2 == 1 => Outcome::Win(n - 1),
+            n => Outcome::Loss(n - 1),
+        }
+    }
+
+    /// Returns the outcome for the side that made the move leading to this position
+    pub fn parent(self) -> Self {
+        match self {
+            Outcome::Win(d) => Outcome::Loss(d + 1),
+            Outcome::Draw => Outcome::Draw,
+            Outcome::Loss(d) => Outcome::Win(d + 1),
+        }
+    }
+
+    /// Orders outcomes from the side to move's point of view: faster wins, then draws, then slower losses
+    pub fn rank(self) -> i32 {
+        match self {
+            Outcome::Win(d) => 1000 - d as i32,
+            Outcome::Draw => 0,
+            Outcome::Loss(d) => -1000 + d as i32,
+        }
+    }
+}
+
+/// Material signature such as `KQvKR`, stored with the stronger side first
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Material {
+    strong: Vec<Piece>,
+    weak: Vec<Piece>,
+}
+
+impl Material {
+    /// Returns the signature for the given sides and whether the colours had to be swapped
+    fn new(mut white: Vec<Piece>, mut black: Vec<Piece>) -> (Self, bool) {
+        white.sort_by_key(|p| Reverse(p.to_index()));
+        black.sort_by_key(|p| Reverse(p.to_index()));
+        let key = |pieces: &Vec<Piece>| (pieces.len(), pieces.iter().map(|p| p.to_index()).collect::<Vec<_>>());
+        if key(&black) > key(&white) {
+            (Self { strong: black, weak: white }, true)
+        } else {
+            (Self { strong: white, weak: black }, false)
+        }
+    }
+
+    pub fn from_board(pos: &chess::Board) -> (Self, bool) {
+        let side = |color: Color| {
+            let mut pieces = Vec::new();
+            for piece in [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight, Piece::Pawn] {
+                let count = (pos.pieces(piece) & pos.color_combined(color)).popcnt();
+                pieces.extend((0..count).map(|_| piece));
+            }
+            pieces
+        };
+        Self::new(side(Color::White), side(Color::Black))
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.strong.len() + self.weak.len() + 2
+    }
+
+    fn has_pawns(&self) -> bool {
+        self.strong.contains(&Piece::Pawn) || self.weak.contains(&Piece::Pawn)
+    }
+
+    /// Returns true if both sides have pawns, so a double push may allow an en passant capture
+    ///
+    /// the index has no room for an en passant square, so these signatures are not supported
+    pub fn allows_en_passant(&self) -> bool {
+        self.strong.contains(&Piece::Pawn) && self.weak.contains(&Piece::Pawn)
+    }
+
+    /// Pieces in index order, starting with the white king
+    fn layout(&self) -> Vec<(Piece, Color)> {
+        let mut layout = vec![(Piece::King, Color::White)];
+        layout.extend(self.strong.iter().map(|&p| (p, Color::White)));
+        layout.push((Piece::King, Color::Black));
+        layout.extend(self.weak.iter().map(|&p| (p, Color::Black)));
+        layout
+    }
+
+    /// Returns the signatures reachable by a capture or a promotion, excluding bare kings
+    pub fn dependencies(&self) -> Vec<Material> {
+        let mut deps: Vec<Material> = Vec::new();
+        let mut add = |white: Vec<Piece>, black: Vec<Piece>| {
+            let (material, _) = Material::new(white, black);
+            if material.piece_count() > 2 && !deps.contains(&material) {
+                deps.push(material);
+            }
+        };
+
+        for (own, other) in [(&self.strong, &self.weak), (&self.weak, &self.strong)] {
+            for i in 0..own.len() {
+                let mut rest = own.clone();
+                let piece = rest.remove(i);
+                add(rest.clone(), other.clone());
+                if piece == Piece::Pawn {
+                    for promotion in [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight] {
+                        let mut promoted = rest.clone();
+                        promoted.push(promotion);
+                        add(promoted, other.clone());
+                    }
+                }
+            }
+        }
+        deps
+    }
+}
+
+impl fmt::Display for Material {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = |pieces: &Vec<Piece>| pieces.iter().map(|p| p.to_string(Color::White)).collect::<String>();
+        write!(f, "K{}vK{}", side(&self.strong), side(&self.weak))
+    }
+}
+
+impl FromStr for Material {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_side = |side: &str| -> Result<Vec<Piece>, String> {
+            let mut chars = side.chars();
+            if chars.next() != Some('K') {
+                return Err(format!("each side of '{}' must start with a king", s));
+            }
+            chars.map(|c| match c {
+                'Q' => Ok(Piece::Queen),
+                'R' => Ok(Piece::Rook),
+                'B' => Ok(Piece::Bishop),
+                'N' => Ok(Piece::Knight),
+                'P' => Ok(Piece::Pawn),
+                _ => Err(format!("unknown piece '{}' in '{}'", c, s)),
+            }).collect()
+        };
+
+        let (white, black) = s.split_once('v').ok_or(format!("expected a signature like KQvK, got '{}'", s))?;
+        let (material, _) = Material::new(parse_side(white)?, parse_side(black)?);
+        if material.piece_count() > MAX_PIECES {
+            return Err(format!("'{}' has more than {} pieces", s, MAX_PIECES));
+        }
+        Ok(material)
+    }
+}
+
+/// Distance to mate for every position of one material signature
+///
+/// Positions are stored one byte each: 0 for a draw, otherwise the distance to mate
+/// plus one, where odd distances are wins and even distances are losses for the side
+/// to move. Mirrored positions share an entry, with the white king kept on the
+/// a1-d1-d4 triangle (or files a-d when pawns are present), and the stronger side is
+/// always stored as white. En passant and castling rights are ignored.
+pub struct Table {
+    material: Material,
+    layout: Vec<(Piece, Color)>,
+    region: &'static [u8; 64],
+    region_size: usize,
+    data: Vec<u8>,
+}
+
+impl Table {
+    fn new(material: Material) -> Self {
+        let layout = material.layout();
+        let (region, region_size) = if material.has_pawns() { (&QUEENSIDE, 32) } else { (&TRIANGLE, 10) };
+        let size = 2 * region_size * 64usize.pow(layout.len() as u32 - 1);
+        Self {
+            material,
+            layout,
+            region,
+            region_size,
+            data: vec![0; size],
+        }
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn symmetries(&self) -> usize {
+        if self.region_size == 32 { 2 } else { 8 }
+    }
+
+    /// Returns the smallest index over all board symmetries and orderings of identical pieces
+    fn canonical(&self, stm: Color, squares: &[Square]) -> usize {
+        let mut best = usize::MAX;
+        for symmetry in 0..self.symmetries() {
+            let mut mapped = [Square::A1; MAX_PIECES];
+            for (i, &sq) in squares.iter().enumerate() {
+                mapped[i] = transform(sq, symmetry);
+            }
+            let king = self.region[mapped[0].to_index()];
+            if king == NO_REGION {
+                continue;
+            }
+
+            let mut start = 1;
+            while start < self.layout.len() {
+                let mut end = start + 1;
+                while end < self.layout.len() && self.layout[end] == self.layout[start] {
+                    end += 1;
+                }
+                mapped[start..end].sort_by_key(|sq| sq.to_index());
+                start = end;
+            }
+
+            let mut idx = king as usize;
+            for sq in &mapped[1..self.layout.len()] {
+                idx = idx * 64 + sq.to_index();
+            }
+            best = best.min(idx * 2 + stm.to_index());
+        }
+        best
+    }
+
+    fn decode(&self, idx: usize) -> (Color, [Square; MAX_PIECES]) {
+        let stm = if idx & 1 == 0 { Color::White } else { Color::Black };
+        let mut rest = idx / 2;
+        let mut squares = [Square::A1; MAX_PIECES];
+        for i in (1..self.layout.len()).rev() {
+            squares[i] = Square::new((rest % 64) as u8);
+            rest /= 64;
+        }
+        let king = self.region.iter().position(|&r| r as usize == rest).unwrap();
+        squares[0] = Square::new(king as u8);
+        (stm, squares)
+    }
+
+    /// Returns the squares of each piece in index order
+    fn squares_of(&self, pos: &chess::Board, flip: bool) -> [Square; MAX_PIECES] {
+        let mut squares = [Square::A1; MAX_PIECES];
+        let mut i = 0;
+        while i < self.layout.len() {
+            let (piece, color) = self.layout[i];
+            let color = if flip { !color } else { color };
+            for sq in pos.pieces(piece) & pos.color_combined(color) {
+                squares[i] = if flip { Square::new(sq.to_int() ^ 56) } else { sq };
+                i += 1;
+            }
+        }
+        squares
+    }
+
+    /// Builds the position for an index, or `None` if the index is not a legal, canonical position
+    fn position(&self, idx: usize) -> Option<chess::Board> {
+        let (stm, squares) = self.decode(idx);
+        let squares = &squares[..self.layout.len()];
+        if self.canonical(stm, squares) != idx {
+            return None;
+        }
+
+        let mut occupied = EMPTY;
+        let mut pieces = Vec::with_capacity(self.layout.len());
+        for (&(piece, color), &sq) in self.layout.iter().zip(squares) {
+            if occupied & BitBoard::from_square(sq) != EMPTY
+            || (piece == Piece::Pawn && (sq.get_rank() == Rank::First || sq.get_rank() == Rank::Eighth)) {
+                return None;
+            }
+            occupied |= BitBoard::from_square(sq);
+            pieces.push((sq, piece, color));
+        }
+        if self.attacked(squares, !stm, stm, occupied) {
+            return None;
+        }
+
+        let builder = chess::BoardBuilder::setup(&pieces, stm, CastleRights::NoRights, CastleRights::NoRights, None);
+        chess::Board::try_from(builder).ok()
+    }
+
+    /// Returns true if the king of colour `king` is attacked by `by`
+    fn attacked(&self, squares: &[Square], king: Color, by: Color, occupied: BitBoard) -> bool {
+        let king_sq = BitBoard::from_square(squares[self.layout.iter().position(|&pc| pc == (Piece::King, king)).unwrap()]);
+        self.layout.iter().zip(squares).any(|(&(piece, color), &sq)| {
+            color == by && attacks(piece, color, sq, occupied) & king_sq != EMPTY
+        })
+    }
+
+    /// Returns the indices of every position that reaches this one with a quiet (non-capture,
+    /// non-promotion) move, found by retracting moves of the side that just moved
+    fn predecessors(&self, idx: usize) -> Vec<usize> {
+        let (stm, squares) = self.decode(idx);
+        let squares = &squares[..self.layout.len()];
+        let mover = !stm;
+        let occupied = squares.iter().fold(EMPTY, |bb, &sq| bb | BitBoard::from_square(sq));
+
+        let mut preds = Vec::new();
+        for (i, &(piece, color)) in self.layout.iter().enumerate() {
+            if color != mover {
+                continue;
+            }
+            let to = squares[i];
+            let origins = match piece {
+                Piece::Pawn => {
+                    let mut origins = EMPTY;
+                    if let Some(back) = to.backward(mover) {
+                        if back.get_rank() != mover.to_my_backrank() && occupied & BitBoard::from_square(back) == EMPTY {
+                            origins |= BitBoard::from_square(back);
+                            if to.get_rank() == mover.to_fourth_rank() {
+                                let start = back.ubackward(mover);
+                                if occupied & BitBoard::from_square(start) == EMPTY {
+                                    origins |= BitBoard::from_square(start);
+                                }
+                            }
+                        }
+                    }
+                    origins
+                },
+                _ => attacks(piece, color, to, occupied) & !occupied,
+            };
+
+            for from in origins {
+                let mut prev = [Square::A1; MAX_PIECES];
+                prev[..squares.len()].copy_from_slice(squares);
+                prev[i] = from;
+                let prev = &prev[..squares.len()];
+                let prev_occupied = occupied ^ BitBoard::from_square(to) ^ BitBoard::from_square(from);
+                if !self.attacked(prev, stm, mover, prev_occupied) {
+                    preds.push(self.canonical(mover, prev));
+                }
+            }
+        }
+        preds.sort_unstable();
+        preds.dedup();
+        preds
+    }
+
+    fn probe(&self, pos: &chess::Board, flip: bool) -> Outcome {
+        let squares = self.squares_of(pos, flip);
+        let stm = if flip { !pos.side_to_move() } else { pos.side_to_move() };
+        Outcome::from_byte(self.data[self.canonical(stm, &squares[..self.layout.len()])])
+    }
+
+    /// Solves every position of `material` by retrograde analysis
+    ///
+    /// Captures and promotions are resolved through the tables in `tablebases`, which must
+    /// already hold every dependency of `material`. Positions are then finalized in order
+    /// of their distance to mate: each loss makes its predecessors wins, and a predecessor
+    /// becomes a loss once all of its quiet moves lead to wins for the opponent.
+    pub fn generate(material: Material, tablebases: &Tablebases) -> Result<Self, String> {
+        if material.allows_en_passant() {
+            return Err(format!("{} has pawns on both sides, and en passant captures are not supported", material));
+        }
+        for dep in material.dependencies() {
+            if !tablebases.tables.contains_key(&dep) {
+                return Err(format!("{} requires the {} table", material, dep));
+            }
+        }
+
+        const ESCAPE_DRAW: u8 = 1;
+        const ESCAPE_WIN: u8 = 2;
+
+        let mut table = Self::new(material);
+        let size = table.data.len();
+        let mut valid = vec![false; size];
+        let mut remaining = vec![0u8; size];
+        let mut longest_loss = vec![0u8; size];
+        let mut escapes = vec![0u8; size];
+        let mut buckets: Vec<Vec<u32>> = vec![Vec::new(); u8::MAX as usize];
+
+        for idx in 0..size {
+            let Some(pos) = table.position(idx) else { continue };
+            valid[idx] = true;
+
+            let moves = MoveGen::new_legal(&pos);
+            if moves.len() == 0 {
+                if *pos.checkers() != EMPTY {
+                    buckets[0].push(idx as u32);
+                }
+                continue;
+            }
+
+            let mut children = Vec::with_capacity(moves.len());
+            let mut best_win = None;
+            for mv in moves {
+                let next = pos.make_move_new(mv);
+                if mv.get_promotion().is_some() || pos.piece_on(mv.get_dest()).is_some() {
+                    match tablebases.lookup(&next).unwrap().parent() {
+                        Outcome::Win(d) => best_win = Some(best_win.map_or(d, |w: u8| w.min(d))),
+                        Outcome::Draw => escapes[idx] |= ESCAPE_DRAW,
+                        Outcome::Loss(d) => longest_loss[idx] = longest_loss[idx].max(d),
+                    }
+                } else {
+                    children.push(table.canonical(next.side_to_move(), &table.squares_of(&next, false)[..table.layout.len()]));
+                }
+            }
+            children.sort_unstable();
+            children.dedup();
+            remaining[idx] = children.len() as u8;
+
+            if let Some(d) = best_win {
+                escapes[idx] |= ESCAPE_WIN;
+                buckets[d as usize].push(idx as u32);
+            } else if remaining[idx] == 0 && escapes[idx] == 0 {
+                buckets[longest_loss[idx] as usize].push(idx as u32);
+            }
+        }
+
+        for dist in 0..buckets.len() {
+            let bucket = std::mem::take(&mut buckets[dist]);
+            for idx in bucket {
+                let idx = idx as usize;
+                if table.data[idx] != 0 {
+                    continue;
+                }
+                table.data[idx] = dist as u8 + 1;
+
+                let next = dist + 1;
+                for pred in table.predecessors(idx) {
+                    if !valid[pred] || table.data[pred] != 0 {
+                        continue;
+                    }
+                    if next >= buckets.len() {
+                        return Err(format!("{} has a mate longer than {} plies", table.material, buckets.len()));
+                    }
+                    if dist % 2 == 0 {
+                        buckets[next].push(pred as u32);
+                    } else {
+                        remaining[pred] -= 1;
+                        longest_loss[pred] = longest_loss[pred].max(next as u8);
+                        if remaining[pred] == 0 && escapes[pred] == 0 {
+                            buckets[longest_loss[pred] as usize].push(pred as u32);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// Returns the longest distance to mate in the table
+    pub fn longest_mate(&self) -> u8 {
+        self.data.iter().max().map_or(0, |&d| d.saturating_sub(1))
+    }
+
+    /// Compares positions sampled from the table against a brute-force mate search of `depth`
+    /// plies, returning the number of positions checked
+    pub fn cross_check(&self, samples: usize, depth: u8) -> Result<usize, String> {
+        let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut checked = 0;
+        while checked < samples {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let idx = (seed % self.data.len() as u64) as usize;
+            let Some(pos) = self.position(idx) else { continue };
+
+            let expected = match Outcome::from_byte(self.data[idx]) {
+                Outcome::Win(d) if d <= depth => MATE_SCORE - d as i32,
+                Outcome::Loss(d) if d <= depth => -MATE_SCORE + d as i32,
+                _ => 0,
+            };
+            let score = mate_search(&pos, depth, 0, -MATE_SCORE - 1, MATE_SCORE + 1);
+            if score != expected {
+                return Err(format!("{}: table gives {:?}, search gives {} (expected {})",
+                    pos, Outcome::from_byte(self.data[idx]), score, expected));
+            }
+            checked += 1;
+        }
+        Ok(checked)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let name = self.material.to_string();
+        let mut file = fs::File::create(path)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&[name.len() as u8])?;
+        file.write_all(name.as_bytes())?;
+        file.write_all(&(self.data.len() as u32).to_le_bytes())?;
+        file.write_all(&self.data)
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), msg));
+
+        let mut bytes = Vec::new();
+        fs::File::open(path)?.read_to_end(&mut bytes)?;
+        if bytes.len() < 5 || &bytes[..4] != MAGIC {
+            return Err(invalid("not a tablebase file"));
+        }
+        let name_end = 5 + bytes[4] as usize;
+        let name = std::str::from_utf8(bytes.get(5..name_end).ok_or_else(|| invalid("truncated header"))?)
+            .map_err(|_| invalid("bad signature"))?;
+        let material = Material::from_str(name).map_err(|e| invalid(&e))?;
+        if material.allows_en_passant() {
+            return Err(invalid("pawns on both sides are not supported"));
+        }
+        let len_bytes = bytes.get(name_end..name_end + 4).ok_or_else(|| invalid("truncated header"))?;
+        let len = u32::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+
+        let mut table = Self::new(material);
+        if len != table.data.len() || bytes.len() != name_end + 4 + len {
+            return Err(invalid("unexpected table size"));
+        }
+        table.data.copy_from_slice(&bytes[name_end + 4..]);
+        Ok(table)
+    }
+}
+
+/// A set of tables, probed by material signature
+#[derive(Default)]
+pub struct Tablebases {
+    tables: HashMap<Material, Table>,
+    max_pieces: usize,
+}
+
+impl Tablebases {
+    /// Loads every table file in a directory
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let mut tablebases = Self::default();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == FILE_EXTENSION) {
+                tablebases.insert(Table::read(&path)?);
+            }
+        }
+        Ok(tablebases)
+    }
+
+    pub fn insert(&mut self, table: Table) {
+        self.max_pieces = self.max_pieces.max(table.layout.len());
+        self.tables.insert(table.material.clone(), table);
+    }
+
+    pub fn contains(&self, material: &Material) -> bool {
+        self.tables.contains_key(material)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// Returns true if the position has no castling rights or en passant square, and few enough pieces
+    ///
+    /// unlike `probe` this also sees Chess960 castling rights, which the board keeps outside the position
+    pub fn can_probe(&self, board: &Board) -> bool {
+        !board.has_castling_rights()
+            && board.position.en_passant().is_none()
+            && board.position.combined().popcnt() as usize <= self.max_pieces
+    }
+
+    /// Returns the outcome of a position, if its material is covered by a loaded table
+    ///
+    /// the position must not come from a Chess960 board with castling rights, see `can_probe`
+    pub fn probe(&self, pos: &chess::Board) -> Option<Outcome> {
+        if pos.combined().popcnt() as usize > self.max_pieces
+            || pos.en_passant().is_some()
+            || pos.castle_rights(Color::White) != CastleRights::NoRights
+            || pos.castle_rights(Color::Black) != CastleRights::NoRights {
+            return None;
+        }
+        self.lookup(pos)
+    }
+
+    fn lookup(&self, pos: &chess::Board) -> Option<Outcome> {
+        let (material, flip) = Material::from_board(pos);
+        if material.piece_count() == 2 {
+            return Some(Outcome::Draw);
+        }
+        self.tables.get(&material).map(|table| table.probe(pos, flip))
+    }
+
+    /// Returns the move with the best outcome at the root, if every move can be probed
+    pub fn probe_root(&self, pos: &chess::Board) -> Option<(Move, Outcome)> {
+        self.probe(pos)?;
+
+        let mut best: Option<(Move, Outcome)> = None;
+        for mv in MoveGen::new_legal(pos) {
+            let outcome = self.probe(&pos.make_move_new(mv))?.parent();
+            match best {
+                Some((_, b)) if b.rank() >= outcome.rank() => (),
+                _ => best = Some((mv, outcome)),
+            }
+        }
+        best
+    }
+}
+
+fn transform(sq: Square, symmetry: usize) -> Square {
+    let (mut file, mut rank) = (sq.to_int() % 8, sq.to_int() / 8);
+    if symmetry & 4 != 0 {
+        std::mem::swap(&mut file, &mut rank);
+    }
+    if symmetry & 1 != 0 {
+        file = 7 - file;
+    }
+    if symmetry & 2 != 0 {
+        rank = 7 - rank;
+    }
+    Square::new(rank * 8 + file)
+}
+
+fn attacks(piece: Piece, color: Color, sq: Square, occupied: BitBoard) -> BitBoard {
+    match piece {
+        Piece::Pawn => get_pawn_attacks(sq, color, !EMPTY),
+        Piece::Knight => get_knight_moves(sq),
+        Piece::Bishop => get_bishop_moves(sq, occupied),
+        Piece::Rook => get_rook_moves(sq, occupied),
+        Piece::Queen => get_bishop_moves(sq, occupied) | get_rook_moves(sq, occupied),
+        Piece::King => get_king_moves(sq),
+    }
+}
+
+const MATE_SCORE: i32 = 1000;
+
+/// Plain alpha-beta search that only scores checkmates
+fn mate_search(pos: &chess::Board, depth: u8, ply: u8, mut alpha: i32, beta: i32) -> i32 {
+    let moves = MoveGen::new_legal(pos);
+    if moves.len() == 0 {
+        return if *pos.checkers() != EMPTY { -MATE_SCORE + ply as i32 } else { 0 };
+    }
+    if depth == 0 {
+        return 0;
+    }
+    for mv in moves {
+        let score = -mate_search(&pos.make_move_new(mv), depth - 1, ply + 1, -beta, -alpha);
+        if score >= beta {
+            return beta;
+        }
+        alpha = alpha.max(score);
+    }
+    alpha
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kpk;
+
+    fn generate_all(signatures: &[&str]) -> Tablebases {
+        let mut tablebases = Tablebases::default();
+        for sig in signatures {
+            let table = Table::generate(Material::from_str(sig).unwrap(), &tablebases).unwrap();
+            tablebases.insert(table);
+        }
+        tablebases
+    }
+
+    fn probe_fen(tablebases: &Tablebases, fen: &str) -> Option<Outcome> {
+        tablebases.probe(&chess::Board::from_str(fen).unwrap())
+    }
+
+    #[test]
+    fn test_material() {
+        assert_eq!(Material::from_str("KQvKR").unwrap().to_string(), "KQvKR");
+        assert_eq!(Material::from_str("KvKQ").unwrap().to_string(), "KQvK");
+        assert_eq!(Material::from_str("KPvKR").unwrap().to_string(), "KRvKP");
+        assert!(Material::from_str("KQRvKR").is_err());
+        assert!(Material::from_str("QvK").is_err());
+
+        let deps = Material::from_str("KPvK").unwrap().dependencies();
+        assert_eq!(deps.len(), 4);
+        assert!(deps.contains(&Material::from_str("KQvK").unwrap()));
+        let deps = Material::from_str("KQvKR").unwrap().dependencies();
+        assert_eq!(deps, vec![Material::from_str("KRvK").unwrap(), Material::from_str("KQvK").unwrap()]);
+    }
+
+    #[test]
+    fn test_generate_missing_dependency() {
+        assert!(Table::generate(Material::from_str("KPvK").unwrap(), &Tablebases::default()).is_err());
+    }
+
+    #[test]
+    fn test_generate_en_passant() {
+        // refused before the dependencies are even looked at
+        let material = Material::from_str("KPvKP").unwrap();
+        assert!(material.allows_en_passant());
+        assert!(Table::generate(material, &Tablebases::default()).is_err_and(|e| e.contains("en passant")));
+        assert!(!Material::from_str("KQvKP").unwrap().allows_en_passant());
+    }
+
+    #[test]
+    fn test_longest_mates() {
+        let tablebases = generate_all(&["KQvK", "KRvK"]);
+        // mated after ten and sixteen moves
+        assert_eq!(tablebases.tables[&Material::from_str("KQvK").unwrap()].longest_mate(), 20);
+        assert_eq!(tablebases.tables[&Material::from_str("KRvK").unwrap()].longest_mate(), 32);
+    }
+
+    #[test]
+    fn test_probe() {
+        let tablebases = generate_all(&["KQvK", "KRvK"]);
+        assert_eq!(probe_fen(&tablebases, "7k/8/6K1/8/8/8/8/1Q6 w - - 0 1"), Some(Outcome::Win(1)));
+        assert_eq!(probe_fen(&tablebases, "Q6k/8/6K1/8/8/8/8/8 b - - 0 1"), Some(Outcome::Loss(0)));
+        // colours reversed
+        assert_eq!(probe_fen(&tablebases, "1q6/8/8/8/8/6k1/8/7K b - - 0 1"), Some(Outcome::Win(1)));
+        // queen is lost immediately
+        assert_eq!(probe_fen(&tablebases, "8/8/8/8/8/8/6kQ/4K3 b - - 0 1"), Some(Outcome::Draw));
+        // stalemate
+        assert_eq!(probe_fen(&tablebases, "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), Some(Outcome::Draw));
+        // missing table, castling rights
+        assert_eq!(probe_fen(&tablebases, "7k/8/6K1/8/8/8/8/1B6 w - - 0 1"), None);
+        assert_eq!(probe_fen(&tablebases, "k7/8/8/8/8/8/8/4K2R w K - 0 1"), None);
+        assert!(!tablebases.can_probe(&Board::from_fen("k7/8/8/8/8/8/8/4K2R w K - 0 1").unwrap()));
+        // Chess960 castling rights aren't in the position
+        let chess960 = Board::from_fen("k7/8/8/8/8/8/8/1R2K3 w B - 0 1").unwrap();
+        assert!(chess960.is_chess960() && chess960.has_castling_rights());
+        assert!(!tablebases.can_probe(&chess960));
+        assert!(tablebases.can_probe(&Board::from_fen("k7/8/8/8/8/8/8/1R2K3 w - - 0 1").unwrap()));
+
+        let (mv, outcome) = tablebases.probe_root(&chess::Board::from_str("7k/8/6K1/8/8/8/8/1Q6 w - - 0 1").unwrap()).unwrap();
+        assert_eq!(mv, Move::new(Square::B1, Square::B8, None));
+        assert_eq!(outcome, Outcome::Win(1));
+    }
+
+    #[test]
+    fn test_kpvk_matches_bitbase() {
+        let tablebases = generate_all(&["KQvK", "KRvK", "KBvK", "KNvK", "KPvK"]);
+        let table = &tablebases.tables[&Material::from_str("KPvK").unwrap()];
+        for idx in 0..table.data.len() {
+            if let Some(pos) = table.position(idx) {
+                let won = matches!(Outcome::from_byte(table.data[idx]), Outcome::Win(_));
+                let lost = matches!(Outcome::from_byte(table.data[idx]), Outcome::Loss(_));
+                let white_to_move = pos.side_to_move() == Color::White;
+                assert!(!(won && !white_to_move || lost && white_to_move), "{}", pos);
+                assert_eq!(won || lost, kpk::probe(&pos), "{}", pos);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cross_check() {
+        let tablebases = generate_all(&["KQvK", "KRvK", "KBvK", "KNvK", "KPvK"]);
+        for table in tablebases.tables.values() {
+            assert!(table.cross_check(100, 5).is_ok(), "{}", table.material);
+        }
+    }
+
+    #[test]
+    fn test_write_read() {
+        let tablebases = generate_all(&["KRvK"]);
+        let table = &tablebases.tables[&Material::from_str("KRvK").unwrap()];
+        let path = std::env::temp_dir().join(format!("engine-test-KRvK.{}", FILE_EXTENSION));
+        table.write(&path).unwrap();
+        let read = Table::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.material, table.material);
+        assert!(read.data == table.data);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{board::Move, eval::Eval, search::SearchLimits};
+
+/// Moves the remaining clock is spread over when there is no `movestogo`
+const MOVE_HORIZON: u32 = 40;
+/// The largest share of the clock a single move may use, in percent
+const MAX_CLOCK_SHARE: u32 = 75;
+/// How many times the optimum time a move may use when the search is unstable
+const MAX_OPTIMUM_RATIO: u32 = 5;
+
+/// Decides how long to search for a move from the clock, and when to stop iterating
+///
+/// the optimum time is a target that grows or shrinks with how settled the search is,
+/// the maximum time is never exceeded
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeManager {
+    optimum: u32,
+    maximum: u32,
+    /// a fixed move time is used in full, however the search goes
+    fixed: bool,
+    /// the optimum time is scaled by this after each iteration
+    scale: f64,
+    /// consecutive iterations that kept the best move
+    stability: u32,
+    best_move: Option<Move>,
+    score: Option<Eval>,
+}
+
+impl TimeManager {
+    /// Allocates time for the next move, or returns `None` if the search has no time limit
+    ///
+    /// `move_overhead` milliseconds of every move are lost to communication with the GUI
+    pub fn new(limits: &SearchLimits, move_overhead: u32) -> Option<Self> {
+        if let Some(ms) = limits.move_time {
+            let ms = ms.saturating_sub(move_overhead).max(1);
+            return Some(Self::with_limits(ms, ms, true));
+        }
+
+        // done in u64, as a long clock with the increments still to come doesn't fit in a u32
+        let time = limits.time? as u64;
+        let moves_left = limits.movestogo.map_or(MOVE_HORIZON, |moves| moves.clamp(1, MOVE_HORIZON)) as u64;
+        let move_overhead = move_overhead as u64;
+        // increments still to come are spent as they arrive, and no move uses all of the clock that is left
+        let budget = (time + limits.inc as u64 * (moves_left - 1)).saturating_sub(move_overhead);
+        let safe = time.saturating_sub(move_overhead) * MAX_CLOCK_SHARE as u64 / 100;
+
+        let optimum = (budget / moves_left).min(safe);
+        let maximum = (optimum * MAX_OPTIMUM_RATIO as u64).min(safe);
+        let ms = |ms: u64| u32::try_from(ms).unwrap_or(u32::MAX);
+        Some(Self::with_limits(ms(optimum), ms(maximum), false))
+    }
+
+    fn with_limits(optimum: u32, maximum: u32, fixed: bool) -> Self {
+        Self { optimum, maximum, fixed, scale: 1.0, stability: 0, best_move: None, score: None }
+    }
+
+    /// Returns the time the search aims for, scaled by how the iterations so far went
+    pub fn optimum(&self) -> u32 {
+        ((self.optimum as f64 * self.scale) as u32).min(self.maximum)
+    }
+
+    /// Returns the time the search may never exceed
+    pub fn maximum(&self) -> u32 {
+        self.maximum
+    }
+
+    /// Rescales the optimum time after a completed iteration
+    ///
+    /// a best move that keeps changing, a dropping score, or a best move that only took a small share of the
+    /// iteration's nodes means the search hasn't settled, and gets more time. `best_move_nodes` and `nodes` count
+    /// the nodes searched below the best move and in the whole iteration
+    pub fn update(&mut self, best_move: Move, score: Eval, best_move_nodes: u64, nodes: u64) {
+        self.stability = if self.best_move == Some(best_move) { (self.stability + 1).min(6) } else { 0 };
+        let stability = 1.5 - 0.1 * self.stability as f64;
+
+        let drop = self.score.map_or(0, |last| (last - score).clamp(0, 100));
+        let falling = 1.0 + drop as f64 / 200.0;
+
+        let share = best_move_nodes as f64 / nodes.max(1) as f64;
+        let effort = 1.5 - share.min(1.0);
+
+        self.scale = stability * falling * effort;
+        self.best_move = Some(best_move);
+        self.score = Some(score);
+    }
+
+    /// Returns true if no further iteration should be started after `elapsed` milliseconds
+    ///
+    /// the next iteration usually takes longer than all those before it, so none is started past half the optimum
+    pub fn soft_limit_reached(&self, elapsed: u32) -> bool {
+        if self.fixed {
+            return elapsed >= self.maximum;
+        }
+        elapsed >= self.optimum() / 2
+    }
+
+    /// Returns true once the search must stop, even in the middle of an iteration
+    pub fn hard_limit_reached(&self, elapsed: u32) -> bool {
+        elapsed >= self.maximum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess::Square;
+
+    fn clock(time: u32, inc: u32, movestogo: Option<u32>) -> SearchLimits {
+        SearchLimits { time: Some(time), inc, movestogo, ..SearchLimits::default() }
+    }
+
+    #[test]
+    fn test_allocation() {
+        assert_eq!(TimeManager::new(&SearchLimits::default(), 10), None);
+        assert_eq!(TimeManager::new(&SearchLimits::depth(5), 10), None);
+
+        // a fixed move time loses only the overhead
+        let tm = TimeManager::new(&SearchLimits::move_time(500), 10).unwrap();
+        assert_eq!((tm.optimum(), tm.maximum()), (490, 490));
+        assert!(!tm.soft_limit_reached(400));
+
+        let tm = TimeManager::new(&clock(60000, 0, None), 0).unwrap();
+        assert_eq!((tm.optimum(), tm.maximum()), (1500, 7500));
+        // increments still to come are counted in
+        let tm = TimeManager::new(&clock(60000, 1000, None), 0).unwrap();
+        assert_eq!(tm.optimum(), 2475);
+        // the time is shared between the moves left until the next time control
+        let tm = TimeManager::new(&clock(60000, 0, Some(10)), 0).unwrap();
+        assert_eq!((tm.optimum(), tm.maximum()), (6000, 30000));
+        // the last move before the time control keeps a reserve
+        let tm = TimeManager::new(&clock(60000, 0, Some(1)), 100).unwrap();
+        assert_eq!((tm.optimum(), tm.maximum()), (44925, 44925));
+        // the overhead leaves nothing
+        let tm = TimeManager::new(&clock(5, 0, None), 10).unwrap();
+        assert_eq!((tm.optimum(), tm.maximum()), (0, 0));
+        // very long clocks don't overflow
+        let tm = TimeManager::new(&clock(60_000_000, 0, None), 0).unwrap();
+        assert_eq!((tm.optimum(), tm.maximum()), (1_500_000, 7_500_000));
+        let tm = TimeManager::new(&clock(u32::MAX, u32::MAX, None), 0).unwrap();
+        assert_eq!((tm.optimum(), tm.maximum()), (3_221_225_471, 3_221_225_471));
+    }
+
+    #[test]
+    fn test_stability() {
+        let e4 = Move::new(Square::E2, Square::E4, None);
+        let d4 = Move::new(Square::D2, Square::D4, None);
+        let mut stable = TimeManager::new(&clock(60000, 0, None), 0).unwrap();
+        let mut unstable = stable.clone();
+        for iteration in 0..8 {
+            stable.update(e4, 20, 500, 1000);
+            unstable.update(if iteration % 2 == 0 { e4 } else { d4 }, 20, 500, 1000);
+        }
+        assert!(stable.optimum() < 1500 && unstable.optimum() > 1500);
+
+        // a dropping score and a best move that took few of the nodes both extend the search
+        let mut dropping = TimeManager::new(&clock(60000, 0, None), 0).unwrap();
+        dropping.update(e4, 50, 900, 1000);
+        dropping.update(e4, -50, 900, 1000);
+        let mut settled = TimeManager::new(&clock(60000, 0, None), 0).unwrap();
+        settled.update(e4, 50, 900, 1000);
+        settled.update(e4, 50, 900, 1000);
+        assert!(dropping.optimum() > settled.optimum());
+        let mut contested = settled.clone();
+        contested.update(e4, 50, 200, 1000);
+        settled.update(e4, 50, 900, 1000);
+        assert!(contested.optimum() > settled.optimum());
+    }
+
+    /// Plays out a game on the clock, with iterations that each take twice as long as the one before
+    ///
+    /// returns the milliseconds used on each move, or `None` if the clock ran out
+    fn simulate(time: u32, inc: u32, control: Option<u32>, move_overhead: u32, moves: u32) -> Option<Vec<u32>> {
+        let e4 = Move::new(Square::E2, Square::E4, None);
+        let d4 = Move::new(Square::D2, Square::D4, None);
+        let mut clock = time;
+        let mut used = Vec::new();
+        for move_number in 0..moves {
+            let movestogo = control.map(|control| control - move_number % control);
+            let mut tm = TimeManager::new(&SearchLimits { time: Some(clock), inc, movestogo, ..SearchLimits::default() }, move_overhead)?;
+
+            let mut elapsed = 0;
+            let mut iteration_time = 1;
+            for iteration in 0.. {
+                if tm.hard_limit_reached(elapsed + iteration_time) {
+                    elapsed = tm.maximum();
+                    break;
+                }
+                elapsed += iteration_time;
+                iteration_time *= 2;
+                // every third move the best move changes late in the search, with a dropping score
+                let unstable = move_number % 3 == 0 && iteration > 6;
+                let best_move = if unstable && iteration % 2 == 0 { d4 } else { e4 };
+                let score = if unstable { -10 * iteration } else { 0 };
+                tm.update(best_move, score, 700, 1000);
+                if tm.soft_limit_reached(elapsed) {
+                    break;
+                }
+            }
+
+            // the GUI loses the overhead on top of the search time
+            clock = clock.checked_sub(elapsed + move_overhead)?;
+            used.push(elapsed);
+            clock += inc;
+            if let Some(control) = control {
+                if (move_number + 1) % control == 0 {
+                    clock += time;
+                }
+            }
+        }
+        Some(used)
+    }
+
+    #[test]
+    fn test_simulated_game() {
+        // sudden death, with and without an increment
+        let used = simulate(60000, 0, None, 10, 200).unwrap();
+        assert!(used[0] >= 500 && used[0] <= 7500);
+        assert!(used[100] < used[0]);
+        let used = simulate(10000, 100, None, 10, 300).unwrap();
+        assert!(used[299] >= 32);
+        // repeating time controls use most of each control
+        let used = simulate(60000, 0, Some(40), 10, 120).unwrap();
+        let first_control = used[..40].iter().sum::<u32>();
+        assert!(first_control > 30000 && first_control < 60000);
+        // a tiny clock with a big overhead still never flags, as long as the increment covers the overhead
+        assert!(simulate(1000, 30, None, 20, 300).is_some());
+    }
+}
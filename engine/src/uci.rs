@@ -1,218 +1,947 @@
 use std::env::current_dir;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::Path;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
-use std::thread;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 use std::{io::stdin, time::Instant};
 use std::str::FromStr;
 
 use chess::Color;
 
-use crate::{board::{Board, Move}, search::SearchContext};
+use crate::{board::{Board, FenError, Move, RepetitionRule}, epd::Epd, eval::Eval, perft::perft, polyglot::Book, search::{SearchContext, SearchLimits}, syzygy::Syzygy, tablebase::Tablebases, tt::TranspositionTable};
+
+/// Reasons a line from the GUI can fail to parse or apply
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UciError {
+    /// a blank line
+    Empty,
+    UnknownCommand(String),
+    /// a keyword that must be followed by a value
+    MissingValue(String),
+    /// a keyword followed by a value of the wrong type
+    InvalidValue(String, String),
+    InvalidMove(String),
+    /// a move that is well formed but can't be played in the position
+    IllegalMove(Move),
+    InvalidFen(FenError),
+    UnknownOption(String),
+}
+
+impl fmt::Display for UciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UciError::Empty => write!(f, "empty command"),
+            UciError::UnknownCommand(s) => write!(f, "unknown command '{}'", s),
+            UciError::MissingValue(keyword) => write!(f, "expected a value after '{}'", keyword),
+            UciError::InvalidValue(keyword, value) => write!(f, "invalid value '{}' for '{}'", value, keyword),
+            UciError::InvalidMove(s) => write!(f, "invalid move '{}'", s),
+            UciError::IllegalMove(mv) => write!(f, "illegal move '{}'", mv),
+            UciError::InvalidFen(e) => write!(f, "invalid fen: {}", e),
+            UciError::UnknownOption(name) => write!(f, "unknown option '{}'", name),
+        }
+    }
+}
+
+impl std::error::Error for UciError { }
+
+impl From<FenError> for UciError {
+    fn from(e: FenError) -> Self {
+        UciError::InvalidFen(e)
+    }
+}
+
+/// The position a `position` command starts from, before its moves
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StartPosition {
+    StartPos,
+    Fen(String),
+}
+
+/// The parameters of a `go` command, all optional
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GoParams {
+    pub searchmoves: Vec<Move>,
+    pub ponder: bool,
+    /// remaining clock times in milliseconds, negative once a GUI lets a clock run out
+    pub wtime: Option<i64>,
+    pub btime: Option<i64>,
+    pub winc: Option<i64>,
+    pub binc: Option<i64>,
+    pub movestogo: Option<u32>,
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub mate: Option<u8>,
+    pub movetime: Option<u32>,
+    pub infinite: bool,
+}
+
+/// Engine specific commands for measuring speed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Benchmark {
+    /// nodes per second over positions from `tools/res/lichess_elite_smaller.epd`
+    Nps { trials: usize, move_time: u32 },
+    /// move generation and make/undo speed from the current position
+    Perft { depth: u8 },
+}
+
+/// A command from the GUI
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UciCommand {
+    Uci,
+    Debug(bool),
+    IsReady,
+    /// `setoption name <name> [value <value>]`, where both may contain spaces
+    SetOption { name: String, value: Option<String> },
+    UciNewGame,
+    Position { start: StartPosition, moves: Vec<Move> },
+    Go(GoParams),
+    Stop,
+    PonderHit,
+    Quit,
+    Benchmark(Benchmark),
+}
+
+impl FromStr for UciCommand {
+    type Err = UciError;
+
+    /// Parses a line from the GUI
+    ///
+    /// as the protocol asks, unknown tokens before the command and unknown `go` parameters are skipped
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = s.split_whitespace().collect::<Vec<&str>>();
+        if tokens.is_empty() {
+            return Err(UciError::Empty);
+        }
+        let start = tokens.iter().position(|t| COMMANDS.contains(t))
+            .ok_or_else(|| UciError::UnknownCommand(tokens[0].to_string()))?;
+        let args = &tokens[start + 1..];
+
+        match tokens[start] {
+            "uci" => Ok(UciCommand::Uci),
+            "debug" => match args.first() {
+                Some(&"on") => Ok(UciCommand::Debug(true)),
+                Some(&"off") => Ok(UciCommand::Debug(false)),
+                Some(value) => Err(UciError::InvalidValue("debug".to_string(), value.to_string())),
+                None => Err(UciError::MissingValue("debug".to_string())),
+            },
+            "isready" => Ok(UciCommand::IsReady),
+            "setoption" => parse_setoption(args),
+            "ucinewgame" => Ok(UciCommand::UciNewGame),
+            "position" => parse_position(args),
+            "go" => parse_go(args).map(UciCommand::Go),
+            "stop" => Ok(UciCommand::Stop),
+            "ponderhit" => Ok(UciCommand::PonderHit),
+            "quit" => Ok(UciCommand::Quit),
+            "benchmark" => parse_benchmark(args).map(UciCommand::Benchmark),
+            _ => unreachable!(),
+        }
+    }
+}
+
+const COMMANDS: [&str; 11] = [
+    "uci", "debug", "isready", "setoption", "ucinewgame", "position", "go", "stop", "ponderhit", "quit", "benchmark",
+];
+
+const GO_KEYWORDS: [&str; 12] = [
+    "searchmoves", "ponder", "wtime", "btime", "winc", "binc", "movestogo", "depth", "nodes", "mate", "movetime", "infinite",
+];
+
+/// Parses the value following `args[idx]`
+fn parse_value<T: FromStr>(args: &[&str], idx: usize) -> Result<T, UciError> {
+    let value = args.get(idx + 1).ok_or_else(|| UciError::MissingValue(args[idx].to_string()))?;
+    value.parse().map_err(|_| UciError::InvalidValue(args[idx].to_string(), value.to_string()))
+}
+
+fn parse_move(s: &str) -> Result<Move, UciError> {
+    Move::from_str(s).map_err(|_| UciError::InvalidMove(s.to_string()))
+}
+
+fn parse_setoption(args: &[&str]) -> Result<UciCommand, UciError> {
+    if args.first() != Some(&"name") {
+        return Err(UciError::MissingValue("setoption".to_string()));
+    }
+    let value_start = args.iter().position(|&t| t == "value").unwrap_or(args.len());
+    let name = args[1..value_start].join(" ");
+    if name.is_empty() {
+        return Err(UciError::MissingValue("name".to_string()));
+    }
+    let value = (value_start < args.len()).then(|| args[value_start + 1..].join(" "));
+    Ok(UciCommand::SetOption { name, value })
+}
+
+fn parse_position(args: &[&str]) -> Result<UciCommand, UciError> {
+    let moves_start = args.iter().position(|&t| t == "moves").unwrap_or(args.len());
+    let start = match args.first() {
+        Some(&"startpos") => StartPosition::StartPos,
+        Some(&"fen") if moves_start > 1 => StartPosition::Fen(args[1..moves_start].join(" ")),
+        Some(&"fen") => return Err(UciError::MissingValue("fen".to_string())),
+        Some(other) => return Err(UciError::InvalidValue("position".to_string(), other.to_string())),
+        None => return Err(UciError::MissingValue("position".to_string())),
+    };
+    let moves = args.get(moves_start + 1..).unwrap_or_default().iter()
+        .map(|s| parse_move(s))
+        .collect::<Result<Vec<Move>, UciError>>()?;
+    Ok(UciCommand::Position { start, moves })
+}
+
+fn parse_go(args: &[&str]) -> Result<GoParams, UciError> {
+    let mut params = GoParams::default();
+    let mut idx = 0;
+    while idx < args.len() {
+        match args[idx] {
+            "searchmoves" => {
+                while idx + 1 < args.len() && !GO_KEYWORDS.contains(&args[idx + 1]) {
+                    params.searchmoves.push(parse_move(args[idx + 1])?);
+                    idx += 1;
+                }
+            },
+            "ponder" => params.ponder = true,
+            "infinite" => params.infinite = true,
+            keyword if GO_KEYWORDS.contains(&keyword) => {
+                match keyword {
+                    "wtime" => params.wtime = Some(parse_value(args, idx)?),
+                    "btime" => params.btime = Some(parse_value(args, idx)?),
+                    "winc" => params.winc = Some(parse_value(args, idx)?),
+                    "binc" => params.binc = Some(parse_value(args, idx)?),
+                    "movestogo" => params.movestogo = Some(parse_value(args, idx)?),
+                    "depth" => params.depth = Some(parse_value(args, idx)?),
+                    "nodes" => params.nodes = Some(parse_value(args, idx)?),
+                    "mate" => params.mate = Some(parse_value(args, idx)?),
+                    _ => params.movetime = Some(parse_value(args, idx)?),
+                }
+                // skip the value
+                idx += 1;
+            },
+            _ => (),
+        }
+        idx += 1;
+    }
+    Ok(params)
+}
+
+fn parse_benchmark(args: &[&str]) -> Result<Benchmark, UciError> {
+    match args.first() {
+        Some(&"nps") => {
+            let (Some(trials), Some(move_time)) = (args.get(1), args.get(2)) else {
+                return Err(UciError::MissingValue("nps".to_string()));
+            };
+            let invalid = |value: &str| UciError::InvalidValue("nps".to_string(), value.to_string());
+            Ok(Benchmark::Nps {
+                trials: trials.parse().map_err(|_| invalid(trials))?,
+                move_time: move_time.parse().map_err(|_| invalid(move_time))?,
+            })
+        },
+        Some(&"perft") => match parse_value(args, 0)? {
+            0 => Err(UciError::InvalidValue("perft".to_string(), "0".to_string())),
+            depth => Ok(Benchmark::Perft { depth }),
+        },
+        Some(other) => Err(UciError::InvalidValue("benchmark".to_string(), other.to_string())),
+        None => Err(UciError::MissingValue("benchmark".to_string())),
+    }
+}
+
+/// The type of a UCI option, with its default, limits and the setter called with a validated value
+pub enum OptionType {
+    Spin { default: i64, min: i64, max: i64, set: fn(&mut UciState, i64) },
+    Check { default: bool, set: fn(&mut UciState, bool) },
+    Combo { default: &'static str, vars: &'static [&'static str], set: fn(&mut UciState, &str) },
+    Button { press: fn(&mut UciState) },
+    /// `<empty>` is passed on to the setter, which should treat it as an empty string
+    String { default: &'static str, set: fn(&mut UciState, &str) },
+}
+
+pub struct UciOption {
+    pub name: &'static str,
+    pub kind: OptionType,
+}
+
+impl fmt::Display for UciOption {
+    /// Writes the option as it is declared in reply to `uci`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "option name {} type ", self.name)?;
+        match &self.kind {
+            OptionType::Spin { default, min, max, .. } => write!(f, "spin default {} min {} max {}", default, min, max),
+            OptionType::Check { default, .. } => write!(f, "check default {}", default),
+            OptionType::Combo { default, vars, .. } => {
+                write!(f, "combo default {}", default)?;
+                vars.iter().try_for_each(|var| write!(f, " var {}", var))
+            },
+            OptionType::Button { .. } => write!(f, "button"),
+            OptionType::String { default, .. } => write!(f, "string default {}", default),
+        }
+    }
+}
+
+/// Every option the engine declares, in the order they are sent to the GUI
+pub static OPTIONS: [UciOption; 17] = [
+    UciOption { name: "Hash", kind: OptionType::Spin { default: 16, min: 1, max: 1048576, set: |state, mb| {
+        match state.tt_mut() {
+            Some(tt) => tt.resize(mb as usize),
+            None => println!("info string can't resize the hash during a search"),
+        }
+    } } },
+    UciOption { name: "Clear Hash", kind: OptionType::Button { press: |state| {
+        match state.tt_mut() {
+            Some(tt) => tt.clear(),
+            None => println!("info string can't clear the hash during a search"),
+        }
+    } } },
+    // the search is single threaded
+    UciOption { name: "Threads", kind: OptionType::Spin { default: 1, min: 1, max: 1, set: |_, _| () } },
+    UciOption { name: "MultiPV", kind: OptionType::Spin { default: 1, min: 1, max: 256, set: |state, lines| {
+        state.sc.multi_pv = lines as usize;
+    } } },
+    // declared so GUIs send `go ponder`, which needs nothing set up in advance
+    UciOption { name: "Ponder", kind: OptionType::Check { default: false, set: |_, _| () } },
+    UciOption { name: "Move Overhead", kind: OptionType::Spin { default: 10, min: 0, max: 5000, set: |state, ms| {
+        state.sc.move_overhead = ms as u32;
+    } } },
+    UciOption { name: "TablebasePath", kind: OptionType::String { default: "<empty>", set: |state, path| {
+        if path == "<empty>" || path.is_empty() {
+            state.sc.tablebases = Arc::new(Tablebases::default());
+            return;
+        }
+        match Tablebases::load(Path::new(path)) {
+            Ok(tablebases) => {
+                println!("info string loaded {} tablebases", tablebases.len());
+                state.sc.tablebases = Arc::new(tablebases);
+            },
+            Err(e) => println!("info string failed to load tablebases: {}", e),
+        }
+    } } },
+    UciOption { name: "SyzygyPath", kind: OptionType::String { default: "<empty>", set: |state, path| {
+        let path = if path == "<empty>" { "" } else { path };
+        match Syzygy::load(path) {
+            Ok(syzygy) => {
+                println!("info string found {} syzygy tables up to {} pieces", syzygy.len(), syzygy.max_pieces());
+                state.sc.syzygy = Arc::new(syzygy);
+            },
+            Err(e) => println!("info string failed to load syzygy tables: {}", e),
+        }
+    } } },
+    UciOption { name: "SyzygyProbeDepth", kind: OptionType::Spin { default: 1, min: 1, max: 100, set: |state, depth| {
+        state.sc.syzygy_probe_depth = depth as u8;
+    } } },
+    UciOption { name: "Syzygy50MoveRule", kind: OptionType::Check { default: true, set: |state, on| {
+        state.sc.syzygy_50_move_rule = on;
+    } } },
+    UciOption { name: "Contempt", kind: OptionType::Spin { default: 0, min: -100, max: 100, set: |state, contempt| {
+        state.sc.contempt = contempt as Eval;
+    } } },
+    UciOption { name: "RepetitionRule", kind: OptionType::Combo { default: "Twofold", vars: &["Twofold", "Threefold"], set: |state, rule| {
+        state.sc.repetition_rule = match rule {
+            "Threefold" => RepetitionRule::Threefold,
+            _ => RepetitionRule::Twofold,
+        };
+    } } },
+    UciOption { name: "OwnBook", kind: OptionType::Check { default: false, set: |state, on| {
+        state.sc.own_book = on;
+    } } },
+    UciOption { name: "BookFile", kind: OptionType::String { default: "<empty>", set: |state, path| {
+        if path == "<empty>" || path.is_empty() {
+            state.sc.book = Arc::new(Book::default());
+            return;
+        }
+        match Book::load(Path::new(path)) {
+            Ok(book) => {
+                println!("info string loaded book with {} entries", book.len());
+                state.sc.book = Arc::new(book);
+            },
+            Err(e) => println!("info string failed to load book: {}", e),
+        }
+    } } },
+    UciOption { name: "BookDepth", kind: OptionType::Spin { default: 16, min: 1, max: 100, set: |state, depth| {
+        state.sc.book_depth = depth as u16;
+    } } },
+    UciOption { name: "BestBookMove", kind: OptionType::Check { default: false, set: |state, on| {
+        state.sc.best_book_move = on;
+    } } },
+    UciOption { name: "UCI_Chess960", kind: OptionType::Check { default: false, set: |state, on| {
+        state.chess960 = on;
+        // the next position command must rebuild the board
+        state.current_pos = None;
+    } } },
+];
+
+/// Runs one search at a time on a background thread, answering each `go` with exactly one `bestmove`
+#[derive(Default)]
+pub struct SearchController {
+    handle: Option<JoinHandle<()>>,
+    /// the stop flag of the running search, fresh for each search
+    stop: Arc<AtomicBool>,
+    /// the pondering flag of the running search, cleared by `ponderhit`
+    pondering: Arc<AtomicBool>,
+}
+
+impl SearchController {
+    /// Searches a copy of the context in the background, first stopping any search still running
+    ///
+    /// an infinite search holds back its `bestmove` until it is stopped, and a pondering search
+    /// until it is stopped or pondering ends, even if they end by themselves
+    pub fn start(&mut self, mut sc: SearchContext, limits: SearchLimits, infinite: bool, ponder: bool, verbose: bool) {
+        self.stop();
+        self.stop = Arc::new(AtomicBool::new(false));
+        self.pondering = Arc::new(AtomicBool::new(ponder));
+        sc.stop_search = self.stop.clone();
+        sc.pondering = self.pondering.clone();
+        let stop = self.stop.clone();
+        let pondering = self.pondering.clone();
+        self.handle = Some(thread::spawn(move || {
+            let mv = sc.search(limits, verbose);
+            while (infinite || pondering.load(Ordering::Relaxed)) && !stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            print_bestmove(mv, sc.ponder_move());
+        }));
+    }
+
+    /// The opponent played the expected move, so the pondering search goes on under its time limits
+    pub fn ponderhit(&self) {
+        self.pondering.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns true while a search is running or waiting to be stopped
+    pub fn is_searching(&self) -> bool {
+        self.handle.as_ref().is_some_and(|handle| !handle.is_finished())
+    }
+
+    /// Stops the running search, returning once it has sent its `bestmove`
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.stop.store(true, Ordering::Relaxed);
+            if handle.join().is_err() {
+                // the search panicked, but the GUI still expects an answer
+                print_bestmove(Move::default(), None);
+            }
+        }
+    }
+}
+
+impl Drop for SearchController {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Sends a `bestmove` with the reply to ponder on, using the null move `0000` when there are no legal moves
+fn print_bestmove(mv: Move, ponder: Option<Move>) {
+    match ponder {
+        _ if mv == Move::default() => println!("bestmove 0000"),
+        Some(ponder) => println!("bestmove {} ponder {}", mv, ponder),
+        None => println!("bestmove {}", mv),
+    }
+}
+
+/// Everything commands and options change between lines from the GUI
+pub struct UciState {
+    pub sc: SearchContext,
+    pub debug: bool,
+    pub chess960: bool,
+    /// the last position set up, so a position extending it only plays the new moves
+    current_pos: Option<(StartPosition, Vec<Move>)>,
+    /// false after a position that failed to set up, so `go` doesn't search a position the GUI didn't ask for
+    position_valid: bool,
+    search: SearchController,
+}
+
+impl UciState {
+    pub fn new() -> Self {
+        Self {
+            sc: SearchContext::new(),
+            debug: false,
+            chess960: false,
+            current_pos: None,
+            position_valid: true,
+            search: SearchController::default(),
+        }
+    }
+
+    /// Returns the transposition table to resize or clear, after waiting for any search to finish
+    ///
+    /// a search thread holds on to the table until it ends, which is only after it has sent `bestmove`
+    fn tt_mut(&mut self) -> Option<&mut TranspositionTable> {
+        self.search.stop();
+        Arc::get_mut(&mut self.sc.tt)
+    }
+
+    /// Sets up the position to search, only playing the new moves if it extends the last one
+    ///
+    /// an invalid FEN or an illegal move leaves the start position and makes `go` reply with a null move
+    /// until a valid position arrives
+    pub fn set_position(&mut self, start: StartPosition, moves: Vec<Move>) -> Result<(), UciError> {
+        let result = self.play_position(start, moves);
+        self.position_valid = result.is_ok();
+        if result.is_err() {
+            self.current_pos = None;
+            self.sc.board = Board::new();
+            if self.chess960 {
+                self.sc.board.enable_chess960();
+            }
+        }
+        result
+    }
+
+    fn play_position(&mut self, start: StartPosition, moves: Vec<Move>) -> Result<(), UciError> {
+        let played = match &self.current_pos {
+            Some((pos, played)) if *pos == start && moves.starts_with(played) => played.len(),
+            _ => {
+                self.current_pos = None;
+                self.sc.board = match &start {
+                    StartPosition::StartPos => Board::new(),
+                    StartPosition::Fen(fen) => Board::from_fen(fen)?,
+                };
+                if self.chess960 {
+                    self.sc.board.enable_chess960();
+                }
+                0
+            },
+        };
+
+        for &mv in &moves[played..] {
+            if !self.sc.board.legal_moves().any(|legal| legal == mv) {
+                return Err(UciError::IllegalMove(mv));
+            }
+            self.sc.board.make_move(mv);
+        }
+        // the search never takes back game moves, so only their keys are kept
+        self.sc.board.forget_moves();
+        self.current_pos = Some((start, moves));
+        Ok(())
+    }
+
+    /// Validates a value against the option's declaration and passes it to the option's setter
+    ///
+    /// names are case insensitive, and a missing value is only allowed for buttons and strings
+    pub fn set_option(&mut self, name: &str, value: Option<String>) -> Result<(), UciError> {
+        let option = OPTIONS.iter().find(|option| option.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| UciError::UnknownOption(name.to_string()))?;
+        let missing = || UciError::MissingValue(option.name.to_string());
+        let invalid = |value: &str| UciError::InvalidValue(option.name.to_string(), value.to_string());
+
+        match &option.kind {
+            OptionType::Spin { min, max, set, .. } => {
+                let value = value.ok_or_else(missing)?;
+                match value.parse::<i64>() {
+                    Ok(n) if (*min..=*max).contains(&n) => set(self, n),
+                    _ => return Err(invalid(&value)),
+                }
+            },
+            OptionType::Check { set, .. } => {
+                let value = value.ok_or_else(missing)?;
+                set(self, value.parse().map_err(|_| invalid(&value))?);
+            },
+            OptionType::Combo { vars, set, .. } => {
+                let value = value.ok_or_else(missing)?;
+                let var = vars.iter().find(|var| var.eq_ignore_ascii_case(&value)).ok_or_else(|| invalid(&value))?;
+                set(self, var);
+            },
+            OptionType::Button { press } => press(self),
+            OptionType::String { set, .. } => set(self, value.as_deref().unwrap_or_default()),
+        }
+        Ok(())
+    }
+}
+
+impl Default for UciState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 pub fn run_uci() {
-    let mut sc = SearchContext::new();
-    let mut debug = false;
-    let mut current_pos = "startpos".to_string();
-    let mut move_count = 0;
-    let mut searching = false;
+    let mut state = UciState::new();
 
+    // the search is stopped when `state` is dropped, including on EOF
     loop {
         let mut buf = String::new();
-        stdin().read_line(&mut buf).unwrap();
-        let tokens = buf.trim().split(" ").collect::<Vec<&str>>();
+        match stdin().read_line(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+        let command = match UciCommand::from_str(&buf) {
+            Ok(command) => command,
+            Err(UciError::Empty) => continue,
+            Err(e) => {
+                println!("info string {}", e);
+                continue;
+            },
+        };
 
-        match tokens[0] {
-            "uci" => {
+        match command {
+            UciCommand::Uci => {
                 println!("id name engine v0.1.1");
                 println!("id author Bryn Deering");
-                println!("option name Hash type spin default 16 min 1 max 1048576"); 
+                for option in OPTIONS.iter() {
+                    println!("{}", option);
+                }
                 println!("uciok");
             }
-            "debug" => {
-                match tokens[1] {
-                    "on" => debug = true,
-                    "off" => debug = false,
-                    _ => (),
+            UciCommand::Debug(on) => state.debug = on,
+            UciCommand::IsReady => {
+                println!("readyok");
+            },
+            UciCommand::SetOption { name, value } => {
+                if let Err(e) = state.set_option(&name, value) {
+                    println!("info string {}", e);
                 }
             },
-            "isready" => {
-                println!("readyok");
+            UciCommand::UciNewGame => {
+                match state.tt_mut() {
+                    Some(tt) => tt.clear(),
+                    None => println!("info string can't clear the hash during a search"),
+                }
             },
-            "setoption" => {
-                match tokens[1] {
-                    "Hash" => {
-                        let hash_size = tokens[2].parse::<usize>().unwrap();
-                        Arc::get_mut(&mut sc.tt).unwrap().resize(hash_size);
+            UciCommand::Position { start, moves } => {
+                let move_count = moves.len();
+                match state.set_position(start, moves) {
+                    Ok(()) if state.debug => {
+                        println!("info string {}", state.sc.board);
+                        println!("info string move_count = {}", move_count);
                     },
-                    _ => (),
+                    Ok(()) => (),
+                    Err(e) => println!("info string {}", e),
                 }
-            
-            },
-            "ucinewgame" => {
-                Arc::get_mut(&mut sc.tt).unwrap().clear();
             },
-            "position" => {
-                searching = false;
-
-                let mut moves_start = 3;
-                if tokens[1] != current_pos {
-                    sc.board = match tokens[1] {
-                        "startpos" => {
-                            Board::new()
-                        },
-                        "fen" => {
-                            moves_start += 6;
-                            current_pos = format!("{} {} {} {} {} {}",
-                                tokens[2], tokens[3], tokens[4], tokens[5], tokens[6], tokens[7]);
-                            Board::from_fen(&current_pos)
-                        },
-                        _ => continue
-                    };
-                    move_count = 0;
+            UciCommand::Go(params) => {
+                // a go during a search replaces it, so both are still answered
+                state.search.stop();
+                if !state.position_valid {
+                    println!("bestmove 0000");
+                    continue;
                 }
 
-                moves_start += move_count;
-                for idx in moves_start..tokens.len() {
-                    let m = Move::from_str(tokens[idx]).unwrap();
-                    sc.board.make_move(m);
-                    move_count += 1;
-                }
-                if debug {
-                    println!("info string {}", sc.board);
-                    println!("info string move_count = {}", move_count);
+                let (time, inc) = match state.sc.board.side_to_move() {
+                    Color::White => (params.wtime, params.winc),
+                    Color::Black => (params.btime, params.binc),
+                };
+                let ms = |ms: i64| ms.clamp(0, u32::MAX as i64) as u32;
+                let limits = SearchLimits {
+                    // a pondering search keeps its clock for after the ponderhit
+                    time: time.map(ms).filter(|_| !params.infinite),
+                    inc: inc.map_or(0, ms),
+                    movestogo: params.movestogo,
+                    move_time: params.movetime.filter(|&move_time| move_time > 0),
+                    depth: params.depth,
+                    nodes: params.nodes,
+                    mate: params.mate,
+                    searchmoves: params.searchmoves,
+                };
+
+                // a bestmove can't be sent while pondering or before `stop` in an infinite search
+                if limits.searchmoves.is_empty() && !params.ponder && !params.infinite {
+                    if let Some(mv) = state.sc.book_move() {
+                        println!("bestmove {}", mv);
+                        continue;
+                    }
                 }
+
+                state.search.start(state.sc.clone(), limits, params.infinite, params.ponder, state.debug);
             },
-            "go" => {
-                if searching { continue }
-
-                let mut ms_remaining: u32 = 0;
-                let mut ms_inc: u32 = 0;
-                let mut move_time: u32 = 0;
-                let mut strict_timing = false;
-
-                let mut idx = 1;
-                while idx < tokens.len() {
-                    match tokens[idx] {
-                        "ponder" => ms_remaining = u32::MAX,
-                        "infinite" => ms_remaining = u32::MAX,
-                        "movetime" => {
-                            idx += 1;
-                            move_time = tokens[idx].parse().unwrap();
-                            strict_timing = true;
-                        },
-                        "wtime" => {
-                            if sc.board.side_to_move() == Color::White {
-                                idx += 1;
-                                ms_remaining = tokens[idx].parse().unwrap();
-                            }
-                        },
-                        "btime" => {
-                            if sc.board.side_to_move() == Color::Black {
-                                idx += 1;
-                                ms_remaining = tokens[idx].parse().unwrap();
-                            }
-                        },
-                        "winc" => {
-                            if sc.board.side_to_move() == Color::White {
-                                idx += 1;
-                                ms_inc = tokens[idx].parse().unwrap();
-                            }
-                        },
-                        "binc" => {
-                            if sc.board.side_to_move() == Color::Black {
-                                idx += 1;
-                                ms_inc = tokens[idx].parse().unwrap();
-                            }
+            UciCommand::Benchmark(Benchmark::Nps { trials: num_trials, move_time }) => { // nodes per second
+                let startpos_path = current_dir().unwrap_or_default().join("tools").join("res").join("lichess_elite_smaller.epd");
+                let startpos_file = match File::open(&startpos_path) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        println!("info string failed to open {}: {}", startpos_path.display(), e);
+                        continue;
+                    },
+                };
+                let mut startpositions = BufReader::new(startpos_file).lines().enumerate();
+                let mut nps_avg: f64 = 0.;
+                let mut nps_max: f64 = f64::MIN;
+                let mut nps_min: f64 = f64::MAX;
+                let mut trials: f64 = 0.0;
+
+                println!("started benchmark");
+                while let Some((n, Ok(line))) = startpositions.next() {
+                    state.sc.board = match Epd::from_str(&line) {
+                        Ok(epd) => epd.board,
+                        Err(e) => {
+                            println!("skipping line {}: {}", n, e);
+                            continue;
                         },
-                        _ => ()
-                    }
-                    idx += 1;
-                }
+                    };
+                    let time: Instant = Instant::now();
 
-                if move_time == 0 {
-                    move_time = ms_remaining / 60 + ms_inc;
-                }
+                    // a timed out search leaves the flag set
+                    state.sc.stop_search.store(false, Ordering::Relaxed);
+                    state.sc.search(SearchLimits::move_time(move_time), false);
+
+                    let end = time.elapsed().as_millis();
+                    let nps = state.sc.debug.nodes as f64 / (end as f64 / 1000.0);
+
+                    nps_max = nps_max.max(nps);
+                    nps_min = nps_min.min(nps);
+                    nps_avg = ((nps_avg * trials) + nps) / (trials + 1.0);
+                    trials += 1.0;
+                    println!("processed {} nodes in {}ms, ({:.0} nps) [{}/{}]",
+                        state.sc.debug.nodes,
+                        end,
+                        nps,
+                        n,
+                        num_trials
+                    );
 
-                let mut sc = sc.clone();
-                thread::spawn(move || {
-                    println!("bestmove {}", sc.search(move_time, strict_timing, debug));
-                });
-                searching = true;
-            },
-            "benchmark" => {
-                match tokens[1] {
-                    "nps" => { // nodes per second
-                        if tokens.len() <= 2 {
-                            println!("Expected: benchmark nps <num_trials> <ms/move>");
-                            break;
-                        }
-
-                        let startpos_file: File = File::open(current_dir().unwrap().join("tools").join("res").join("lichess_elite_smaller.epd")).unwrap();
-                        let mut startpositions = BufReader::new(startpos_file).lines().enumerate();
-                        let num_trials: usize = tokens[2].parse::<usize>().expect("Expected: benchmark nps <num_trials> <ms/move>");
-                        let move_time: u32 = tokens[3].parse::<u32>().expect("Expected: benchmark nps <num_trials> <ms/move>");
-                        let mut nps_avg: f64 = 0.;
-                        let mut nps_max: f64 = f64::MIN;
-                        let mut nps_min: f64 = f64::MAX;
-                        let mut trials: f64 = 0.0;
-
-                        println!("started benchmark");
-                        while let Some((n, Ok(line))) = startpositions.next() {
-                            sc.board = Board::from_fen(&line);
-                            let time: Instant = Instant::now();
-
-                            sc.search(move_time, true, false);
-
-                            let end = time.elapsed().as_millis();
-                            let nps = sc.debug.nodes as f64 / (end as f64 / 1000.0);
-                            
-                            nps_max = nps_max.max(nps);
-                            nps_min = nps_min.min(nps);
-                            nps_avg = ((nps_avg * trials) + nps) / (trials + 1.0);
-                            trials += 1.0;
-                            println!("processed {} nodes in {}ms, ({:.0} nps) [{}/{}]",
-                                sc.debug.nodes,
-                                end,
-                                nps,
-                                n,
-                                num_trials
-                            );
-                            
-                            if n % 20 == 0 {
-                                println!("-----------");
-                                println!("Current avg nps: {:.0} nps max: {:.0} nps min: {:.0} nps [{}/{}]",
-                                    nps_avg,
-                                    nps_max,
-                                    nps_min,
-                                    trials,
-                                    num_trials
-                                );
-                                println!("-----------");
-                            }
-                            if n >= num_trials - 1 { break; }
-                        }
+                    if n % 20 == 0 {
                         println!("-----------");
-                        println!("Results [{:.0} trials]\nAvg nps: {:.0} nps\nAvg ms/node {:.3}ms\nMax: {:.0} nps\nMin: {:.0} nps",
-                            trials,
+                        println!("Current avg nps: {:.0} nps max: {:.0} nps min: {:.0} nps [{}/{}]",
                             nps_avg,
-                            (1.0 / nps_avg) * 1000.0,
                             nps_max,
-                            nps_min
+                            nps_min,
+                            trials,
+                            num_trials
                         );
                         println!("-----------");
-                    },
-                    _ => (),
+                    }
+                    if n + 1 >= num_trials { break; }
                 }
+                println!("-----------");
+                println!("Results [{:.0} trials]\nAvg nps: {:.0} nps\nAvg ms/node {:.3}ms\nMax: {:.0} nps\nMin: {:.0} nps",
+                    trials,
+                    nps_avg,
+                    (1.0 / nps_avg) * 1000.0,
+                    nps_max,
+                    nps_min
+                );
+                println!("-----------");
+                // the benchmark replaced the board
+                state.current_pos = None;
             },
-            "ponderhit" => (),
-            "stop" => {
-                sc.stop_search.store(true, Ordering::Relaxed);
-                searching = false;
+            UciCommand::Benchmark(Benchmark::Perft { depth }) => {
+                let time: Instant = Instant::now();
+                let nodes = perft(&mut state.sc.board, depth);
+                let end = time.elapsed().as_millis().max(1);
+                println!("processed {} nodes in {}ms, ({:.0} nps)", nodes, end, nodes as f64 / (end as f64 / 1000.0));
             },
-            "quit" => break,
-            _ => ()
+            UciCommand::PonderHit => state.search.ponderhit(),
+            UciCommand::Stop => state.search.stop(),
+            UciCommand::Quit => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess::Square;
+    use crate::tablebase::{Material, Table};
+
+    fn parse(s: &str) -> Result<UciCommand, UciError> {
+        UciCommand::from_str(s)
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse("uci\n"), Ok(UciCommand::Uci));
+        assert_eq!(parse("  isready  "), Ok(UciCommand::IsReady));
+        assert_eq!(parse("debug off"), Ok(UciCommand::Debug(false)));
+        // unknown tokens before a command are skipped
+        assert_eq!(parse("joho debug on"), Ok(UciCommand::Debug(true)));
+
+        assert_eq!(
+            parse("setoption name Hash value 64"),
+            Ok(UciCommand::SetOption { name: "Hash".to_string(), value: Some("64".to_string()) }),
+        );
+        assert_eq!(
+            parse("setoption  name Move Overhead   value 100"),
+            Ok(UciCommand::SetOption { name: "Move Overhead".to_string(), value: Some("100".to_string()) }),
+        );
+        assert_eq!(
+            parse("setoption name Clear Hash"),
+            Ok(UciCommand::SetOption { name: "Clear Hash".to_string(), value: None }),
+        );
+        assert_eq!(
+            parse("setoption name SyzygyPath value /tb/wdl 345"),
+            Ok(UciCommand::SetOption { name: "SyzygyPath".to_string(), value: Some("/tb/wdl 345".to_string()) }),
+        );
+
+        assert_eq!(
+            parse("position startpos moves e2e4 e7e5"),
+            Ok(UciCommand::Position {
+                start: StartPosition::StartPos,
+                moves: vec![Move::new(Square::E2, Square::E4, None), Move::new(Square::E7, Square::E5, None)],
+            }),
+        );
+        assert_eq!(
+            parse("position fen 8/8/8/8/8/8/8/K6k w - - 0 1"),
+            Ok(UciCommand::Position { start: StartPosition::Fen("8/8/8/8/8/8/8/K6k w - - 0 1".to_string()), moves: Vec::new() }),
+        );
+
+        let go = parse("go wtime -1500 btime 3000 winc 0 binc 0 movestogo 12 foo depth 9 searchmoves e2e4 d2d4 infinite");
+        assert_eq!(go, Ok(UciCommand::Go(GoParams {
+            wtime: Some(-1500),
+            btime: Some(3000),
+            winc: Some(0),
+            binc: Some(0),
+            movestogo: Some(12),
+            depth: Some(9),
+            searchmoves: vec![Move::new(Square::E2, Square::E4, None), Move::new(Square::D2, Square::D4, None)],
+            infinite: true,
+            ..GoParams::default()
+        })));
+        assert_eq!(parse("go"), Ok(UciCommand::Go(GoParams::default())));
+        assert_eq!(parse("benchmark perft 5"), Ok(UciCommand::Benchmark(Benchmark::Perft { depth: 5 })));
+    }
+
+    #[test]
+    fn test_malformed_commands() {
+        let missing = |s: &str| Err(UciError::MissingValue(s.to_string()));
+        let invalid = |s: &str, v: &str| Err(UciError::InvalidValue(s.to_string(), v.to_string()));
+        let cases = [
+            ("", Err(UciError::Empty)),
+            (" \t \n", Err(UciError::Empty)),
+            ("foo bar", Err(UciError::UnknownCommand("foo".to_string()))),
+            ("debug", missing("debug")),
+            ("debug maybe", invalid("debug", "maybe")),
+            ("setoption", missing("setoption")),
+            ("setoption Hash 64", missing("setoption")),
+            ("setoption name", missing("name")),
+            ("setoption name value 5", missing("name")),
+            ("position", missing("position")),
+            ("position fen", missing("fen")),
+            ("position fen moves e2e4", missing("fen")),
+            ("position somewhere", invalid("position", "somewhere")),
+            ("position startpos moves e2e9", Err(UciError::InvalidMove("e2e9".to_string()))),
+            ("go wtime", missing("wtime")),
+            ("go wtime btime 100", invalid("wtime", "btime")),
+            ("go movetime 1.5", invalid("movetime", "1.5")),
+            ("go depth -3", invalid("depth", "-3")),
+            ("go nodes lots", invalid("nodes", "lots")),
+            ("go searchmoves e2e4 xx", Err(UciError::InvalidMove("xx".to_string()))),
+            ("benchmark", missing("benchmark")),
+            ("benchmark nps 10", missing("nps")),
+            ("benchmark nps 10 fast", invalid("nps", "fast")),
+            ("benchmark perft 0", invalid("perft", "0")),
+            ("benchmark speed", invalid("benchmark", "speed")),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_search_controller() {
+        let mut controller = SearchController::default();
+        let sc = SearchContext::new();
+
+        // an infinite search only ends when stopped
+        controller.start(sc.clone(), SearchLimits::default(), true, false, false);
+        thread::sleep(Duration::from_millis(50));
+        assert!(controller.is_searching());
+        controller.stop();
+        assert!(!controller.is_searching());
+
+        // a stop sent before the search thread starts isn't lost
+        controller.start(sc.clone(), SearchLimits::default(), true, false, false);
+        controller.stop();
+
+        // a timed search ends by itself, after which a new one can start
+        controller.start(sc.clone(), SearchLimits::move_time(20), false, false, false);
+        let timer = Instant::now();
+        while controller.is_searching() {
+            assert!(timer.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+        controller.start(sc.clone(), SearchLimits::move_time(20), false, false, false);
+        controller.stop();
+
+        // a pondering search waits for the ponderhit, then stops under its time limit
+        controller.start(sc, SearchLimits::move_time(20), false, true, false);
+        thread::sleep(Duration::from_millis(100));
+        assert!(controller.is_searching());
+        controller.ponderhit();
+        let timer = Instant::now();
+        while controller.is_searching() {
+            assert!(timer.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
         }
     }
+
+    #[test]
+    fn test_hash_after_search() {
+        let mut state = UciState::new();
+        // the search thread keeps its reference to the table for a moment after `bestmove`
+        state.search.start(state.sc.clone(), SearchLimits::depth(1), false, false, false);
+        assert!(state.tt_mut().is_some());
+        // a running search is stopped first
+        state.search.start(state.sc.clone(), SearchLimits::default(), true, false, false);
+        assert_eq!(state.set_option("Clear Hash", None), Ok(()));
+        assert!(!state.search.is_searching());
+    }
+
+    #[test]
+    fn test_option_declarations() {
+        let declared = OPTIONS.iter().map(|option| option.to_string()).collect::<Vec<String>>();
+        assert!(declared.contains(&"option name Hash type spin default 16 min 1 max 1048576".to_string()));
+        assert!(declared.contains(&"option name Clear Hash type button".to_string()));
+        assert!(declared.contains(&"option name Ponder type check default false".to_string()));
+        assert!(declared.contains(&"option name RepetitionRule type combo default Twofold var Twofold var Threefold".to_string()));
+        assert!(declared.contains(&"option name BookFile type string default <empty>".to_string()));
+        // every option can be found by its name
+        for option in OPTIONS.iter() {
+            assert_eq!(OPTIONS.iter().filter(|other| other.name.eq_ignore_ascii_case(option.name)).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_set_position() {
+        let mut state = UciState::new();
+        let e4 = Move::new(Square::E2, Square::E4, None);
+        let e5 = Move::new(Square::E7, Square::E5, None);
+        let start = Board::new().to_fen();
+
+        assert_eq!(state.set_position(StartPosition::StartPos, vec![e4]), Ok(()));
+        assert_eq!(state.set_position(StartPosition::StartPos, vec![e4, e5]), Ok(()));
+        assert_eq!(state.sc.board.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2");
+        assert!(state.position_valid);
+
+        // a failed position doesn't leave the last one behind to be searched
+        let fen = StartPosition::Fen("8/8/8/8/8/8/8/8 w - - 0 1".to_string());
+        assert!(matches!(state.set_position(fen, vec![]), Err(UciError::InvalidFen(_))));
+        assert_eq!(state.sc.board.to_fen(), start);
+        assert!(!state.position_valid);
+        assert_eq!(state.set_position(StartPosition::StartPos, vec![e4]), Ok(()));
+        assert!(state.position_valid);
+        assert_eq!(state.set_position(StartPosition::StartPos, vec![e4, e4]), Err(UciError::IllegalMove(e4)));
+        assert_eq!(state.sc.board.to_fen(), start);
+        assert!(!state.position_valid);
+        assert_eq!(state.set_position(StartPosition::StartPos, vec![]), Ok(()));
+        assert!(state.position_valid);
+    }
+
+    #[test]
+    fn test_set_option() {
+        let mut state = UciState::new();
+        let value = |s: &str| Some(s.to_string());
+
+        assert_eq!(state.set_option("Move Overhead", value("250")), Ok(()));
+        assert_eq!(state.sc.move_overhead, 250);
+        assert_eq!(state.set_option("multipv", value("3")), Ok(()));
+        assert_eq!(state.sc.multi_pv, 3);
+        assert_eq!(state.set_option("Ponder", value("true")), Ok(()));
+        assert_eq!(state.set_option("RepetitionRule", value("threefold")), Ok(()));
+        assert_eq!(state.sc.repetition_rule, RepetitionRule::Threefold);
+        assert_eq!(state.set_option("Clear Hash", None), Ok(()));
+        assert_eq!(state.set_option("BookFile", value("<empty>")), Ok(()));
+        let mut tablebases = Tablebases::default();
+        tablebases.insert(Table::generate(Material::from_str("KRvK").unwrap(), &tablebases).unwrap());
+        state.sc.tablebases = Arc::new(tablebases);
+        assert_eq!(state.set_option("TablebasePath", value("<empty>")), Ok(()));
+        assert!(state.sc.tablebases.is_empty());
+
+        let invalid = |name: &str, value: &str| Err(UciError::InvalidValue(name.to_string(), value.to_string()));
+        assert_eq!(state.set_option("Hash", None), Err(UciError::MissingValue("Hash".to_string())));
+        assert_eq!(state.set_option("Hash", value("0")), invalid("Hash", "0"));
+        assert_eq!(state.set_option("Hash", value("lots")), invalid("Hash", "lots"));
+        assert_eq!(state.set_option("Threads", value("4")), invalid("Threads", "4"));
+        assert_eq!(state.set_option("OwnBook", value("yes")), invalid("OwnBook", "yes"));
+        assert_eq!(state.set_option("RepetitionRule", value("Fivefold")), invalid("RepetitionRule", "Fivefold"));
+        assert_eq!(state.set_option("Foo", value("1")), Err(UciError::UnknownOption("Foo".to_string())));
+        // rejected values leave the option unchanged
+        assert_eq!(state.sc.move_overhead, 250);
+    }
 }
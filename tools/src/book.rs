@@ -0,0 +1,49 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use engine::{pgn::PgnReader, polyglot::BookBuilder};
+
+const DEFAULT_MAX_PLIES: usize = 24;
+
+pub fn run(args: &[String]) {
+   if args.len() < 2 {
+      println!("Usage: <program> book <output.bin> <pgn file>... [--plies <n>]\n");
+      println!("Builds a polyglot book from finished games, weighting moves by 2 per win and 1 per draw");
+      return;
+   }
+
+   let mut max_plies = DEFAULT_MAX_PLIES;
+   let mut inputs = Vec::new();
+   let mut idx = 1;
+   while idx < args.len() {
+      if args[idx] == "--plies" {
+         idx += 1;
+         max_plies = args.get(idx).and_then(|n| n.parse().ok()).expect("Expected: --plies <n>");
+      } else {
+         inputs.push(&args[idx]);
+      }
+      idx += 1;
+   }
+
+   let mut builder = BookBuilder::default();
+   for input in inputs {
+      let file = File::open(input).expect("Failed to open pgn file");
+      let (mut games, mut errors) = (0, 0);
+      for game in PgnReader::new(BufReader::new(file)) {
+         match game {
+            Ok(game) => {
+               builder.add_game(&game, max_plies);
+               games += 1;
+            },
+            Err(e) => {
+               println!("{}: {}", input, e);
+               errors += 1;
+            },
+         }
+      }
+      println!("read {} games from {} ({} skipped)", games, input, errors);
+   }
+
+   let book = builder.build();
+   book.write(Path::new(&args[0])).expect("Failed to write book");
+   println!("wrote {} entries to {}", book.len(), args[0]);
+}
@@ -2,13 +2,13 @@ use std::{process::Command, env::current_dir};
 use chrono::Local;
 
 use dotenvy_macro::dotenv;
-use num_cpus;
+use engine::adjudication::Adjudication;
 
-pub fn run(args: &Vec<String>) {
+pub fn run(args: &[String]) {
    let cutechess = dotenv!("CUTE_CHESS");
 
    if args.len() < 2 {
-      println!("Usage: <program> cutechess <opponent> <rounds>\n");
+      println!("Usage: <program> cutechess <opponent> <rounds> [--adjudicate]\n");
       return;
    }
    println!("running cutechess from {}", cutechess);
@@ -19,15 +19,24 @@ pub fn run(args: &Vec<String>) {
    let engine_cmd = format!("cmd={}", engine_build_path);
    let opponent_cmd = format!("cmd={}", args[0]);
 
-   let rounds = format!("{}",args[1]);
+   let rounds = args[1].to_string();
 
    let openings_path = current_dir.join("tools").join("res").join("openings1.epd").to_str().unwrap().to_string();
    let openings_cmd = format!("file={}", openings_path);
    let output_path = current_dir.join("tools").join("res").join("testresults").to_str().unwrap().to_string();
    let output_cmd = format!("{}/{}.pgn", output_path, Local::now().format("%d-%m-%y %H_%M"));
 
+   // games are only cut short by resign and draw adjudication when asked for
+   let adjudication = Adjudication::default();
+   let adjudicate = args[2..].iter().any(|arg| arg == "--adjudicate");
+   let resign_count = format!("movecount={}", adjudication.resign_moves);
+   let resign_score = format!("score={}", adjudication.resign_score);
+   let draw_number = format!("movenumber={}", adjudication.draw_move_number);
+   let draw_count = format!("movecount={}", adjudication.draw_moves);
+   let draw_score = format!("score={}", adjudication.draw_score);
+
    let max_threads = (num_cpus::get() - 2).to_string();
-   let run_args = vec!(
+   let mut run_args = vec!(
       "-engine",
       &engine_cmd, "name=current-iter",
       "-engine",
@@ -44,6 +53,9 @@ pub fn run(args: &Vec<String>) {
       "-tournament", "gauntlet",
       "-openings", &openings_cmd, "format=epd", "order=random",
    );
+   if adjudicate {
+      run_args.extend(["-resign", &resign_count, &resign_score, "-draw", &draw_number, &draw_count, &draw_score]);
+   }
 
    let mut cutechess_program = Command::new(cutechess).args(run_args).spawn().expect("Failed to run cutechess");
    
@@ -1,6 +1,9 @@
 use std::env::args;
 
+mod book;
 mod cutechess;
+mod tablebase;
+mod wdl;
 
 fn main() {
    let args: Vec<String> = args().collect();
@@ -12,7 +15,10 @@ fn main() {
 
    let cmd = args[1].as_str();
    match cmd {
-      "cutechess" => cutechess::run(&args[2..].to_vec()),
+      "book" => book::run(&args[2..]),
+      "cutechess" => cutechess::run(&args[2..]),
+      "tablebase" => tablebase::run(&args[2..]),
+      "wdl" => wdl::run(&args[2..]),
       _ => println!("Unknown command: {}", cmd),
    }
 }
@@ -0,0 +1,58 @@
+use std::{fs, path::Path, str::FromStr, time::Instant};
+
+use engine::tablebase::{Material, Table, Tablebases, FILE_EXTENSION};
+
+const CROSS_CHECK_SAMPLES: usize = 1000;
+const CROSS_CHECK_DEPTH: u8 = 5;
+
+pub fn run(args: &[String]) {
+   if args.len() < 2 {
+      println!("Usage: <program> tablebase <output dir> <material>... [--verify]\n");
+      println!("Generates tables such as KQvK or KRvKP, along with any tables they depend on");
+      return;
+   }
+
+   let out_dir = Path::new(&args[0]);
+   let verify = args.iter().any(|arg| arg == "--verify");
+   fs::create_dir_all(out_dir).expect("Failed to create output directory");
+   let mut tablebases = Tablebases::load(out_dir).expect("Failed to load existing tables");
+
+   for arg in args[1..].iter().filter(|arg| *arg != "--verify") {
+      match Material::from_str(arg) {
+         Ok(material) if material.allows_en_passant() => {
+            println!("{} has pawns on both sides, which needs en passant and is not supported", material);
+         },
+         Ok(material) => generate(material, &mut tablebases, out_dir, verify),
+         Err(e) => println!("{}", e),
+      }
+   }
+}
+
+/// Generates a table after its dependencies, skipping tables that already exist
+fn generate(material: Material, tablebases: &mut Tablebases, out_dir: &Path, verify: bool) {
+   if tablebases.contains(&material) {
+      return;
+   }
+   for dep in material.dependencies() {
+      generate(dep, tablebases, out_dir, verify);
+   }
+
+   let timer = Instant::now();
+   let table = Table::generate(material.clone(), tablebases).expect("Failed to generate table");
+   println!("generated {} in {:.1}s, longest mate {} plies",
+      material,
+      timer.elapsed().as_secs_f32(),
+      table.longest_mate()
+   );
+
+   if verify {
+      match table.cross_check(CROSS_CHECK_SAMPLES, CROSS_CHECK_DEPTH) {
+         Ok(checked) => println!("cross-checked {} positions against a {} ply search", checked, CROSS_CHECK_DEPTH),
+         Err(e) => println!("cross-check failed for {}: {}", material, e),
+      }
+   }
+
+   let path = out_dir.join(format!("{}.{}", material, FILE_EXTENSION));
+   table.write(&path).expect("Failed to write table");
+   tablebases.insert(table);
+}
@@ -0,0 +1,114 @@
+use std::{fs::File, io::BufReader};
+
+use engine::{eval::{win_rate, Eval, WDL_EVEN_SCORE, WDL_SCALE}, pgn::{GameResult, PgnReader}};
+
+/// scores beyond this say little more than that the game is decided, and mates are written as +-499.99
+const MAX_SCORE: Eval = 1000;
+const MAX_EVEN_SCORE: f64 = 500.0;
+const MAX_SCALE: f64 = 5000.0;
+
+pub fn run(args: &[String]) {
+   if args.is_empty() {
+      println!("Usage: <program> wdl <pgn file>... [--forfeits]\n");
+      println!("Fits the win rate model behind UCI_ShowWDL to the engine scores and results of finished games");
+      println!("games lost on time are left out unless --forfeits is given, as their result doesn't follow from the position");
+      return;
+   }
+
+   let forfeits = args.iter().any(|arg| arg == "--forfeits");
+   // the score of each move from the mover's side, with 1 for a win, 0 for a draw and -1 for a loss
+   let mut samples: Vec<(Eval, i8)> = Vec::new();
+   for input in args.iter().filter(|arg| *arg != "--forfeits") {
+      let file = File::open(input).expect("Failed to open pgn file");
+      let (mut games, mut skipped) = (0, 0);
+      for game in PgnReader::new(BufReader::new(file)) {
+         let game = match game {
+            Ok(game) if game.result != GameResult::Ongoing
+               && (forfeits || game.tag("Termination") != Some("time forfeit")) => game,
+            Ok(_) => {
+               skipped += 1;
+               continue;
+            },
+            Err(e) => {
+               println!("{}: {}", input, e);
+               skipped += 1;
+               continue;
+            },
+         };
+         for node in game.mainline() {
+            let Some((score, _)) = node.engine_score() else { continue };
+            if score.abs() > MAX_SCORE {
+               continue;
+            }
+            let white_moved = node.board.side_to_move() == chess::Color::Black;
+            let result = match game.result {
+               GameResult::WhiteWin => if white_moved { 1 } else { -1 },
+               GameResult::BlackWin => if white_moved { -1 } else { 1 },
+               _ => 0,
+            };
+            samples.push((score, result));
+         }
+         games += 1;
+      }
+      println!("read {} games from {} ({} skipped)", games, input, skipped);
+   }
+   if samples.is_empty() {
+      println!("no scored moves to fit");
+      return;
+   }
+
+   let (even_score, scale) = fit(&samples);
+   println!("fitted {} scores: WDL_EVEN_SCORE = {:.0}, WDL_SCALE = {:.0} (currently {:.0} and {:.0})",
+      samples.len(), even_score, scale, WDL_EVEN_SCORE, WDL_SCALE
+   );
+   if even_score >= MAX_EVEN_SCORE || scale >= MAX_SCALE {
+      println!("the fit ran into its bounds, so these scores barely predict the results");
+   }
+}
+
+/// Average negative log likelihood of the results under the model
+fn cost(samples: &[(Eval, i8)], even_score: f64, scale: f64) -> f64 {
+   samples.iter().map(|&(score, result)| {
+      let win = win_rate(score as f64, even_score, scale);
+      let loss = win_rate(-score as f64, even_score, scale);
+      let p = match result {
+         1 => win,
+         -1 => loss,
+         _ => 1.0 - win - loss,
+      };
+      -p.max(1e-12).ln()
+   }).sum::<f64>() / samples.len() as f64
+}
+
+/// Finds the most likely parameters, on a coarse grid first as the likelihood can be very flat, and then
+/// with a pattern search that halves its steps when no neighbour is better
+fn fit(samples: &[(Eval, i8)]) -> (f64, f64) {
+   let mut best = (f64::MAX, 0.0, 0.0);
+   for i in 0..=20 {
+      for j in 1..=20 {
+         let (even_score, scale) = (MAX_EVEN_SCORE * i as f64 / 20.0, MAX_SCALE * j as f64 / 20.0);
+         let c = cost(samples, even_score, scale);
+         if c < best.0 {
+            best = (c, even_score, scale);
+         }
+      }
+   }
+
+   let (mut best, mut even_score, mut scale) = best;
+   let mut step = 1.0 / 40.0;
+   while step > 1e-4 {
+      let mut improved = false;
+      for (de, ds) in [(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)] {
+         let e = (even_score + de * step * MAX_EVEN_SCORE).clamp(0.0, MAX_EVEN_SCORE);
+         let s = (scale + ds * step * MAX_SCALE).clamp(1.0, MAX_SCALE);
+         let c = cost(samples, e, s);
+         if c < best {
+            (even_score, scale, best, improved) = (e, s, c, true);
+         }
+      }
+      if !improved {
+         step /= 2.0;
+      }
+   }
+   (even_score, scale)
+}